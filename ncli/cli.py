@@ -3,26 +3,48 @@
 This module provides the main CLI for managing notes using Audible, Kindle, and Notion services.
 """
 
+import json
 import os
+import sys
+from datetime import datetime
 from pathlib import Path
-from typing import Any, Dict, Optional, Union, get_type_hints
+from typing import Any, Dict, Optional, Union, get_origin, get_type_hints
 
 import click
 import toml
 from click import echo, prompt
-from pydantic import BaseModel  # pylint: disable=no-name-in-module
+from pydantic import BaseModel, ValidationError  # pylint: disable=no-name-in-module
 
 from ncli import constants, \
     kit_amazon as amazon, \
     kit_audible as audible, \
+    kit_dashboard as dashboard, \
+    kit_import as importer, \
     kit_kindle as kindle, \
     kit_notion as notion, \
+    kit_quote as quote, \
+    kit_validate as validate, \
     kit_youtube as youtube, \
     utils
-from ncli.kit_amazon import Config as AmazonConfig
+from ncli.errors import ConfigError
+from ncli.kit_amazon import Config as AmazonConfig, FrontmatterConfig
+from ncli.kit_audible import Config as AudibleConfig
+from ncli.kit_kindle import Config as KindleConfig
 from ncli.kit_youtube import Config as YoutubeConfig
 
 
+class RetentionConfig(BaseModel):
+    """
+    Config for removing old files automatically at the start of each export, which is useful for scheduled runs.
+    """
+    # Whether to remove leftover temporary files (e.g., from interrupted exports), similar to `ncli clean`.
+    remove_leftovers: bool = False
+
+    # Number of previous versions (`*.old.N`) to keep for each downloaded file (e.g., Audible PDFs).
+    # A negative value keeps all of them.
+    keep_old_downloads: int = -1
+
+
 class Config(BaseModel):
     """
     General config for the CLI
@@ -30,9 +52,24 @@ class Config(BaseModel):
     audible_export_dir: str = ""
     kindle_export_dir: str = ""
     notion_export_dir: str = ""
+    dashboard_dir: str = ""
+
+    # IANA name of the timezone for the dates in the exported data (e.g., "Asia/Singapore"). Empty for the local one.
+    timezone: str = ""
+
+    # strftime format for the dates (without time) in the exported notes, e.g., "%d/%m/%Y". Empty for "%Y-%m-%d".
+    date_format: str = ""
+
+    retention: RetentionConfig = RetentionConfig()
+
+    frontmatter: FrontmatterConfig = FrontmatterConfig()
 
     amazon: AmazonConfig = AmazonConfig()
 
+    audible: AudibleConfig = AudibleConfig()
+
+    kindle: KindleConfig = KindleConfig()
+
     youtube: YoutubeConfig = YoutubeConfig()
 
 
@@ -41,19 +78,112 @@ CONFIG_PATH = constants.BASE_PATH.joinpath('config.toml')
 
 
 @click.group()
+@click.option('--color', type=click.Choice(['auto', 'always', 'never']), default='auto', show_default=True,
+              help='Whether to use colors in the output. With "auto", colors are used if the output is a terminal '
+              'and the NO_COLOR env var is not set.')
+@click.option('--no-color', is_flag=True, help='Same as "--color never".')
+@click.option('--timezone', help='IANA timezone (e.g., "Asia/Singapore") for the dates in the exported data, instead '
+              'of the local one. Overrides the timezone config.')
+@click.option('--date-format', help='strftime format (e.g., "%d/%m/%Y") for the dates in the exported notes. '
+              'Overrides the date_format config.')
+@click.option('--verify-free-space', is_flag=True, help='Estimate the space needed by an export, and fail early if '
+              'there is not enough free space (instead of running out of space midway).')
 @click.pass_context
-def cli(ctx: click.Context) -> None:
+def cli(
+    ctx: click.Context,
+    color: str,
+    no_color: bool,
+    timezone: Optional[str],
+    date_format: Optional[str],
+    verify_free_space: bool,
+) -> None:
     """Note-taking CLI."""
     # ensure that ctx.obj exists and is a dict (in case `cli()` is called
     # by means other than the `if` block below)
     ctx.ensure_object(dict)
 
+    # Note that the color setting is inherited by the context of the subcommands. If the value is None, click will
+    # automatically strip the styles if the output is not a terminal.
+    if no_color or color == 'never' or (color == 'auto' and os.environ.get('NO_COLOR')):
+        ctx.color = False
+    elif color == 'always':
+        ctx.color = True
+
     # Load config file if exists
     config_dict = {}
     config_path = CONFIG_PATH
     if config_path.exists() and config_path.is_file():
         config_dict = toml.load(config_path)
-    ctx.obj['config'] = Config.parse_obj(config_dict)
+    ctx.obj['config'] = _parse_config(config_dict)
+
+    # Note that the dates are configured globally, since they are written by most of the kits.
+    config: Config = ctx.obj['config']
+    utils.configure_dates(timezone or config.timezone, date_format or config.date_format)
+    # The same goes for the durations, which are currently only written by the Audible kit (and the quotes from it).
+    utils.configure_durations(config.audible.duration_precision)
+
+    ctx.obj['verify_free_space'] = verify_free_space
+
+
+# ---
+# Clean
+# ---
+
+
+@cli.command(name='clean')
+@click.option('--dry-run', is_flag=True, help='Lists the files that would be removed without removing them.')
+@click.pass_context
+def clean(ctx: click.Context, dry_run: bool) -> None:
+    """
+    Command to remove leftover temporary files (e.g., from interrupted exports).
+
+    Note that this should not be run while another export is in progress.
+    """
+    config: Config = ctx.obj['config']
+
+    paths = _find_leftovers(config)
+    if not paths:
+        echo('Nothing to clean.')
+        return
+
+    utils.remove_paths(paths, dry_run)
+
+
+def _find_leftovers(config: Config) -> list[Path]:
+    paths = notion.find_leftover_exports()
+    if config.audible_export_dir:
+        paths.extend(amazon.find_leftover_downloads(
+            Path(config.audible_export_dir).expanduser()))
+    return paths
+
+
+def _apply_retention(config: Config, download_dir: Optional[Path] = None) -> None:
+    """
+    Removes old files according to the retention config. This is expected to be called at the start of each export.
+    """
+    paths = []
+    if config.retention.remove_leftovers:
+        paths.extend(_find_leftovers(config))
+    if download_dir is not None and config.retention.keep_old_downloads >= 0:
+        paths.extend(amazon.find_old_downloads(download_dir, config.retention.keep_old_downloads))
+
+    # The same file may be found more than once (e.g., if the download dir is the audible export dir).
+    utils.remove_paths(sorted(set(paths)))
+
+
+def _ensure_amazon_auth(ctx: click.Context) -> None:
+    """
+    Offers to set up the Amazon auth if it is missing, instead of failing when loading it. Non-interactive runs (e.g.,
+    scheduled exports) still fail right away.
+    """
+    config: Config = ctx.obj['config']
+    if config.amazon.auth_file:
+        return
+
+    if not sys.stdin.isatty() or not utils.prompt_user('Amazon auth is not set up yet. Do you want to set it up now?'):
+        raise ConfigError('Amazon auth is not set up, please run `ncli config amazon-auth` first')
+    # Note that the command updates the config in place, which is then used by the caller.
+    ctx.invoke(config_amazon_auth)
 
 
 # ---
@@ -75,10 +205,9 @@ def config_list(ctx: click.Context) -> None:
     """
     config: Config = ctx.obj['config']
 
-    echo('Config:')
+    utils.echo_heading('Config:')
     kv_pairs = _config_to_kv(config)
-    for pair in kv_pairs:
-        echo(f'{pair[0]} = {pair[1]}')
+    utils.echo_table(['Key', 'Value'], [list(pair) for pair in kv_pairs])
 
 
 def _config_to_kv(config: BaseModel, prefix: str = '') -> list:
@@ -110,9 +239,25 @@ def config_set(
     """
     config: Config = ctx.obj['config']
     _update_config(config, key, value)
+    # Note that the validators (e.g., for the allowed values) are not run on assignment.
+    _parse_config(config.dict())
     _save_config(config)
 
 
+def _parse_config(config_dict: Dict[str, Any]) -> Config:
+    try:
+        return Config.parse_obj(config_dict)
+    except ValidationError as e:
+        # The errors raised by the validators (e.g., for unknown values) are wrapped by pydantic, so they are reported
+        # with the path of the field instead.
+        messages = []
+        for error in e.errors():
+            cause = error.get('ctx', {}).get('error')
+            message = cause.args[0] if isinstance(cause, ConfigError) else error['msg']
+            messages.append(f"{'.'.join(str(part) for part in error['loc'])}: {message}")
+        raise ConfigError(f'invalid config. {"; ".join(messages)}') from e
+
+
 def _update_config(config: BaseModel, key: str, value: Union[str, int]) -> None:
     keys = key.split('.', 1)
 
@@ -124,16 +269,24 @@ def _update_config(config: BaseModel, key: str, value: Union[str, int]) -> None:
         declared_type = get_type_hints(config.__class__)[keys[0]]
 
         # Convert string to declared type if necessary
-        if declared_type is int or declared_type is float:
+        if get_origin(declared_type) is list:
+            # Lists are given as comma-separated values.
+            value = [item.strip() for item in value.split(',') if item.strip()]
+            declared_type = list
+        elif declared_type is bool:
+            if value.lower() not in ('true', 'false'):
+                raise ConfigError(f'Expected true or false for field {keys[0]}')
+            value = value.lower() == 'true'
+        elif declared_type is int or declared_type is float:
             try:
                 value = declared_type(value)
             except ValueError as e:
-                raise TypeError(
+                raise ConfigError(
                     f'Cannot convert value to {declared_type}') from e
 
         # Check if the value has correct type
         if not isinstance(value, declared_type):
-            raise TypeError(
+            raise ConfigError(
                 f'Expected type {declared_type} for field {keys[0]}, got {type(value)}')
 
         setattr(config, keys[0], value)
@@ -160,12 +313,17 @@ def _save_config(config: Config):
 
 
 @config_cli.command(name='amazon-auth')
+@click.option('--save-password', is_flag=True, help='Store the password of the encrypted auth file in the OS keyring '
+              '(and enable amazon.use_keyring), so that it is not prompted for on every export.')
 @click.pass_context
-def config_amazon_auth(ctx: click.Context) -> None:
+def config_amazon_auth(ctx: click.Context, save_password: bool) -> None:
     """
     Command to set up amazon auth.
     """
     config: Config = ctx.obj['config']
+    if save_password:
+        # Fail early if the keyring is not available, before going through the login.
+        amazon.import_keyring()
     if config.amazon.auth_file:
         if not utils.prompt_user(
             f'Auth file {config.amazon.auth_file} is found in the config. '
@@ -188,7 +346,7 @@ def config_amazon_auth(ctx: click.Context) -> None:
         # Else will exit the for loop
 
     encryption_pass = None
-    if utils.prompt_user('Do you want to encrypt the auth file?'):
+    if save_password or utils.prompt_user('Do you want to encrypt the auth file?'):
         echo()
         encryption_pass = prompt('Please enter a password for the encryption',
                                  confirmation_prompt=True, hide_input=True)
@@ -222,13 +380,129 @@ def config_amazon_auth(ctx: click.Context) -> None:
         file_password=encryption_pass,
         external_login=external_login,
         with_username=False,
+        totp_secret=config.amazon.totp_secret,
+        login_fallback=config.amazon.login_fallback,
+        totp_command=config.amazon.totp_command,
     )
 
+    # Offer to store the password if the keyring is available, so that it is not prompted for on every export.
+    if encryption_pass and not save_password:
+        try:
+            amazon.import_keyring()
+            save_password = utils.prompt_user('Do you want to store the password in the OS keyring?')
+        except ConfigError:
+            pass
+
+    if save_password:
+        amazon.set_keyring_password(auth_file, encryption_pass)
+        config.amazon.use_keyring = True
+
     config.amazon.auth_file = auth_file
     config.amazon.country_code = country_code
     _save_config(config)
 
 
+# ---
+# Dashboard
+# ---
+
+
+@cli.command(name='dashboard')
+@click.option('--target', type=click.Path(), help='Path to the directory for the dashboard note.')
+@click.pass_context
+def dashboard_generate(
+    ctx: click.Context,
+    target: Optional[str],
+) -> None:
+    """Generates a dashboard note summarizing the Kindle and Audible exports."""
+    config: Config = ctx.obj['config']
+    target = target if target is not None else config.dashboard_dir
+    if not target:
+        raise ConfigError('unknown dashboard target')
+
+    dashboard.generate(Path(target).expanduser(), _book_sources(config))
+
+
+def _book_sources(config: Config) -> list[dashboard.Source]:
+    sources = []
+    if config.kindle_export_dir:
+        sources.append(dashboard.Source('Kindle', Path(config.kindle_export_dir).expanduser()))
+    if config.audible_export_dir:
+        sources.append(dashboard.Source('Audible', Path(config.audible_export_dir).expanduser()))
+    return sources
+
+
+# ---
+# Export
+# ---
+
+# Sources that can be exported together with `ncli export`, i.e., the ones that only need a configured export
+# directory and the Amazon auth.
+EXPORT_SOURCES = ('kindle', 'audible')
+
+
+@cli.command(name='export')
+@click.argument('sources', nargs=-1, type=click.Choice(EXPORT_SOURCES))
+@click.option('--all', 'all_sources', is_flag=True, help='Export all sources that are enabled in the config.')
+@click.pass_context
+def export_sources(
+    ctx: click.Context,
+    sources: tuple[str, ...],
+    all_sources: bool,
+) -> None:
+    """
+    Exports multiple sources (e.g., Kindle and Audible) to their configured export directories.
+
+    Sources that are disabled or not set up in the config are skipped, instead of failing the whole command. Apart
+    from setting up the Amazon auth in a terminal, nothing is prompted for, so that it can be run from a scheduled job:
+    the modified and new books are always fetched. The dashboard is regenerated afterwards if `dashboard_dir` is set.
+    """
+    config: Config = ctx.obj['config']
+    if all_sources:
+        sources = EXPORT_SOURCES
+    elif not sources:
+        raise click.UsageError('specify the sources to export, or use --all')
+
+    # All the sources need the Amazon auth, which is offered to be set up in interactive runs as in the other commands.
+    # Non-interactive runs skip the sources instead (see below).
+    if sys.stdin.isatty():
+        _ensure_amazon_auth(ctx)
+
+    failed = False
+    for source in sources:
+        source_config = config.kindle if source == 'kindle' else config.audible
+        target = config.kindle_export_dir if source == 'kindle' else config.audible_export_dir
+
+        if not source_config.enabled:
+            echo(f'Skipping {source}, which is disabled in the config.')
+            continue
+        if not target:
+            echo(f'Skipping {source}, which has no export directory (see `{source}_export_dir`).')
+            continue
+        if not config.amazon.auth_file:
+            echo(f'Skipping {source}, since Amazon auth is not set up (see `ncli config amazon-auth`).')
+            continue
+
+        utils.echo_heading(f'\nExporting {source} ...')
+        target = Path(target).expanduser()
+        if source == 'kindle':
+            _apply_retention(config)
+            summary = kindle.export(config.amazon, target, False, config.kindle, frontmatter=config.frontmatter,
+                                    verify_free_space=ctx.obj['verify_free_space'], unattended=True)
+        else:
+            _apply_retention(config, download_dir=target)
+            summary = audible.export(config.amazon, target, False, config.audible, frontmatter=config.frontmatter,
+                                     verify_free_space=ctx.obj['verify_free_space'], unattended=True)
+        failed = failed or summary.has_failures()
+
+    if failed:
+        ctx.exit(1)
+
+    # Keep the dashboard (if any) up to date with the exports, since this is usually run from a scheduled job.
+    if config.dashboard_dir:
+        dashboard.generate(Path(config.dashboard_dir).expanduser(), _book_sources(config))
+
+
 # ---
 # Audible
 # ---
@@ -243,19 +517,65 @@ def audible_cli(_: click.Context) -> None:
 @audible_cli.command(name='export')
 @click.option('--target', type=click.Path(), help='Path to the target location for the export.')
 @click.option('--renew', is_flag=True, help='Fetch all books regardless of the index data.')
+@click.option('--format', 'output_format', type=click.Choice(list(amazon.OUTPUT_FORMATS)), default='markdown',
+              show_default=True, help='Output format for the exported books.')
 @click.pass_context
 def audible_export(
     ctx: click.Context,
     target: Optional[str],
     renew: bool,
+    output_format: str,
 ) -> None:
     """Audible export command."""
     config: Config = ctx.obj['config']
     target = target if target is not None else config.audible_export_dir
-    if target is None:
-        raise ValueError('unknown export target')
+    if not target:
+        raise ConfigError('unknown export target')
 
-    audible.export(config.amazon, Path(target).expanduser(), renew)
+    target = Path(target).expanduser()
+    _ensure_amazon_auth(ctx)
+    _apply_retention(config, download_dir=target)
+    summary = audible.export(config.amazon, target, renew, config.audible, output_format, config.frontmatter,
+                             ctx.obj['verify_free_space'])
+    if summary.has_failures():
+        ctx.exit(1)
+
+
+# ---
+# Import
+# ---
+
+
+@cli.group(name='import')
+@click.pass_context
+def import_cli(_: click.Context) -> None:
+    """Import group command."""
+
+
+@import_cli.command(name='generic-markdown')
+@click.argument('source', type=click.Path(exists=True))
+@click.option('--target', type=click.Path(), help='Path to the target location for the import. Defaults to the '
+              'Kindle export directory.')
+@click.option('--layout', type=click.Choice(importer.MARKDOWN_LAYOUTS), default='auto', show_default=True,
+              help='Layout of the source files, which is detected per file by default.')
+@click.option('--format', 'output_format', type=click.Choice(list(amazon.OUTPUT_FORMATS)), default='markdown',
+              show_default=True, help='Output format for the imported books.')
+@click.pass_context
+def import_generic_markdown(
+    ctx: click.Context,
+    source: str,
+    target: Optional[str],
+    layout: str,
+    output_format: str,
+) -> None:
+    """Imports highlights exported by other tools (e.g., Readwise or Bookcision) from a file or directory."""
+    config: Config = ctx.obj['config']
+    target = target if target is not None else config.kindle_export_dir
+    if not target:
+        raise ConfigError('unknown import target')
+
+    importer.import_markdown(Path(source).expanduser(), Path(target).expanduser(), layout, output_format,
+                             config.frontmatter)
 
 
 # ---
@@ -272,19 +592,189 @@ def kindle_cli(_: click.Context) -> None:
 @kindle_cli.command(name='export')
 @click.option('--target', type=click.Path(), help='Path to the target location for the export.')
 @click.option('--renew', is_flag=True, help='Fetch all books regardless of the index data.')
+@click.option('--format', 'output_format', type=click.Choice(list(amazon.OUTPUT_FORMATS)), default='markdown',
+              show_default=True, help='Output format for the exported books.')
+@click.option('--decisions', type=click.Path(dir_okay=False),
+              help='Path to a TOML file with the answers to the index prompts (fetch, skip, or index-only) per '
+              'ASIN. Answers for books that are not in the file are added to it.')
+@click.option('--group-by', type=click.Choice(kindle.GROUP_BY_MODES), default='none', show_default=True,
+              help='Whether to group the annotations of each book into sections, e.g., per highlight color.')
+@click.option('--books-since-index', 'new_only', is_flag=True,
+              help='Only fetch books that are not in the index yet, without any prompts.')
+@click.option('--title', help='Only export books whose title contains this text (case-insensitive).')
+@click.option('--author', help='Only export books whose author contains this text (case-insensitive).')
+@click.option('--asin', 'asins', multiple=True, help='Only export the book with this ASIN, which is always fetched '
+              'without any prompts. Can be repeated.')
+@click.option('--since', type=click.DateTime(formats=['%Y-%m-%d']),
+              help='Only export books that are last opened on or after this date (YYYY-MM-DD).')
+@click.option('--redact-over-limit', is_flag=True,
+              help='Truncate the highlights of books over the kindle.max_highlight_words limit, keeping the notes.')
+@click.option('--merge', is_flag=True, help='Only append annotations with new locations to the existing Markdown '
+              'notes, keeping their content (e.g., manual edits) intact.')
+@click.option('--list-pending', is_flag=True, help='Print the books that would need a decision (i.e., new or '
+              'modified according to the index) as JSON, without prompting or exporting anything.')
+@click.option('--sort-by', type=click.Choice(kindle.SORT_MODES), default='none', show_default=True,
+              help='How to sort the annotations of each book, instead of the order of the Kindle notebook.')
+@click.option('--dedupe', is_flag=True, help='Drop highlights with exactly the same text and location as another '
+              'highlight in the same book (e.g., after editing a highlight on the device).')
+@click.option('--single-file', type=click.Path(dir_okay=False), help='Also combine all exported books into this '
+              'Markdown file (relative to the target), with a table of contents and a section per book.')
+@click.option('--concurrency', type=click.IntRange(min=1), default=1, show_default=True,
+              help='Number of books whose annotations are fetched concurrently. Each one keeps its own pacing.')
+@click.option('--readwise-csv', type=click.Path(dir_okay=False), help='Also write the highlights of all exported books '
+              'into this CSV file (relative to the target), in the layout of the Readwise bulk import.')
+@click.option('--retry-failed', is_flag=True, help='Only fetch the books that failed in the previous run (according '
+              'to its run-summary.json in the target), without any prompts.')
 @click.pass_context
 def kindle_export(
     ctx: click.Context,
     target: Optional[str],
     renew: bool,
+    output_format: str,
+    decisions: Optional[str],
+    group_by: str,
+    new_only: bool,
+    title: Optional[str],
+    author: Optional[str],
+    asins: tuple[str, ...],
+    since: Optional[datetime],
+    redact_over_limit: bool,
+    merge: bool,
+    list_pending: bool,
+    sort_by: str,
+    dedupe: bool,
+    single_file: Optional[str],
+    concurrency: int,
+    readwise_csv: Optional[str],
+    retry_failed: bool,
 ) -> None:
     """Kindle export command."""
     config: Config = ctx.obj['config']
+    if renew and new_only:
+        raise click.UsageError('--renew and --books-since-index cannot be used together')
+    if list_pending and (renew or new_only or asins):
+        raise click.UsageError('--list-pending cannot be used with --renew, --books-since-index, or --asin')
+    if single_file and output_format != 'markdown':
+        raise click.UsageError('--single-file can only be used with the markdown format')
+    if readwise_csv and output_format != 'markdown':
+        raise click.UsageError('--readwise-csv can only be used with the markdown format')
+    if retry_failed and (renew or new_only or list_pending or asins):
+        raise click.UsageError('--retry-failed cannot be used with --renew, --books-since-index, --list-pending, or '
+                               '--asin')
     target = target if target is not None else config.kindle_export_dir
-    if target is None:
-        raise ValueError('unknown export target')
+    if not target:
+        raise ConfigError('unknown export target')
+
+    _ensure_amazon_auth(ctx)
+    if retry_failed:
+        # The failed books are requested by their ASIN, which are fetched without any prompts.
+        asins = tuple(amazon.RunSummary.load(Path(target).expanduser().joinpath(amazon.RUN_SUMMARY_FILE_NAME))
+                      .failed_asins())
+        if not asins:
+            echo('No books failed in the previous run.')
+            return
+
+    book_filter = None
+    if title or author or asins or since:
+        book_filter = kindle.BookFilter(title=title, author=author, asins=list(asins), since=since)
+
+    if list_pending:
+        pending = kindle.list_pending(config.amazon, Path(target).expanduser(), config.kindle, book_filter)
+        echo(json.dumps(pending, indent=2, ensure_ascii=False))
+        return
+
+    _apply_retention(config)
+    target = Path(target).expanduser()
+    summary = kindle.export(config.amazon, target, renew, config.kindle, output_format,
+                            Path(decisions).expanduser() if decisions else None, group_by, config.frontmatter,
+                            new_only, book_filter, redact_over_limit, merge, sort_by, dedupe,
+                            target.joinpath(Path(single_file).expanduser()) if single_file else None,
+                            ctx.obj['verify_free_space'], concurrency,
+                            target.joinpath(Path(readwise_csv).expanduser()) if readwise_csv else None)
+    if summary.has_failures():
+        ctx.exit(1)
+
+
+@kindle_cli.command(name='list')
+@click.option('--format', 'output_format', type=click.Choice(['table', 'json']), default='table', show_default=True,
+              help='Output format for the list of books.')
+@click.pass_context
+def kindle_list(
+    ctx: click.Context,
+    output_format: str,
+) -> None:
+    """Lists the books in the Kindle library, without exporting them."""
+    config: Config = ctx.obj['config']
+    _ensure_amazon_auth(ctx)
+    books = kindle.list_books(config.amazon, config.kindle)
 
-    kindle.export(config.amazon, Path(target).expanduser(), renew)
+    if output_format == 'json':
+        data = [book.dict(include={'title', 'author', 'asin', 'last_opened_date'}) for book in books]
+        echo(json.dumps(data, indent=2, ensure_ascii=False))
+        return
+
+    utils.echo_table(['Title', 'Author', 'ASIN', 'Last opened'],
+                     [[book.title, book.author, book.asin, book.last_opened_date] for book in books])
+
+
+@kindle_cli.command(name='show')
+@click.option('--asin', required=True, help='ASIN of the book to show.')
+@click.option('--group-by', type=click.Choice(kindle.GROUP_BY_MODES), default='none', show_default=True,
+              help='Whether to group the annotations into sections, e.g., per highlight color.')
+@click.pass_context
+def kindle_show(
+    ctx: click.Context,
+    asin: str,
+    group_by: str,
+) -> None:
+    """Prints the annotations of a book as Markdown, without touching the export target or index."""
+    config: Config = ctx.obj['config']
+    _ensure_amazon_auth(ctx)
+    kindle.show(config.amazon, asin, config.kindle, group_by, config.frontmatter)
+
+
+@kindle_cli.command(name='open')
+@click.argument('asin', type=str)
+@click.option('--location', type=click.IntRange(min=0), help='Location to open the book at (only for the app).')
+@click.pass_context
+def kindle_open(
+    ctx: click.Context,
+    asin: str,
+    location: Optional[int],
+) -> None:
+    """Opens a book in the Kindle app or Cloud Reader (see the kindle.open_with config)."""
+    config: Config = ctx.obj['config']
+
+    if location is not None and config.kindle.open_with == 'cloud':
+        utils.echo_warning('The Cloud Reader does not support opening a specific location.')
+
+    url = kindle.get_book_url(asin, location, config.kindle.open_with)
+    echo(f'Opening {url} ...')
+    click.launch(url)
+
+
+@kindle_cli.command(name='import-clippings')
+@click.option('--source', type=click.Path(exists=True, dir_okay=False), required=True,
+              help='Path to the "My Clippings.txt" file of the Kindle device.')
+@click.option('--target', type=click.Path(), help='Path to the target location for the import. Defaults to the '
+              'Kindle export directory.')
+@click.option('--format', 'output_format', type=click.Choice(list(amazon.OUTPUT_FORMATS)), default='markdown',
+              show_default=True, help='Output format for the imported books.')
+@click.pass_context
+def kindle_import_clippings(
+    ctx: click.Context,
+    source: str,
+    target: Optional[str],
+    output_format: str,
+) -> None:
+    """Imports the highlights and notes from a Kindle device, e.g., for books that are not bought from Amazon."""
+    config: Config = ctx.obj['config']
+    target = target if target is not None else config.kindle_export_dir
+    if not target:
+        raise ConfigError('unknown import target')
+
+    importer.import_clippings(Path(source).expanduser(), Path(target).expanduser(), output_format,
+                              config.frontmatter)
 
 
 # ---
@@ -302,26 +792,152 @@ def notion_cli(_: click.Context) -> None:
 @click.option('--source', type=click.Path(), help='Path to the source file.')
 @click.option('--target', type=click.Path(), help='Path to the target location after the conversion.')
 @click.option('--force', is_flag=True, help='Removes the current target directory if it exists.')
+@click.option('--workers', type=click.IntRange(min=1), default=notion.DEFAULT_WORKERS, show_default=True,
+              help='Maximum number of threads used to extract and copy files.')
+@click.option('--aliases', type=click.Choice(notion.ALIAS_MODES), default='none', show_default=True,
+              help='How to handle links to pages that are exported in another location.')
+@click.option('--inline-databases', type=click.Choice(notion.INLINE_DATABASE_MODES), default='link',
+              show_default=True, help='How to render inline databases and linked views in a page.')
+@click.option('--dry-run', is_flag=True, help='Prints the planned target structure without writing the target.')
+@click.option('--flatten-root', type=click.IntRange(min=0), default=0, show_default=True,
+              help='Number of leading directory levels to drop, e.g., the top-level wrapper folders.')
+@click.option('--sqlite', 'sqlite_path', type=click.Path(dir_okay=False),
+              help='Also loads all exported databases into a SQLite file at this path (one table per database).')
+@click.option('--split-pages-over', 'split_pages_over', type=click.IntRange(min=1), metavar='KB',
+              help='Splits pages larger than this size (in KB) into a file per H1/H2 section, with the page as index.')
 @click.pass_context
 def notion_export(
     ctx: click.Context,
     source: str,
     target: Optional[str],
     force: bool,
+    workers: int,
+    aliases: str,
+    inline_databases: str,
+    dry_run: bool,
+    flatten_root: int,
+    sqlite_path: Optional[str],
+    split_pages_over: Optional[int],
 ) -> None:
     """Notion export command."""
     config: Config = ctx.obj['config']
     target = target if target is not None else config.notion_export_dir
-    if target is None:
-        raise ValueError('unknown export target')
+    if not target:
+        raise ConfigError('unknown export target')
 
+    if not dry_run:
+        _apply_retention(config)
     notion.export(
         Path(source).expanduser(),
         Path(target).expanduser(),
         force,
+        workers,
+        aliases,
+        inline_databases,
+        dry_run,
+        flatten_root,
+        Path(sqlite_path).expanduser() if sqlite_path else None,
+        split_pages_over * 1024 if split_pages_over else 0,
+        ctx.obj['verify_free_space'] and not dry_run,
     )
 
 
+@notion_cli.command(name='search')
+@click.argument('query')
+@click.option('--target', type=click.Path(), help='Path to the exported target.')
+@click.option('--limit', type=click.IntRange(min=1), default=20, show_default=True,
+              help='Maximum number of pages to show.')
+@click.option('--rebuild', is_flag=True, help='Rebuilds the search index, even if the target has not changed.')
+@click.option('--format', 'output_format', type=click.Choice(['text', 'json']), default='text', show_default=True,
+              help='Output format of the results.')
+@click.pass_context
+def notion_search(
+    ctx: click.Context,
+    query: str,
+    target: Optional[str],
+    limit: int,
+    rebuild: bool,
+    output_format: str,
+) -> None:
+    """Searches the pages of an exported target, with matches in the titles ranked higher."""
+    config: Config = ctx.obj['config']
+    target = target if target is not None else config.notion_export_dir
+    if not target:
+        raise ConfigError('unknown export target')
+
+    results = notion.search(Path(target).expanduser(), query, limit, rebuild)
+    if output_format == 'json':
+        echo(json.dumps([result.dict() for result in results], indent=2, ensure_ascii=False))
+        return
+
+    if not results:
+        echo('No matching pages are found.')
+    for result in results:
+        utils.echo_heading(result.path)
+        if result.snippet:
+            echo(f'    {result.snippet}')
+
+
+# ---
+# Quote
+# ---
+
+
+@cli.command(name='quote')
+@click.option('--id', 'quote_id', help='ID of the quote, i.e., "<ASIN>-<n>" for the n-th highlight of the book.')
+@click.option('--search', help='Only pick among the highlights (or notes) that contain this text (case-insensitive).')
+@click.option('--format', 'output_format', type=click.Choice(quote.QUOTE_FORMATS), default='plain',
+              show_default=True, help='Output format of the quote.')
+@click.option('--output', type=click.Path(dir_okay=False), help='Path to write the quote to, instead of stdout.')
+@click.pass_context
+def quote_print(
+    ctx: click.Context,
+    quote_id: Optional[str],
+    search: Optional[str],
+    output_format: str,
+    output: Optional[str],
+) -> None:
+    """Prints a random (or specified) highlight from the Kindle and Audible exports, formatted for sharing."""
+    config: Config = ctx.obj['config']
+    if quote_id is not None and search is not None:
+        raise click.UsageError('--id and --search cannot be used together')
+
+    selected = quote.select_quote(quote.load_quotes(_book_sources(config)), quote_id, search)
+    content = quote.format_quote(selected, output_format)
+
+    if output is None:
+        echo(content, nl=False)
+        return
+
+    with open(Path(output).expanduser(), 'w', encoding='utf-8') as f:
+        f.write(content)
+    echo(f"Quote {selected.id} has been written to '{output}'")
+
+
+# ---
+# Validate
+# ---
+
+
+@cli.command(name='validate')
+@click.option('--target', type=click.Path(exists=True, file_okay=False), required=True,
+              help='Path to the directory to be validated.')
+@click.pass_context
+def validate_vault(ctx: click.Context, target: str) -> None:
+    """
+    Checks an exported directory for broken links, malformed frontmatter, duplicate IDs, and invalid names.
+    """
+    issues = validate.validate(Path(target).expanduser())
+    for issue in issues:
+        echo(str(issue))
+
+    if issues:
+        utils.echo_warning(f'Found {len(issues)} issue(s).')
+        ctx.exit(1)
+
+    echo('No issues found.')
+
+
 # ---
 # YouTube
 # ---
@@ -349,8 +965,8 @@ def youtube_export(
     """Export YouTube video data"""
     config: Config = ctx.obj['config']
     target = target if target is not None else config.youtube.export_dir
-    if target is None:
-        raise ValueError('unknown export target')
+    if not target:
+        raise ConfigError('unknown export target')
 
     youtube.export(
         source,
@@ -0,0 +1,82 @@
+"""
+The `errors` module contains the error types shared across the CLI.
+
+Each error class carries a stable code, so that callers can distinguish failure classes programmatically
+instead of matching on error messages.
+"""
+
+
+class NcliError(Exception):
+    """
+    Base class for all errors raised intentionally by ncli.
+
+    Attributes:
+        code (str): A stable identifier for the class of the error.
+    """
+    code: str = 'E000'
+
+    def __str__(self) -> str:
+        return f'[{self.code}] {super().__str__()}'
+
+
+class ConfigError(NcliError, ValueError):
+    """
+    Raised when the config (or the equivalent command-line option) is missing or invalid.
+    """
+    code = 'E100'
+
+
+class AuthError(NcliError):
+    """
+    Raised when we are unable to authenticate with a service (e.g., missing or expired credentials).
+    """
+    code = 'E200'
+
+
+class SignInRequiredError(AuthError):
+    """
+    Raised when a service asks to sign in again (e.g., because the session has expired).
+    """
+    code = 'E201'
+
+
+class CaptchaError(AuthError):
+    """
+    Raised when a service asks to solve a captcha before continuing.
+    """
+    code = 'E202'
+
+
+class PasswordResetError(AuthError):
+    """
+    Raised when a service requires the password to be reset before continuing.
+    """
+    code = 'E203'
+
+
+class AccountLockedError(AuthError):
+    """
+    Raised when the account has been locked (or put on hold) by the service.
+    """
+    code = 'E204'
+
+
+class NetworkError(NcliError):
+    """
+    Raised when a request to a remote service fails.
+    """
+    code = 'E300'
+
+
+class ParseError(NcliError, ValueError):
+    """
+    Raised when the data (e.g., exported files or fetched pages) does not match the expected format.
+    """
+    code = 'E400'
+
+
+class IoError(NcliError):
+    """
+    Raised when reading from or writing to the local file system fails.
+    """
+    code = 'E500'
@@ -6,20 +6,27 @@ A module for processing and managing Amazon data.
 """
 from __future__ import annotations
 
+import csv
 import io
 import getpass
+import json
 import os.path
+import re
+import subprocess
+import unicodedata
+import xml.etree.ElementTree as ET
 
-from typing import List, Optional, Union
-from datetime import datetime
+from typing import Any, Callable, Dict, List, Literal, Optional, TextIO, Tuple, Union
 from pathlib import Path
 
 import click
 import httpx
+import jinja2
 import requests
 import toml
+import yaml
 from PIL import Image
-from pydantic import BaseModel, Field  # pylint: disable=no-name-in-module
+from pydantic import BaseModel, Field, ValidationError  # pylint: disable=no-name-in-module
 from click import echo, secho, prompt
 
 from audible import Authenticator
@@ -27,7 +34,10 @@ from audible.auth import detect_file_encryption
 from audible.login import default_login_url_callback
 
 from ncli import constants
-from ncli.utils import prompt_user, format_duration_from_ms, toml_dumps_with_newline
+from ncli.errors import AuthError, ConfigError, IoError, ParseError
+from ncli.utils import current_timestamp, echo_heading, echo_table, echo_warning, prompt_user, \
+    format_duration_from_ms, format_stored_date, generate_totp, parse_duration_to_ms, same_date, sanitize_file_name, \
+    toml_dumps_with_newline
 
 AVAILABLE_COUNTRY_CODES: List[str] = [
     "us", "ca", "uk", "au", "fr", "de", "es", "jp", "it", "in"]
@@ -42,26 +52,106 @@ class Config(BaseModel):
     auth_file: str = ''
     country_code: str = 'us'
 
+    # Whether to offer continuing with the external browser login (i.e., logging in manually and pasting the resulting
+    # URL) if the login on the terminal fails, e.g., because of an unexpected captcha or verification page.
+    login_fallback: bool = True
+
+    # Base32 secret of the authenticator app for 2FA. If set, the OTP codes are generated automatically during
+    # login instead of being prompted for.
+    totp_secret: str = ''
+
+    # Shell command that prints the current OTP code for 2FA (e.g., from a password manager CLI), as an alternative
+    # to storing the TOTP secret in the config file. Takes precedence over `totp_secret`.
+    totp_command: str = ''
+
+    # Whether to look up the password of the encrypted auth file in the OS keyring (e.g., Keychain, Secret Service,
+    # or Credential Manager) instead of prompting for it, which requires the `keyring` package.
+    use_keyring: bool = False
+
+
+class FrontmatterConfig(BaseModel):
+    """
+    Config for the YAML frontmatter of the exported Markdown notes.
+    """
+    enabled: bool = False
+
+    # Mapping of a book field (e.g., "image_url") to the key used in the frontmatter (e.g., "cover").
+    # Mapping a field to an empty string omits it from the frontmatter.
+    renames: Dict[str, str] = {}
+
+    # Static key/values added to the frontmatter of every note (e.g., "type" -> "book").
+    extra: Dict[str, Any] = {}
+
+
+# Service name of the auth file passwords in the OS keyring, which are keyed by the auth file name.
+KEYRING_SERVICE: str = 'ncli'
+
+
+def import_keyring():
+    """
+    Imports the optional `keyring` package, which is needed for `use_keyring`.
+    """
+    try:
+        import keyring  # pylint: disable=import-outside-toplevel
+    except ImportError as e:
+        raise ConfigError('the keyring package is required for amazon.use_keyring, please install it first '
+                          '(e.g., `pip install keyring`)') from e
+    return keyring
+
+
+def get_keyring_password(auth_file: str) -> Optional[str]:
+    """
+    Returns the password of the given auth file from the OS keyring, if any.
+    """
+    keyring = import_keyring()
+    try:
+        return keyring.get_password(KEYRING_SERVICE, auth_file)
+    except keyring.errors.KeyringError as e:
+        echo_warning(f'Failed to read the auth file password from the keyring. Error: {e}')
+        return None
+
+
+def set_keyring_password(auth_file: str, password: str) -> None:
+    """
+    Stores the password of the given auth file in the OS keyring.
+    """
+    keyring = import_keyring()
+    try:
+        keyring.set_password(KEYRING_SERVICE, auth_file, password)
+    except keyring.errors.KeyringError as e:
+        raise ConfigError(f'Failed to store the auth file password in the keyring. Error: {e}') from e
+
 
 def load_authenticator(config: Config) -> Authenticator:
     """
     Loads authenticator
+
+    The password of an encrypted auth file is looked up in the OS keyring first if `use_keyring` is set, and
+    prompted for otherwise (or if it is not found there).
     """
     if config.auth_file:
         file_path = constants.BASE_PATH.joinpath(config.auth_file)
         pwd = None
+        from_keyring = False
         if detect_file_encryption(file_path):
-            pwd = getpass.getpass('Enter auth file password: ')
+            if config.use_keyring:
+                pwd = get_keyring_password(config.auth_file)
+                from_keyring = pwd is not None
+            if pwd is None:
+                pwd = getpass.getpass('Enter auth file password: ')
 
         try:
             return Authenticator.from_file(file_path, pwd)
         except ValueError as e:
+            if from_keyring:
+                raise AuthError('Failed to decrypt the auth file with the password from the keyring, please re-run '
+                                f'`ncli config amazon-auth --save-password`. Error: {e}') from e
             if pwd:
-                raise ValueError(
+                raise AuthError(
                     f'Failed to decrypt the auth file. Wrong password? Error: {e}') from e
-            raise e
+            raise AuthError(f'Failed to load the auth file. Error: {e}') from e
 
-    raise ValueError('Config without auth file not supported')
+    raise ConfigError('Config without auth file not supported')
 
 
 class Book(BaseModel):
@@ -84,10 +174,25 @@ class Book(BaseModel):
     # Note that the URL may require some cookies to be accessed.
     pdf_url: Optional[str] = None
 
-    # Publication date for the book. Currently only available for Audible.
+    # Publication date for the book. Available for Audible, and for Kindle if the product metadata is fetched.
     publication_date: Optional[str] = None
+    # Publisher and description of the book. Currently only available for Kindle if the product metadata is fetched.
+    #
+    # Note that these are not recorded in the index, since they are only added to the exported copy of the book.
+    publisher: Optional[str] = None
+    description: Optional[str] = None
+    # Path of the downloaded cover image, relative to the export directory (see `download_cover`). Similarly, this is
+    # only added to the exported copy of the book.
+    cover_path: Optional[str] = None
     # Purchase date for the book. Currently only available for Audible.
     purchase_date: Optional[str] = None
+    # Type of the document if it is not a regular book, i.e., "personal" for personal documents (e.g., PDFs sent to
+    # Kindle). Currently only available for Kindle.
+    doc_type: Optional[str] = None
+    # Version of the audio content, i.e., "<ACR>/<version>". Currently only available for Audible.
+    #
+    # This changes if the audio is re-encoded, which may shift the positions of the clips.
+    content_version: Optional[str] = None
 
     # Last opened date represents:
     # - Last time the book is read for Kindle.
@@ -117,13 +222,17 @@ class Annotation(BaseModel):
     # For Kindle
     highlight: Optional[str] = None
     highlight_color: Optional[str] = None
+    # Tag (or any other text, e.g., "#idea") written after the highlight header, which is derived from the highlight
+    # color (see `color_tags` in `kit_kindle`).
+    tag: Optional[str] = None
 
     # For Kindle and Audible
     note: Optional[str] = None
 
     # For Kindle
     #
-    # Note that location is guaranteed to exist for Kindle.
+    # Note that location is guaranteed to exist for Kindle books, but personal documents (e.g., PDFs) may only have
+    # the page.
     location: Optional[int] = None
     page: Optional[int] = None
 
@@ -137,14 +246,61 @@ class Annotation(BaseModel):
     created_at: Optional[str] = None
     updated_at: Optional[str] = None
 
+    # Date when the annotation was found to be removed from the source (only for Kindle, see `keep_deleted`).
+    removed_at: Optional[str] = None
+
+    # Whether the highlight has been cut short by Amazon, since the clipping limit of the book has been reached (only
+    # for Kindle).
+    truncated: bool = False
+
+
+class AnnotationGroup(BaseModel):
+    """
+    Represents a group of annotations to be exported in its own section (e.g., all highlights of the same color).
+    """
+    title: str
+    annotations: List[Annotation]
+
+
+def format_book_dates(book: Book) -> Book:
+    """
+    Returns a copy of the book with its dates in the configured date format, for writing the notes. Note that the dates
+    are kept in ISO format in the book (and hence in the index), so that they can still be compared after the format
+    is changed.
+    """
+    return book.copy(update={field: format_stored_date(getattr(book, field))
+                             for field in ('publication_date', 'purchase_date', 'last_opened_date')})
+
+
+def is_book_modified(indexed: Book, book: Book) -> bool:
+    """
+    Returns whether the book metadata differs from the indexed one. The last opened dates are compared as dates,
+    so that the same date in another format (e.g., from an older index) is not considered a modification.
+    """
+    if indexed.dict(exclude={'last_opened_date'}) != book.dict(exclude={'last_opened_date'}):
+        return True
+    return not same_date(indexed.last_opened_date, book.last_opened_date)
+
 
 class ExportItem(BaseModel):
     """
     Represents an item to be exported, containing a Book and its associated metadata.
     """
+    # Time when the item is last updated, in RFC 3339 format (older indexes use '%a, %d %b %Y %H:%M:%S %z').
     last_updated_time: str
     info: Book
 
+    # Name of the exported file (without extension).
+    #
+    # This is recorded so that the file name stays the same across runs, even if the book title is ambiguous
+    # (e.g., two editions of the same book) and we need to disambiguate it.
+    file_name: Optional[str] = None
+
+    # Change signals of the exported data (currently only for Audible), i.e., the md5 of the annotations returned by
+    # the API and a hash of the chapters. If both stay the same, only the book metadata in the exported file changes.
+    annotations_version: Optional[str] = None
+    chapters_hash: Optional[str] = None
+
     checked: bool = Field(default=False, exclude=True)
 
     class Config:  # pylint: disable=too-few-public-methods
@@ -154,6 +310,74 @@ class ExportItem(BaseModel):
         fields = {'checked': {'exclude': True}}
 
 
+class Decisions(BaseModel):
+    """
+    Represents the answers to the index prompts, keyed by the book ASIN.
+
+    This allows reproducible (e.g., scheduled) runs, since the prompts are only shown for books that are not
+    covered yet. The possible decisions are:
+    - fetch: fetch the book data and update the index.
+    - skip: neither fetch the book data nor update the index.
+    - index-only: only update the index.
+    """
+    books: Dict[str, Literal['fetch', 'skip', 'index-only']] = {}
+    # Decision for the books that are not covered, which are then neither prompted for nor recorded (e.g., for
+    # unattended runs).
+    default: Optional[Literal['fetch', 'skip', 'index-only']] = None
+
+    @staticmethod
+    def load_or_default(path: Path):
+        """
+        Load the decisions or default to an empty state if there is no such file.
+        """
+        if not os.path.exists(path):
+            return Decisions()
+
+        with open(path, "r", encoding='utf-8') as file:
+            decisions_str = file.read()
+        try:
+            return Decisions(**toml.loads(decisions_str))
+        except (toml.TomlDecodeError, ValidationError) as e:
+            raise ParseError(f'Invalid decisions file {path}. Error: {e}') from e
+
+    def save(self, path: Path):
+        """
+        Save the decisions into the specified path.
+        """
+        decisions_str = toml_dumps_with_newline(self.dict())
+        with open(path, "w", encoding='utf-8') as file:
+            file.write(decisions_str)
+
+    def decide(self, book: Book, fetch_prompt: str, index_prompt: str) -> str:
+        """
+        Returns the decision for the given book, prompting the user (and recording the answer) if there is none.
+        """
+        if book.asin in self.books:
+            return self.books[book.asin]
+        if self.default is not None:
+            return self.default
+
+        decision = prompt_decision(fetch_prompt, index_prompt)
+        self.books[book.asin] = decision
+        return decision
+
+
+# How to name the file of a book whose name collides with another book: ask the user, or append the ASIN, the author,
+# or a counter (e.g., "Title (2)").
+FILE_NAME_COLLISION_POLICIES = ("prompt", "asin", "author", "counter")
+
+
+def prompt_decision(fetch_prompt: str, index_prompt: str) -> str:
+    """
+    Asks the user whether to fetch the book data, and if not, whether to update the index.
+    """
+    if prompt_user(fetch_prompt):
+        return 'fetch'
+    if prompt_user(index_prompt):
+        return 'index-only'
+    return 'skip'
+
+
 class ExportIndex(BaseModel):
     """
     Represents an index of exported items.
@@ -181,7 +405,7 @@ class ExportIndex(BaseModel):
         with open(path, "w", encoding='utf-8') as file:
             file.write(index_str)
 
-    def check_book(self, book: Book, skip_check: bool = False) -> bool:
+    def check_book(self, book: Book, skip_check: bool = False, decisions: Optional[Decisions] = None) -> bool:
         """
         This function checks the book against the index. It returns a boolean that indicates whether the
         book data (e.g., annotations) should be further fetched or not.
@@ -195,10 +419,19 @@ class ExportIndex(BaseModel):
         WARN: They may be some inconsistencies between the exported markdown (if any) and the index file if a
         user decides to update the index but not fetch the book. However, this could be useful to avoid
         keep getting prompts.
+
+        If decisions are given, the prompts are skipped for the books covered by them, and the answers for the
+        remaining books are recorded.
         """
+        def decide(fetch_prompt: str, index_prompt: str) -> str:
+            if skip_check:
+                return 'fetch'
+            if decisions is not None:
+                return decisions.decide(book, fetch_prompt, index_prompt)
+            return prompt_decision(fetch_prompt, index_prompt)
 
         # Generate the current time in case we want to update the index
-        current_datetime = datetime.now().astimezone().strftime("%a, %d %b %Y %H:%M:%S %z")
+        current_datetime = current_timestamp()
 
         # WARN: This could be problematic if someone tampers with the index file manually and adds a book
         #       with a duplicate ASIN. However, we ignore it now since it is not an expected behavior.
@@ -219,7 +452,11 @@ class ExportIndex(BaseModel):
             #       it in the evening, we may not be able to detect the changes. To handle this case, a user
             #       can simply reopen the book on the next day, which will trigger the prompt again, or perhaps
             #       update some metadata in the index which could trigger a fetch prompt.
-            if indexed_book.info == book:
+            if not is_book_modified(indexed_book.info, book):
+                # The dates may be written in another format (e.g., before they are normalized), in which case the
+                # index is updated silently.
+                indexed_book.info = book
+
                 # Only skip if there's no special skip_check flag, which is usually used if one wants to re-export
                 # the entire notebook (e.g., because of changes in note formatting).
                 if not skip_check:
@@ -230,20 +467,17 @@ class ExportIndex(BaseModel):
                 echo(f"- Old: {indexed_book.info}")
                 echo(f"- New: {book}\n")
 
-            # Ask the user first whether they want to fetch the updated annotations
+            # Ask the user first whether they want to fetch the updated annotations.
+            # If yes, then we will automatically update the index to reflect the latest metadata.
+            # If no, then we need to ask users whether they want to update the metadata.
+            decision = decide("Do you want to fetch the latest data for this book?",
+                              "Do you want to update the indexed metadata?")
 
-            # If yes, then we will automatically update the index to reflect the latest metadata
-            if skip_check or prompt_user("Do you want to fetch the latest data for this book?"):
+            if decision != 'skip':
                 indexed_book.info = book
                 indexed_book.last_updated_time = current_datetime
-                return True
 
-            # If no, then we need to ask users whether they want to update the metadata
-            if prompt_user("Do you want to update the indexed metadata?"):
-                indexed_book.info = book
-                indexed_book.last_updated_time = current_datetime
-
-            return False
+            return decision == 'fetch'
 
         # A book couldn't be found on the index
         #
@@ -253,24 +487,102 @@ class ExportIndex(BaseModel):
         echo("\nUnable to find information about the following book in the index:")
         echo(f"  {book}\n")
 
-        # Ask the user first whether they want to fetch the book
-
         # Prepare the export item in case we need to update the index
         item = ExportItem(last_updated_time=current_datetime, info=book)
         item.checked = True
 
-        # If yes, we will automatically update the index as well
-        if skip_check or prompt_user("Do you want to fetch the book data?"):
-            self.books.append(item)
-            return True
+        # Ask the user first whether they want to fetch the book. If yes, we will automatically update the
+        # index as well.
+        #
+        # If no, we ask the user whether they want to update the index. This could be useful if they want to
+        # avoid keep getting prompts for a book that has not been opened again.
+        decision = decide("Do you want to fetch the book data?", "Do you want to add the book to the index?")
 
-        # If no, we ask the user whether they want to update the index.
-        # This could be useful if they want to avoid keep getting prompts for a book that has not
-        # been opened again.
-        if prompt_user("Do you want to add the book to the index?"):
+        if decision != 'skip':
             self.books.append(item)
 
-        return False
+        return decision == 'fetch'
+
+    def get_pending_reason(self, book: Book) -> Optional[str]:
+        """
+        Returns why `check_book` would prompt for the given book, i.e., 'new' if it is not in the index or 'modified'
+        if its metadata has changed, or None if it would be skipped. This does not update the index.
+        """
+        item = self.get_item(book.asin)
+        if item is None:
+            return 'new'
+        return 'modified' if is_book_modified(item.info, book) else None
+
+    def get_item(self, asin: str) -> Optional[ExportItem]:
+        """
+        Returns the indexed item for the given ASIN, if any.
+        """
+        for item in self.books:
+            if item.info.asin == asin:
+                return item
+        return None
+
+    def resolve_file_name(self, book: Book, ambiguous: bool, collision_policy: str = 'prompt') -> str:
+        """
+        Returns the file name (without extension) for exporting the given book.
+
+        If the file name has been recorded in the index, it will be reused. Otherwise, the sanitized book title is
+        used (see `sanitize_file_name`), unless it collides with another book, i.e., the title is ambiguous (shared
+        with another book in the library) or the name is already used by another book in the index. Collisions are
+        resolved according to the policy (see `FILE_NAME_COLLISION_POLICIES`). The file name is then recorded in the
+        index to keep future runs consistent.
+        """
+        item = self.get_item(book.asin)
+        if item is not None and item.file_name:
+            return item.file_name
+
+        title = sanitize_file_name(book.title)
+        taken = {
+            (other.file_name or sanitize_file_name(other.info.title)).casefold()
+            for other in self.books if other.info.asin != book.asin
+        }
+
+        file_name = title
+        if ambiguous or file_name.casefold() in taken:
+            choice = collision_policy
+            if choice == 'prompt':
+                echo(f"\nFound more than one book with the file name '{title}':")
+                echo(f"  {book}\n")
+                choice = prompt(
+                    'How should the file name be disambiguated?',
+                    type=click.Choice(['asin', 'author', 'counter']),
+                    default='asin',
+                )
+
+            if choice == 'author':
+                file_name = sanitize_file_name(f'{book.title} ({book.author})')
+            elif choice == 'counter':
+                # The first book keeps the title as is (if it is not used yet), and the next ones get a counter.
+                counter = 2
+                while file_name.casefold() in taken:
+                    file_name = f'{title} ({counter})'
+                    counter += 1
+            else:
+                file_name = f'{title} ({book.asin})'
+
+        if item is not None:
+            item.file_name = file_name
+        return file_name
+
+    def revert_item(self, asin: str, snapshot: Optional[ExportItem]) -> None:
+        """
+        Reverts the indexed item for the given ASIN to a snapshot taken before checking the book, e.g., because
+        exporting the book failed. This ensures that the book will be fetched again in the next run.
+        """
+        for i, item in enumerate(self.books):
+            if item.info.asin != asin:
+                continue
+            if snapshot is None:
+                del self.books[i]
+            else:
+                self.books[i] = snapshot.copy(deep=True)
+                self.books[i].checked = True
+            return
 
     def warn_unchecked_books(self):
         """
@@ -278,7 +590,117 @@ class ExportIndex(BaseModel):
         """
         for book in self.books:
             if not book.checked:
-                echo(f"Warning: Book {book.info} has not been checked")
+                echo_warning(f"Book {book.info} has not been checked")
+
+
+RUN_SUMMARY_FILE_NAME: str = "run-summary.json"
+
+# How to render the annotations in Markdown.
+#
+# - default: the highlight and note under their own labels.
+# - quote: notes of clips (i.e., Audible) as blockquotes with an attribution footer (book title, chapter, and
+#   timestamp), which is suitable for copy-pasting into other documents.
+ANNOTATION_STYLES = ("default", "quote")
+
+
+class BookResult(BaseModel):
+    """
+    Represents the outcome of exporting a single book.
+    """
+    asin: str
+    title: str
+
+    # One of "exported", "unchanged" (i.e., fetched, but only the book metadata of the exported file is rewritten),
+    # "skipped", or "failed".
+    status: str
+
+    # Number of exported annotations (only for exported books).
+    annotations: int = 0
+    # Time taken to fetch and export the book, in seconds.
+    duration_s: float = 0.0
+    # Error message (only for failed books).
+    error: Optional[str] = None
+    # Warning that needs attention even if the book is exported (e.g., the audio content has changed).
+    warning: Optional[str] = None
+
+
+class RunSummary(BaseModel):
+    """
+    Represents the outcome of an export run, which is written as a machine-readable file for downstream automation.
+    """
+    books: List[BookResult] = []
+
+    @staticmethod
+    def load(path: Path) -> RunSummary:
+        """
+        Loads the summary of a previous run.
+        """
+        if not os.path.exists(path):
+            raise IoError(f'run summary {path} is not found')
+
+        with open(path, "r", encoding='utf-8') as file:
+            summary_str = file.read()
+        try:
+            return RunSummary(**json.loads(summary_str))
+        except (json.JSONDecodeError, ValidationError) as e:
+            raise ParseError(f'Invalid run summary {path}. Error: {e}') from e
+
+    def add(
+        self,
+        book: Book,
+        status: str,
+        annotations: int = 0,
+        duration_s: float = 0.0,
+        error: Optional[str] = None,
+        warning: Optional[str] = None,
+    ) -> None:
+        """
+        Records the outcome for the given book.
+        """
+        self.books.append(BookResult(asin=book.asin, title=book.title, status=status, annotations=annotations,
+                                     duration_s=round(duration_s, 3), error=error, warning=warning))
+
+    def has_failures(self) -> bool:
+        """
+        Returns whether any of the books failed to be exported.
+        """
+        return any(result.status == 'failed' for result in self.books)
+
+    def failed_asins(self) -> List[str]:
+        """
+        Returns the ASINs of the books that failed to be exported.
+        """
+        return [result.asin for result in self.books if result.status == 'failed']
+
+    def save(self, path: Path) -> None:
+        """
+        Saves the summary as a JSON file.
+        """
+        with open(path, "w", encoding='utf-8') as file:
+            json.dump(self.dict(), file, indent=2, ensure_ascii=False)
+            file.write('\n')
+
+    def echo(self) -> None:
+        """
+        Prints the summary, with a row for each book that is exported or failed.
+        """
+        counts = {status: 0 for status in ('exported', 'unchanged', 'skipped', 'failed')}
+        for result in self.books:
+            counts[result.status] += 1
+
+        echo_heading('\nSummary:')
+        rows = [
+            [result.title, result.asin, result.status, result.annotations, f'{result.duration_s:.1f}s',
+             result.error or '']
+            for result in self.books if result.status in ('exported', 'failed')
+        ]
+        if rows:
+            echo_table(['Title', 'ASIN', 'Status', 'Annotations', 'Duration', 'Error'], rows)
+        echo(', '.join(f'{count} {status}' for status, count in counts.items() if count or status != 'unchanged'))
+
+        for result in self.books:
+            if result.warning:
+                echo_warning(f'{result.title} ({result.asin}): {result.warning}')
 
 
 def export_to_markdown(
@@ -287,96 +709,1017 @@ def export_to_markdown(
     chapters: Optional[List[Chapter]] = None,
     annotations: Optional[List[Annotation]] = None,
     annotations_version: Optional[str] = None,
+    notebook_url: Optional[str] = None,
+    annotation_groups: Optional[List[AnnotationGroup]] = None,
+    frontmatter: Optional[FrontmatterConfig] = None,
+    annotation_style: str = 'default',
+    removed_annotations: Optional[List[Annotation]] = None,
+    template: Optional[jinja2.Template] = None,
+    managed_sections: bool = False,
+    dataview_fields: bool = False,
 ) -> None:
     """
-    Exports the given book and annotation data to a Markdown file.
+    Exports the given book and annotation data to a Markdown file. See `write_markdown` for the other arguments.
+
+    If the generated Markdown has managed sections (see `merge_managed_sections`) and so does the existing file, only
+    the managed sections of the existing file are regenerated, so that the text around them is kept.
 
     Args:
         output_file (str): The path to the output Markdown file.
+        template (jinja2.Template): If given, the Markdown is rendered with this template (see
+            `write_markdown_template`) instead of the built-in layout.
+    """
+    buffer = io.StringIO()
+    if template is not None:
+        write_markdown_template(buffer, template, book, chapters=chapters, annotations=annotations,
+                                notebook_url=notebook_url, annotation_groups=annotation_groups,
+                                frontmatter=frontmatter, removed_annotations=removed_annotations,
+                                dataview_fields=dataview_fields)
+    else:
+        write_markdown(buffer, book, chapters=chapters, annotations=annotations,
+                       annotations_version=annotations_version, notebook_url=notebook_url,
+                       annotation_groups=annotation_groups, frontmatter=frontmatter,
+                       annotation_style=annotation_style, removed_annotations=removed_annotations,
+                       managed_sections=managed_sections, dataview_fields=dataview_fields)
+
+    content = buffer.getvalue()
+    if os.path.isfile(output_file):
+        with open(output_file, 'r', encoding='utf-8') as f:
+            content = merge_managed_sections(f.read(), content)
+
+    with open(output_file, 'w', encoding='utf-8') as f:
+        f.write(content)
+
+
+def write_markdown(
+    f: TextIO,
+    book: Book,
+    chapters: Optional[List[Chapter]] = None,
+    annotations: Optional[List[Annotation]] = None,
+    annotations_version: Optional[str] = None,
+    notebook_url: Optional[str] = None,
+    annotation_groups: Optional[List[AnnotationGroup]] = None,
+    frontmatter: Optional[FrontmatterConfig] = None,
+    annotation_style: str = 'default',
+    removed_annotations: Optional[List[Annotation]] = None,
+    managed_sections: bool = False,
+    dataview_fields: bool = False,
+) -> None:
+    """
+    Writes the given book and annotation data as Markdown to the given stream.
+
+    Args:
+        f (TextIO): The stream to write to (e.g., a file or stdout).
         book (Book): The Book object to be exported.
         annotation_list (AnnotationList): The list of annotations associated with the book.
+        notebook_url (str): The URL to view and edit the annotations at the source (only for Kindle).
+        annotation_groups (List[AnnotationGroup]): If given, the annotations are written in a section per group
+            instead of sequentially.
+        frontmatter (FrontmatterConfig): If enabled, the book data is written as YAML frontmatter as well.
+        annotation_style (str): One of the `ANNOTATION_STYLES`.
+        removed_annotations (List[Annotation]): Annotations that have been removed from the source, which are
+            written in an appendix section.
+        managed_sections (bool): Whether to wrap the generated sections with markers, so that they can be
+            regenerated without touching the text around them (see `merge_managed_sections`).
+        dataview_fields (bool): Whether to add the inline fields of each annotation for Obsidian Dataview (see
+            `format_dataview_fields`).
     """
-    with open(output_file, 'w', encoding='utf-8') as f:
-        f.write(f'# {book.title}\n\n')
+    def begin_section(name: str):
+        if managed_sections:
+            f.write(MANAGED_SECTION_BEGIN.format(name=name))
+
+    def end_section(name: str):
+        if managed_sections:
+            f.write(MANAGED_SECTION_END.format(name=name))
+
+    if frontmatter is not None and frontmatter.enabled:
+        f.write('---\n')
+        f.write(yaml.safe_dump(build_frontmatter(book, frontmatter), sort_keys=False, allow_unicode=True))
+        f.write('---\n\n')
+
+    begin_section('book')
+    f.write(f'# {book.title}\n\n')
+
+    # Write information about the book
+
+    if book.subtitle:
+        f.write(f'- Subtitle: {book.subtitle}\n')
+    f.write(f"- Author(s): {book.author}\n")
+    if book.doc_type:
+        f.write(f'- Document type: {book.doc_type}\n')
+    if book.image_url:
+        f.write(f'- Image URL: {book.image_url}\n')
+    if book.cover_path:
+        f.write(f'- Cover: ![cover]({book.cover_path})\n')
+    if book.pdf_url:
+        # Note that accessing the URL typically requires special params. Hence, it's already expected
+        # to be downloaded separately.
+        f.write(f'- PDF URL: {book.pdf_url}\n')
+    if book.publisher:
+        f.write(f'- Publisher: {book.publisher}\n')
+    if book.publication_date:
+        f.write(f'- Publication date: {book.publication_date}\n')
+    if book.purchase_date:
+        f.write(f'- Purchase date: {book.purchase_date}\n')
+    f.write(f"- Last opened date: {book.last_opened_date}\n")
+    f.write(f"- ASIN: {book.asin}\n")
+    if notebook_url:
+        f.write(f'- Notebook: [(kindle notebook)]({notebook_url})\n')
+    f.write('\n')
+
+    # Write chapters
+    if chapters:
+        f.write('## Contents\n\n')
+
+        def write_chapters(chapters: List[Chapter], depth: int):
+            for chapter in chapters:
+                f.write(f"{'  ' * depth}")
+                f.write(f"- {chapter.title}")
+
+                if chapter.start_ms:
+                    start_time = format_duration_from_ms(chapter.start_ms)
+                    end_time = format_duration_from_ms(chapter.end_ms)
+                    f.write(f' [{start_time}, {end_time}]')
 
-        # Write information about the book
+                f.write('\n')
+                if chapter.subchapters:
+                    write_chapters(chapter.subchapters, depth+1)
 
-        if book.subtitle:
-            f.write(f'- Subtitle: {book.subtitle}\n')
-        f.write(f"- Author(s): {book.author}\n")
-        if book.image_url:
-            f.write(f'- Image URL: {book.image_url}\n')
-        if book.pdf_url:
-            # Note that accessing the URL typically requires special params. Hence, it's already expected
-            # to be downloaded separately.
-            f.write(f'- PDF URL: {book.pdf_url}\n')
-        if book.publication_date:
-            f.write(f'- Publication date: {book.publication_date}\n')
-        if book.purchase_date:
-            f.write(f'- Purchase date: {book.purchase_date}\n')
-        f.write(f"- Last opened date: {book.last_opened_date}\n")
-        f.write(f"- ASIN: {book.asin}\n")
+        write_chapters(chapters, 0)
+        f.write('\n')
+    end_section('book')
+
+    # Used to show the position of each clip within the book (only for Audible).
+    runtime_ms = get_runtime_ms(chapters)
+
+    def write_annotation(annotation: Annotation):
+        # Metadata
+        if annotation.removed_at:
+            f.write(f'- Removed: {annotation.removed_at}\n')
+        if annotation.created_at:
+            f.write(f'- Created: {annotation.created_at}')
+            if annotation.updated_at and annotation.updated_at != annotation.created_at:
+                f.write(f' | Updated: {annotation.updated_at}')
+            f.write('\n')
+        if annotation.clip_start_ms:
+            # Note that this is only for Audible
+            start_time = format_duration_from_ms(
+                annotation.clip_start_ms)
+            end_time = format_duration_from_ms(annotation.clip_end_ms)
+            duration = format_duration_from_ms(annotation.clip_end_ms - annotation.clip_start_ms)
+            f.write(f'- Clip: [{start_time}, {end_time}] | Duration: {duration}')
+            if runtime_ms:
+                f.write(f' | Position: {format_position(annotation.clip_start_ms, runtime_ms)}')
+            f.write('\n')
+        if annotation.location:
+            # Note that this is only for Kindle
+            f.write('- ')
+            if annotation.page:
+                f.write(f'Page: {annotation.page} | ')
+            f.write(f'Location: {annotation.location} [(kindle link)]'
+                    f'(kindle://book?action=open&asin={book.asin}&location={annotation.location})\n')
+        elif annotation.page:
+            # Some personal documents (e.g., PDFs) only have the page, which cannot be linked to.
+            f.write(f'- Page: {annotation.page}\n')
+        if annotation.truncated:
+            f.write(f'{MARKDOWN_TRUNCATED_LINE}\n')
+        if dataview_fields:
+            f.write(f'{format_dataview_fields(book, annotation)}\n')
+
+        # Main content
         f.write('\n')
+        if annotation.highlight:
+            tag = f' {annotation.tag}' if annotation.tag else ''
+            f.write(f"**{annotation.highlight_color} highlight:**{tag}\n")
+            # Multi-line highlights need the quote marker on each line.
+            highlight = isolate_bidi(annotation.highlight).replace('\n', '\n> ')
+            f.write(f"> {highlight}\n")
+            f.write('\n')
+        if annotation.note and annotation_style == 'quote' and annotation.clip_start_ms is not None:
+            note = isolate_bidi(annotation.note).replace('\n', '\n> ')
+            f.write(f"> {note}\n>\n")
+            f.write(f"> — {format_attribution(book, chapters, annotation.clip_start_ms)}\n")
+        elif annotation.note:
+            f.write("**Note:**\n")
+            f.write(f"{isolate_bidi(annotation.note)}\n")
+
+        f.write('\n---\n\n')
+
+    # Write annotations
+    if annotations:
+        begin_section('annotations')
+        f.write('## Annotations\n\n')
+        if annotations_version:
+            f.write(f'Version: {annotations_version}\n')
+        f.write('\n---\n\n')
+
+        if annotation_groups is None:
+            for annotation in annotations:
+                write_annotation(annotation)
+        else:
+            # Each group heading is written as its own block between separators, so that the annotation
+            # blocks stay the same as in the sequential order.
+            for group in annotation_groups:
+                f.write(f'### {group.title}\n')
+                f.write('\n---\n\n')
+                for annotation in group.annotations:
+                    write_annotation(annotation)
+        end_section('annotations')
 
-        # Write chapters
-        if chapters:
-            f.write('## Contents\n\n')
+    # Write removed annotations (if any) in an appendix, so that they are not lost from the exported file.
+    if removed_annotations:
+        begin_section('removed')
+        f.write(f'{MARKDOWN_REMOVED_HEADING}\n')
+        f.write('\n---\n\n')
+        for annotation in removed_annotations:
+            write_annotation(annotation)
+        end_section('removed')
 
-            def write_chapters(chapters: List[Chapter], depth: int):
-                for chapter in chapters:
-                    f.write(f"{'  ' * depth}")
-                    f.write(f"- {chapter.title}")
 
-                    if chapter.start_ms:
-                        start_time = format_duration_from_ms(chapter.start_ms)
-                        end_time = format_duration_from_ms(chapter.end_ms)
-                        f.write(f' [{start_time}, {end_time}]')
+# Markers around the sections generated by ncli (see `merge_managed_sections`). HTML comments are not rendered by
+# most Markdown viewers.
+MANAGED_SECTION_BEGIN = '<!-- ncli:begin {name} -->\n'
+MANAGED_SECTION_END = '<!-- ncli:end {name} -->\n'
+MANAGED_SECTION_RE = re.compile(r'^<!-- ncli:begin (\S+) -->\n.*?^<!-- ncli:end \1 -->\n', re.MULTILINE | re.DOTALL)
 
-                    f.write('\n')
-                    if chapter.subchapters:
-                        write_chapters(chapter.subchapters, depth+1)
+# Name of the managed section with the companion PDF appendix of Audible books (see `write_pdf_appendix` in
+# `kit_audible`), which is always wrapped with the markers and placed after the annotations.
+PDF_APPENDIX_SECTION = 'pdf'
 
-            write_chapters(chapters, 0)
-            f.write('\n')
 
-        # Write annotations
-        if annotations:
-            f.write('## Annotations\n\n')
-            if annotations_version:
-                f.write(f'Version: {annotations_version}\n')
-            f.write('\n---\n\n')
-            for annotation in annotations:
-                # Metadata
-                if annotation.created_at:
-                    f.write(f'- Created: {annotation.created_at}')
-                    if annotation.updated_at and annotation.updated_at != annotation.created_at:
-                        f.write(f' | Updated: {annotation.updated_at}')
-                    f.write('\n')
-                if annotation.clip_start_ms:
-                    # Note that this is only for Audible
-                    start_time = format_duration_from_ms(
-                        annotation.clip_start_ms)
-                    end_time = format_duration_from_ms(annotation.clip_end_ms)
-                    f.write(f'- Clip: [{start_time}, {end_time}]\n')
-                if annotation.location:
-                    # Note that this is only for Kindle
-                    f.write('- ')
-                    if annotation.page:
-                        f.write(f'Page: {annotation.page} | ')
-                    f.write(f'Location: {annotation.location} [(kindle link)]'
-                            f'(kindle://book?action=open&asin={book.asin}&location={annotation.location})\n')
-
-                # Main content
-                f.write('\n')
-                if annotation.highlight:
-                    f.write(f"**{annotation.highlight_color} highlight:**\n")
-                    f.write(f"> {annotation.highlight}\n")
-                    f.write('\n')
-                if annotation.note:
-                    f.write("**Note:**\n")
-                    f.write(f"{annotation.note}\n")
+def merge_managed_sections(existing: str, generated: str) -> str:
+    """
+    Merges the managed sections (i.e., the ones wrapped with `MANAGED_SECTION_BEGIN` and `MANAGED_SECTION_END`) of
+    the generated Markdown into the existing one, keeping the text outside of them (e.g., the user's commentary).
 
-                f.write('\n---\n\n')
+    The frontmatter (if any) is always regenerated. Sections that are newly generated are added after the preceding
+    section, while sections that are no longer generated are removed. If either of them has no managed sections
+    (e.g., the existing file is exported before this feature), the generated Markdown is returned as is.
+    """
+    sections = {match.group(1): match.group(0) for match in MANAGED_SECTION_RE.finditer(generated)}
+    if not sections or MANAGED_SECTION_RE.search(existing) is None:
+        return generated
+
+    frontmatter, _ = split_frontmatter(generated)
+    _, body = split_frontmatter(existing)
+
+    # Pieces of the existing body, i.e., (name, text) for the sections and (None, text) for the text around them.
+    pieces: List[Tuple[Optional[str], str]] = []
+    last = 0
+    for match in MANAGED_SECTION_RE.finditer(body):
+        pieces.append((None, body[last:match.start()]))
+        pieces.append((match.group(1), match.group(0)))
+        last = match.end()
+    pieces.append((None, body[last:]))
+
+    names = list(sections)
+    for i, name in enumerate(names):
+        existing_names = [piece[0] for piece in pieces]
+        if name in existing_names:
+            continue
+        previous = next((existing_names.index(other) for other in reversed(names[:i]) if other in existing_names), None)
+        if previous is None:
+            previous = next(j for j, other in enumerate(existing_names) if other is not None) - 1
+        pieces.insert(previous + 1, (name, ''))
+
+    return frontmatter + ''.join(sections.get(name, '') if name is not None else text for name, text in pieces)
+
+
+def split_frontmatter(content: str) -> Tuple[str, str]:
+    """
+    Splits the given Markdown into its frontmatter (if any, including the delimiters) and the rest.
+    """
+    if content.startswith('---\n'):
+        end = content.find('\n---\n', 3)
+        if end != -1:
+            return content[:end + 5], content[end + 5:]
+    return '', content
+
+
+# Default template for the Kindle Markdown notes, which produces the same layout as `write_markdown`. It is meant
+# as a starting point for custom templates.
+DEFAULT_KINDLE_TEMPLATE_PATH: Path = Path(__file__).parent.joinpath('templates', 'kindle.md.j2')
+
+# Directory for templates that are shared across sources (e.g., partials for the frontmatter or an annotation), which
+# can be included by any template. Templates in this directory can also be referred to by their name only.
+TEMPLATES_DIR: Path = constants.BASE_PATH.joinpath('templates')
+
+
+def load_markdown_template(path: Path) -> jinja2.Template:
+    """
+    Loads a Jinja2 template for the Markdown notes. Other templates in the same directory, in `TEMPLATES_DIR`, or the
+    default ones (e.g., "annotation.md.j2") can be included, in this order of precedence.
+    """
+    if not path.is_file() and not path.is_absolute() and TEMPLATES_DIR.joinpath(path).is_file():
+        path = TEMPLATES_DIR.joinpath(path)
+    if not path.is_file():
+        raise ConfigError(f"template '{path}' is not found")
+
+    env = jinja2.Environment(
+        loader=jinja2.FileSystemLoader([path.parent, TEMPLATES_DIR, DEFAULT_KINDLE_TEMPLATE_PATH.parent]),
+        trim_blocks=True,
+        lstrip_blocks=True,
+        keep_trailing_newline=True,
+        undefined=jinja2.StrictUndefined,
+    )
+    env.filters['bidi'] = isolate_bidi
+    env.filters['blockquote'] = lambda text: '> ' + text.replace('\n', '\n> ')
+    env.filters['duration'] = format_duration_from_ms
+    env.filters['yaml'] = lambda data: yaml.safe_dump(data, sort_keys=False, allow_unicode=True)
+    env.filters['dataview'] = lambda annotation, book: format_dataview_fields(book, annotation)
+
+    try:
+        return env.get_template(path.name)
+    except jinja2.TemplateError as e:
+        raise ConfigError(f"invalid template '{path}'. Error: {e}") from e
+
+
+def write_markdown_template(
+    f: TextIO,
+    template: jinja2.Template,
+    book: Book,
+    chapters: Optional[List[Chapter]] = None,
+    annotations: Optional[List[Annotation]] = None,
+    notebook_url: Optional[str] = None,
+    annotation_groups: Optional[List[AnnotationGroup]] = None,
+    frontmatter: Optional[FrontmatterConfig] = None,
+    removed_annotations: Optional[List[Annotation]] = None,
+    dataview_fields: bool = False,
+) -> None:
+    """
+    Renders the given book and annotation data with a template (see `load_markdown_template`) to the given stream.
+
+    The arguments are exposed as template variables with the same names, except for the frontmatter, which is
+    exposed as the mapping to be written (or None if it is disabled).
+    """
+    f.write(template.render(
+        book=book,
+        chapters=chapters or [],
+        annotations=annotations or [],
+        notebook_url=notebook_url,
+        annotation_groups=annotation_groups,
+        frontmatter=build_frontmatter(book, frontmatter) if frontmatter is not None and frontmatter.enabled else None,
+        removed_annotations=removed_annotations or [],
+        dataview_fields=dataview_fields,
+    ))
+
+
+# Unicode "first strong isolate" and "pop directional isolate" characters.
+BIDI_ISOLATE_START = '\u2068'
+BIDI_ISOLATE_END = '\u2069'
+
+
+def isolate_bidi(text: str) -> str:
+    """
+    Wraps each line of the given text in a bidi isolate if it contains right-to-left characters (e.g., Hebrew or
+    Arabic), so that its direction is detected on its own instead of being mixed with the surrounding labels.
+
+    Text without right-to-left characters is returned as is.
+    """
+    if not any(unicodedata.bidirectional(char) in ('R', 'AL') for char in text):
+        return text
+    return '\n'.join(f'{BIDI_ISOLATE_START}{line}{BIDI_ISOLATE_END}' if line else line for line in text.split('\n'))
+
+
+def strip_bidi_isolates(text: str) -> str:
+    """
+    Removes the bidi isolates added by `isolate_bidi`.
+    """
+    return text.replace(BIDI_ISOLATE_START, '').replace(BIDI_ISOLATE_END, '')
+
+
+def format_dataview_fields(book: Book, annotation: Annotation) -> str:
+    """
+    Formats the metadata of an annotation as inline fields for Obsidian Dataview, e.g.,
+    "[color:: yellow] [page:: 32] [location:: 1024]", so that the annotations can be queried across notes.
+
+    The cover of the book (or its image URL) is included as well, so that query results can show it.
+    """
+    fields: Dict[str, Any] = {}
+    if annotation.highlight and annotation.highlight_color:
+        fields['color'] = annotation.highlight_color.lower()
+    if annotation.page is not None:
+        fields['page'] = annotation.page
+    if annotation.location is not None:
+        fields['location'] = annotation.location
+    if annotation.clip_start_ms is not None:
+        fields['clip'] = format_duration_from_ms(annotation.clip_start_ms)
+    if book.cover_path or book.image_url:
+        fields['cover'] = book.cover_path or book.image_url
+    return ' '.join(f'[{key}:: {value}]' for key, value in fields.items())
+
+
+def build_frontmatter(book: Book, config: FrontmatterConfig) -> Dict[str, Any]:
+    """
+    Returns the frontmatter for the given book, with the keys renamed and extra values added according to the config.
+    Empty fields are omitted.
+    """
+    data: Dict[str, Any] = {}
+    for field, value in book.dict().items():
+        key = config.renames.get(field, field)
+        if key and value:
+            data[key] = value
+
+    data.update(config.extra)
+    return data
+
+
+# Supported output formats for exporting a book, mapped to their file extensions.
+OUTPUT_FORMATS: dict[str, str] = {
+    'markdown': 'md',
+    'opml': 'opml',
+    'csv': 'csv',
+    'org': 'org',
+    'anki': 'tsv',
+}
+
+
+def export_book(
+    target: Path,
+    file_name: str,
+    output_format: str,
+    book: Book,
+    chapters: Optional[List[Chapter]] = None,
+    annotations: Optional[List[Annotation]] = None,
+    annotations_version: Optional[str] = None,
+    notebook_url: Optional[str] = None,
+    annotation_groups: Optional[List[AnnotationGroup]] = None,
+    frontmatter: Optional[FrontmatterConfig] = None,
+    annotation_style: str = 'default',
+    removed_annotations: Optional[List[Annotation]] = None,
+    template: Optional[jinja2.Template] = None,
+    managed_sections: bool = False,
+    dataview_fields: bool = False,
+) -> Path:
+    """
+    Exports the given book and annotation data in the given output format.
+
+    Args:
+        target (Path): The directory for the exported file.
+        file_name (str): The name of the exported file, without extension.
+        output_format (str): One of the `OUTPUT_FORMATS`.
+        annotation_groups (List[AnnotationGroup]): If given, the annotations are exported in a section per group.
+        frontmatter (FrontmatterConfig): The frontmatter config, which is only used for Markdown.
+        annotation_style (str): One of the `ANNOTATION_STYLES`, which is only used for Markdown.
+        removed_annotations (List[Annotation]): Annotations removed from the source, which are only kept in Markdown.
+        template (jinja2.Template): The template for Markdown, or None to use the built-in layout.
+        managed_sections (bool): Whether to only regenerate the managed sections of existing Markdown files (see
+            `merge_managed_sections`).
+        dataview_fields (bool): Whether to add inline fields for Obsidian Dataview, which is only used for Markdown.
+
+    Returns:
+        Path: The path to the exported file.
+    """
+    output_file = target.joinpath(f'{file_name}.{OUTPUT_FORMATS[output_format]}')
+
+    book = format_book_dates(book)
+
+    if output_format == 'opml':
+        export_to_opml(output_file, book, chapters=chapters, annotations=annotations,
+                       annotation_groups=annotation_groups)
+    elif output_format == 'csv':
+        export_to_csv(output_file, book, annotations=annotations)
+    elif output_format == 'org':
+        export_to_org(output_file, book, annotations=annotations, annotation_groups=annotation_groups)
+    elif output_format == 'anki':
+        export_to_anki(output_file, book, annotations=annotations)
+    else:
+        export_to_markdown(output_file, book, chapters=chapters, annotations=annotations,
+                           annotations_version=annotations_version, notebook_url=notebook_url,
+                           annotation_groups=annotation_groups, frontmatter=frontmatter,
+                           annotation_style=annotation_style, removed_annotations=removed_annotations,
+                           template=template, managed_sections=managed_sections, dataview_fields=dataview_fields)
+
+    return output_file
+
+
+# Rough sizes of the files written for each book, which are used to estimate the free space needed by an export (see
+# `estimate_export_size`). These are on the generous side, since running out of space midway is worse.
+ESTIMATED_NOTE_SIZE: int = 256 * 1024
+ESTIMATED_COVER_SIZE: int = 512 * 1024
+ESTIMATED_PDF_SIZE: int = 20 * 1024 * 1024
+
+
+def estimate_export_size(books: List[Book], download_covers: bool = False) -> int:
+    """
+    Estimates the number of bytes written by exporting the given books, including their covers (if downloaded) and
+    companion PDFs (only for Audible). Note that a new copy of each PDF is written next to the previous one.
+    """
+    size = 0
+    for book in books:
+        size += ESTIMATED_NOTE_SIZE
+        if download_covers:
+            size += ESTIMATED_COVER_SIZE
+        if book.pdf_url:
+            size += ESTIMATED_PDF_SIZE
+    return size
+
+
+# Directory within the export target for the downloaded cover images.
+COVERS_DIR_NAME: str = "covers"
+
+
+def download_cover(book: Book, target: Path) -> Optional[str]:
+    """
+    Downloads the cover image of the book to `covers/<asin>.<ext>` in the target directory, since the image URLs
+    may stop working over time. Covers that have been downloaded before are reused.
+
+    Returns:
+        Optional[str]: The path of the cover relative to the target, or None if the book has no cover image or the
+        download fails (which is only reported as a warning).
+    """
+    if not book.image_url:
+        return None
+
+    extension = os.path.splitext(book.image_url.split('?', 1)[0])[1] or '.jpg'
+    relative_path = f'{COVERS_DIR_NAME}/{book.asin}{extension}'
+    cover_file = target.joinpath(relative_path)
+    if cover_file.is_file():
+        return relative_path
+
+    try:
+        response = requests.get(book.image_url, timeout=30)
+        response.raise_for_status()
+    except requests.RequestException as e:
+        echo_warning(f'Failed to download the cover of book {book.title} ({book.asin}). Error: {e}')
+        return None
+
+    os.makedirs(cover_file.parent, exist_ok=True)
+    with open(cover_file, 'wb') as f:
+        f.write(response.content)
+    return relative_path
+
+
+def get_runtime_ms(chapters: Optional[List[Chapter]]) -> Optional[int]:
+    """
+    Returns the runtime of a book based on its chapters (i.e., the end of the last chapter), if known.
+    """
+    return max((chapter.end_ms for chapter in chapters or [] if chapter.end_ms is not None), default=None)
+
+
+def format_position(position_ms: int, runtime_ms: int) -> str:
+    """
+    Formats the given position as a percentage of the runtime, e.g., "37%".
+    """
+    return f'{position_ms * 100 // runtime_ms}%'
+
+
+def format_attribution(book: Book, chapters: Optional[List[Chapter]], position_ms: int) -> str:
+    """
+    Formats the attribution for a quote at the given position, e.g., "*Title*, Chapter 1, 0:01:02".
+    """
+    parts = [f'*{book.title}*']
+    chapter = find_chapter(chapters, position_ms)
+    if chapter is not None:
+        parts.append(chapter.title)
+    parts.append(format_duration_from_ms(position_ms))
+    return ', '.join(parts)
+
+
+def find_chapter(chapters: Optional[List[Chapter]], position_ms: int) -> Optional[Chapter]:
+    """
+    Finds the most specific (i.e., deepest) chapter that contains the given position.
+    """
+    for chapter in chapters or []:
+        if chapter.start_ms is None or chapter.end_ms is None:
+            continue
+        if chapter.start_ms <= position_ms < chapter.end_ms:
+            return find_chapter(chapter.subchapters, position_ms) or chapter
+    return None
+
+
+def export_to_opml(
+    output_file: Path,
+    book: Book,
+    chapters: Optional[List[Chapter]] = None,
+    annotations: Optional[List[Annotation]] = None,
+    annotation_groups: Optional[List[AnnotationGroup]] = None,
+) -> None:
+    """
+    Exports the given book and annotation data to an OPML file.
+
+    The outline is structured as book -> chapters (if any) -> highlights -> notes, which can be imported into
+    outliners (e.g., Workflowy and Dynalist). If annotation groups are given, each group is added as an outline
+    between the book and its highlights instead.
+    """
+    opml = ET.Element('opml', version='2.0')
+    head = ET.SubElement(opml, 'head')
+    ET.SubElement(head, 'title').text = book.title
+    body = ET.SubElement(opml, 'body')
+
+    book_outline = ET.SubElement(body, 'outline', text=book.title)
+    if book.subtitle:
+        book_outline.set('_note', book.subtitle)
+
+    runtime_ms = get_runtime_ms(chapters)
+
+    # Outline element for each chapter, keyed by their identity since chapters are not hashable.
+    chapter_outlines: dict[int, ET.Element] = {}
+
+    def add_chapters(parent: ET.Element, chapters: List[Chapter]):
+        for chapter in chapters:
+            outline = ET.SubElement(parent, 'outline', text=chapter.title)
+            chapter_outlines[id(chapter)] = outline
+            if chapter.subchapters:
+                add_chapters(outline, chapter.subchapters)
+
+    if chapters:
+        add_chapters(book_outline, chapters)
+
+    # Parent outline for each annotation, keyed by their identity since annotations are not hashable.
+    group_outlines: dict[int, ET.Element] = {}
+    for group in annotation_groups or []:
+        group_outline = ET.SubElement(book_outline, 'outline', text=group.title)
+        for annotation in group.annotations:
+            group_outlines[id(annotation)] = group_outline
+
+    for annotation in annotations or []:
+        parent = group_outlines.get(id(annotation), book_outline)
+        if annotation.clip_start_ms is not None:
+            chapter = find_chapter(chapters, annotation.clip_start_ms)
+            if chapter is not None:
+                parent = chapter_outlines[id(chapter)]
+
+        if annotation.highlight:
+            outline = ET.SubElement(parent, 'outline', text=annotation.highlight)
+            if annotation.note:
+                ET.SubElement(outline, 'outline', text=annotation.note)
+        elif annotation.note:
+            outline = ET.SubElement(parent, 'outline', text=annotation.note)
+        else:
+            continue
+
+        notes = []
+        if annotation.location:
+            notes.append(f'Location: {annotation.location}')
+        elif annotation.clip_start_ms is not None:
+            clip_note = f'Clip: {format_duration_from_ms(annotation.clip_start_ms)}'
+            if runtime_ms:
+                clip_note += f' ({format_position(annotation.clip_start_ms, runtime_ms)})'
+            notes.append(clip_note)
+        if annotation.created_at:
+            notes.append(f'Created: {annotation.created_at}')
+        if notes:
+            outline.set('_note', ' | '.join(notes))
+
+    ET.indent(opml)
+    ET.ElementTree(opml).write(output_file, encoding='utf-8', xml_declaration=True)
+
+
+# Heading of the appendix section for the annotations that have been removed from the source.
+MARKDOWN_REMOVED_HEADING = '## Removed highlights\n'
+
+
+# Columns of the CSV export, with a row per annotation. The clip columns are only filled for Audible.
+CSV_COLUMNS = [
+    'asin', 'title', 'author', 'page', 'location', 'color', 'highlight', 'note', 'clip_start', 'clip_end', 'created_at',
+]
+
+
+def export_to_csv(
+    output_file: Path,
+    book: Book,
+    annotations: Optional[List[Annotation]] = None,
+) -> None:
+    """
+    Exports the given annotations to a CSV file with a row per annotation (see `CSV_COLUMNS`), which can be imported
+    into spreadsheets or other tools (e.g., Readwise).
+    """
+    with open(output_file, 'w', encoding='utf-8', newline='') as f:
+        writer = csv.writer(f)
+        writer.writerow(CSV_COLUMNS)
+        for annotation in annotations or []:
+            writer.writerow([
+                book.asin,
+                book.title,
+                book.author,
+                annotation.page or '',
+                annotation.location or '',
+                annotation.highlight_color if annotation.highlight else '',
+                annotation.highlight or '',
+                annotation.note or '',
+                format_duration_from_ms(annotation.clip_start_ms) if annotation.clip_start_ms is not None else '',
+                format_duration_from_ms(annotation.clip_end_ms) if annotation.clip_end_ms is not None else '',
+                annotation.created_at or '',
+            ])
+
+
+def export_to_org(
+    output_file: Path,
+    book: Book,
+    annotations: Optional[List[Annotation]] = None,
+    annotation_groups: Optional[List[AnnotationGroup]] = None,
+) -> None:
+    """
+    Exports the given book and annotation data to an Org file (e.g., for Emacs).
+
+    The book is written as a top-level heading with a property drawer for its metadata, and each annotation as a
+    sub-heading with a property drawer for its position. If annotation groups are given, each group is written as a
+    heading between the book and its annotations instead.
+    """
+    with open(output_file, 'w', encoding='utf-8') as f:
+        f.write(f'#+TITLE: {book.title}\n\n')
+        f.write(f'* {book.title}\n')
+        _write_org_properties(f, {
+            'ASIN': book.asin,
+            'AUTHOR': book.author,
+            'SUBTITLE': book.subtitle,
+            'PUBLISHER': book.publisher,
+            'PUBLICATION_DATE': book.publication_date,
+            'LAST_OPENED_DATE': book.last_opened_date,
+        })
+
+        if annotation_groups is None:
+            for annotation in annotations or []:
+                _write_org_annotation(f, annotation, 2)
+        else:
+            for group in annotation_groups:
+                f.write(f'** {group.title}\n')
+                for annotation in group.annotations:
+                    _write_org_annotation(f, annotation, 3)
+
+
+def _write_org_properties(f: TextIO, properties: Dict[str, Any]) -> None:
+    f.write(':PROPERTIES:\n')
+    for key, value in properties.items():
+        if value is not None and value != '':
+            f.write(f':{key}: {value}\n')
+    f.write(':END:\n')
+
+
+def _write_org_annotation(f: TextIO, annotation: Annotation, level: int) -> None:
+    if annotation.location is not None:
+        title = f'Location {annotation.location}'
+    elif annotation.page is not None:
+        title = f'Page {annotation.page}'
+    elif annotation.clip_start_ms is not None:
+        title = f'Clip {format_duration_from_ms(annotation.clip_start_ms)}'
+    else:
+        title = 'Annotation'
+    f.write(f"{'*' * level} {title}\n")
+
+    clip = None
+    if annotation.clip_start_ms is not None and annotation.clip_end_ms is not None:
+        clip = f'{format_duration_from_ms(annotation.clip_start_ms)}-{format_duration_from_ms(annotation.clip_end_ms)}'
+    _write_org_properties(f, {
+        'LOCATION': annotation.location,
+        'PAGE': annotation.page,
+        'COLOR': annotation.highlight_color if annotation.highlight else None,
+        'CLIP': clip,
+        'CREATED': annotation.created_at,
+        'UPDATED': annotation.updated_at if annotation.updated_at != annotation.created_at else None,
+    })
+
+    if annotation.highlight:
+        # Lines starting with "*" or "#+" would be parsed as headings or keywords, so they are escaped with a comma.
+        highlight = re.sub(r'^(\*|#\+)', r',\1', annotation.highlight, flags=re.MULTILINE)
+        f.write(f'#+BEGIN_QUOTE\n{highlight}\n#+END_QUOTE\n')
+    if annotation.note:
+        # A leading space is enough to prevent a note line from being parsed as a heading.
+        note = re.sub(r'^\*', ' *', annotation.note, flags=re.MULTILINE)
+        f.write(f'{note}\n')
+
+
+# Parent deck of the Anki export, under which each book gets its own deck.
+ANKI_PARENT_DECK = 'Highlights'
+
+
+def export_to_anki(
+    output_file: Path,
+    book: Book,
+    annotations: Optional[List[Annotation]] = None,
+) -> None:
+    """
+    Exports the highlights to a TSV file that can be imported into Anki (2.1.55 or later) as Basic notes in a deck
+    per book, i.e., "Highlights::<title>". The front of each card is the note (or the position of the highlight if it
+    does not have one), and the back is the highlight, while the tag of the highlight color (if any) becomes a tag.
+
+    Annotations without a highlight (e.g., Audible clips without the transcript) are skipped.
+    """
+    # "::" separates the deck levels in Anki.
+    deck = f"{ANKI_PARENT_DECK}::{book.title.replace('::', ':')}"
+    with open(output_file, 'w', encoding='utf-8', newline='') as f:
+        # See https://docs.ankiweb.net/importing/text-files.html#file-headers
+        f.write(f'#separator:tab\n#html:false\n#notetype:Basic\n#deck:{deck}\n#tags column:3\n')
+        writer = csv.writer(f, delimiter='\t', lineterminator='\n')
+        for annotation in annotations or []:
+            if not annotation.highlight:
+                continue
+
+            if annotation.note:
+                front = annotation.note
+            elif annotation.location is not None:
+                front = f'{book.title}, location {annotation.location}'
+            elif annotation.page is not None:
+                front = f'{book.title}, page {annotation.page}'
+            elif annotation.clip_start_ms is not None:
+                front = f'{book.title}, {format_duration_from_ms(annotation.clip_start_ms)}'
+            else:
+                front = book.title
+            # Tags cannot contain spaces in Anki.
+            tag = (annotation.tag or '').lstrip('#').replace(' ', '_')
+            writer.writerow([front, annotation.highlight, tag])
+
+
+def parse_markdown_annotations(path: Path) -> List[Annotation]:
+    """
+    Parses the annotations from a Markdown file generated by `export_to_markdown`.
+
+    Note that this is a best-effort parser, which only recovers the fields that are written to the file.
+    Returns an empty list if the file does not exist.
+    """
+    if not path.is_file():
+        return []
+
+    with open(path, 'r', encoding='utf-8') as f:
+        section = _find_markdown_annotations_section(f.read())
+    if section is None:
+        return []
+
+    # The first block contains the annotations version (if any), so it is skipped.
+    return _parse_markdown_annotation_blocks(section.split('\n---\n')[1:])
+
+
+def _find_markdown_annotations_section(content: str) -> Optional[str]:
+    """
+    Returns the annotations section of a Markdown file generated by `export_to_markdown` (without its heading), which
+    ends before the removed annotations or the PDF appendix, or None if there are no annotations.
+    """
+    _, found, section = content.partition('## Annotations\n')
+    if not found:
+        return None
+    for end in [MARKDOWN_REMOVED_HEADING, MANAGED_SECTION_END.format(name='annotations'),
+                MANAGED_SECTION_BEGIN.format(name=PDF_APPENDIX_SECTION)]:
+        section = section.split(end, 1)[0]
+    return section
+
+
+def parse_markdown_removed_annotations(path: Path) -> List[Annotation]:
+    """
+    Parses the removed annotations (i.e., the appendix section) from a Markdown file generated by `export_to_markdown`.
+
+    Returns an empty list if the file does not exist.
+    """
+    if not path.is_file():
+        return []
+
+    with open(path, 'r', encoding='utf-8') as f:
+        content = f.read()
+
+    _, found, section = content.partition(MARKDOWN_REMOVED_HEADING)
+    if not found:
+        return []
+    section = section.split(MANAGED_SECTION_END.format(name='removed'), 1)[0]
+
+    # The first block is empty (i.e., between the heading and the first separator), so it is skipped.
+    return _parse_markdown_annotation_blocks(section.split('\n---\n')[1:])
+
+
+MARKDOWN_PAGE_RE = re.compile(r'Page: (\d+)')
+MARKDOWN_LOCATION_RE = re.compile(r'Location: (\d+)')
+MARKDOWN_CLIP_RE = re.compile(r'- Clip: \[([0-9:.]+), ([0-9:.]+)\]')
+MARKDOWN_REMOVED_RE = re.compile(r'- Removed: (.+)$')
+MARKDOWN_CREATED_RE = re.compile(r'- Created: ([^|\n]+?)(?: \| Updated: (.+))?$')
+MARKDOWN_HIGHLIGHT_RE = re.compile(r'^\*\*(\S+) highlight:\*\*(?: (.+))?$')
+MARKDOWN_ATTRIBUTION_PREFIX = '> — '
+MARKDOWN_TRUNCATED_LINE = '- Truncated: the clipping limit of the book has been reached'
+
+
+def _parse_markdown_annotation_blocks(blocks: List[str]) -> List[Annotation]:
+    annotations = []
+    for block in blocks:
+        annotation = _parse_markdown_annotation_block(block)
+        if annotation is not None:
+            annotations.append(annotation)
+    return annotations
+
+
+def _parse_markdown_annotation_block(block: str) -> Optional[Annotation]:
+    annotation = Annotation()
+
+    lines = strip_bidi_isolates(block).strip('\n').split('\n')
+    i = 0
+
+    # Metadata lines, until the first empty line
+    while i < len(lines) and lines[i].strip():
+        line = lines[i]
+        if match := MARKDOWN_REMOVED_RE.match(line):
+            annotation.removed_at = match.group(1)
+        elif line == MARKDOWN_TRUNCATED_LINE:
+            annotation.truncated = True
+        elif match := MARKDOWN_CREATED_RE.match(line):
+            annotation.created_at = match.group(1)
+            annotation.updated_at = match.group(2) or match.group(1)
+        elif match := MARKDOWN_CLIP_RE.match(line):
+            annotation.clip_start_ms = parse_duration_to_ms(match.group(1))
+            annotation.clip_end_ms = parse_duration_to_ms(match.group(2))
+        elif match := MARKDOWN_LOCATION_RE.search(line):
+            annotation.location = int(match.group(1))
+            if page_match := MARKDOWN_PAGE_RE.search(line):
+                annotation.page = int(page_match.group(1))
+        elif match := MARKDOWN_PAGE_RE.search(line):
+            annotation.page = int(match.group(1))
+        i += 1
+
+    # Main content
+    while i < len(lines):
+        line = lines[i]
+        if match := MARKDOWN_HIGHLIGHT_RE.match(line):
+            annotation.highlight_color = match.group(1)
+            annotation.tag = match.group(2)
+            highlight_lines = []
+            i += 1
+            while i < len(lines) and lines[i].startswith('>'):
+                highlight_lines.append(lines[i][1:].strip())
+                i += 1
+            annotation.highlight = '\n'.join(highlight_lines)
+            continue
+        if line.startswith('**Note:**'):
+            note = '\n'.join([line[len('**Note:**'):]] + lines[i + 1:]).strip()
+            annotation.note = note if note else None
+            break
+        if line.startswith('>') and annotation.highlight is None:
+            # A note in the quote style, followed by its attribution.
+            note_lines = []
+            while i < len(lines) and lines[i].startswith('>') and not lines[i].startswith(MARKDOWN_ATTRIBUTION_PREFIX):
+                note_lines.append(lines[i][1:].strip())
+                i += 1
+            note = '\n'.join(note_lines).strip()
+            annotation.note = note if note else None
+            break
+        i += 1
+
+    if annotation.highlight is None and annotation.note is None:
+        return None
+    return annotation
+
+
+def count_markdown_annotations(path: Path) -> int:
+    """
+    Counts the annotations in a Markdown file generated by `export_to_markdown`.
+
+    Returns 0 if the file does not exist.
+    """
+    # Note that the blocks are parsed rather than counting the separators, since the group headings (e.g., with
+    # `--group-by color`) are written as their own blocks as well.
+    return len(parse_markdown_annotations(path))
+
+
+def merge_into_markdown(
+    output_file: Path,
+    book: Book,
+    annotations: List[Annotation],
+    notebook_url: Optional[str] = None,
+    dataview_fields: bool = False,
+) -> int:
+    """
+    Appends the annotations that are not in an existing Markdown file (generated by `export_to_markdown`) yet, based
+    on their position (i.e., location, page, or clip start for Audible) and highlight, keeping the rest of the file
+    intact (e.g., manual edits).
+
+    The appended annotations are written with the built-in layout, before the removed annotations section (if any).
+
+    Returns:
+        int: The number of appended annotations.
+    """
+    def key(annotation: Annotation):
+        # The highlight is needed to tell apart the annotations without a location (e.g., the ones of PDFs, which only
+        # have the page). Note that the whitespace is normalized, since the parsed lines are stripped.
+        highlight = ' '.join((annotation.highlight or '').split())
+        return annotation.location, annotation.page, annotation.clip_start_ms, highlight
+
+    existing_keys = {key(annotation) for annotation in parse_markdown_annotations(output_file)}
+    new_annotations = [annotation for annotation in annotations if key(annotation) not in existing_keys]
+    if not new_annotations:
+        return 0
+
+    with open(output_file, 'r', encoding='utf-8') as f:
+        content = f.read()
+
+    managed = MANAGED_SECTION_RE.search(content) is not None
+    buffer = io.StringIO()
+    write_markdown(buffer, format_book_dates(book), annotations=new_annotations, notebook_url=notebook_url,
+                   managed_sections=managed, dataview_fields=dataview_fields)
+    generated = buffer.getvalue()
+
+    annotations_end = MANAGED_SECTION_END.format(name='annotations')
+    if '## Annotations\n' in content:
+        # Only the annotation blocks are needed, i.e., the ones after the first separator. Each block ends with a
+        # separator, so the new blocks can be added right after the last one.
+        section = generated.partition('## Annotations\n')[2].split('\n---\n\n', 1)[1].split(annotations_end, 1)[0]
+        positions = [annotations_end, MARKDOWN_REMOVED_HEADING]
+    else:
+        section = generated[generated.index(MANAGED_SECTION_BEGIN.format(name='annotations') if managed
+                                            else '## Annotations\n'):]
+        positions = [MANAGED_SECTION_BEGIN.format(name='removed'), MARKDOWN_REMOVED_HEADING]
+    positions.append(MANAGED_SECTION_BEGIN.format(name=PDF_APPENDIX_SECTION))
+
+    # The new annotations are added before the removed annotations section or the PDF appendix (if any).
+    position = next((content.index(marker) for marker in positions if marker in content), len(content))
+    with open(output_file, 'w', encoding='utf-8') as f:
+        f.write(content[:position] + section + content[position:])
+
+    return len(new_annotations)
 
 
 # ---
@@ -417,6 +1760,45 @@ def prompt_otp_callback() -> str:
     return str(guess).strip().lower()
 
 
+def run_totp_command(command: str) -> str:
+    """
+    Runs the given shell command and returns the OTP code that it prints.
+    """
+    try:
+        result = subprocess.run(command, shell=True, capture_output=True, text=True, timeout=60, check=False)
+    except subprocess.TimeoutExpired as e:
+        raise AuthError(f'TOTP command timed out: {command}') from e
+    if result.returncode != 0:
+        raise AuthError(f'TOTP command failed with exit code {result.returncode}: {result.stderr.strip()}')
+
+    code = result.stdout.strip().replace(' ', '')
+    if not code.isdigit():
+        raise AuthError('TOTP command did not print an OTP code')
+    return code
+
+
+def build_otp_callback(totp_secret: Optional[str], totp_command: Optional[str] = None) -> Callable[[], str]:
+    """
+    Returns the callback for 2-factor authentication, which gets the code from the TOTP command or generates it from
+    the TOTP secret if given.
+    """
+    if totp_command:
+        def command_callback() -> str:
+            echo("2FA is activated for this account. Getting OTP code from the configured TOTP command.")
+            return run_totp_command(totp_command)
+
+        return command_callback
+
+    if not totp_secret:
+        return prompt_otp_callback
+
+    def callback() -> str:
+        echo("2FA is activated for this account. Generating OTP code from the configured TOTP secret.")
+        return generate_totp(totp_secret)
+
+    return callback
+
+
 def prompt_external_callback(url: str) -> str:
     # import readline to prevent issues when input URL in
     # CLI prompt when using macOS
@@ -435,8 +1817,17 @@ def build_auth_file(
     country_code: str,
     file_password: Optional[str] = None,
     external_login: bool = False,
-    with_username: bool = False
+    with_username: bool = False,
+    totp_secret: Optional[str] = None,
+    login_fallback: bool = False,
+    totp_command: Optional[str] = None,
 ) -> None:
+    """
+    Logs in to Amazon and saves the registered device into the auth file.
+
+    If `login_fallback` is set and the login on the terminal fails, the user is offered to continue with the
+    external browser login instead of aborting, after which the device is registered the same way.
+    """
     echo()
     secho("Login with amazon to your audible account now.", bold=True)
 
@@ -447,18 +1838,32 @@ def build_auth_file(
             encryption=DEFAULT_AUTH_FILE_ENCRYPTION
         )
 
-    if external_login:
+    auth = None
+    if not external_login:
+        try:
+            auth = Authenticator.from_login(
+                username=username,
+                password=password,
+                locale=country_code,
+                captcha_callback=prompt_captcha_callback,
+                otp_callback=build_otp_callback(totp_secret, totp_command))
+        except click.Abort:
+            # The user aborted one of the prompts (e.g., the captcha or OTP), which should not offer another login.
+            raise
+        except Exception as e:  # pylint: disable=broad-except
+            # The login flow may fail in many ways (e.g., a page that it does not recognize), which can typically be
+            # handled by a human in the browser.
+            if not login_fallback:
+                raise
+            echo_warning(f'Failed to log in on the terminal. Error: {e}')
+            if not prompt_user('Do you want to continue by logging in with the external browser?'):
+                raise
+
+    if auth is None:
         auth = Authenticator.from_login_external(
             locale=country_code,
             with_username=with_username,
             login_url_callback=prompt_external_callback)
-    else:
-        auth = Authenticator.from_login(
-            username=username,
-            password=password,
-            locale=country_code,
-            captcha_callback=prompt_captcha_callback,
-            otp_callback=prompt_otp_callback)
 
     echo()
 
@@ -475,6 +1880,43 @@ def build_auth_file(
 # Downloader
 # ---
 
+# Suffix of the file that is used while a download is in progress.
+DOWNLOAD_TMP_SUFFIX: str = ".tmp"
+
+
+def find_leftover_downloads(directory: Path) -> List[Path]:
+    """
+    Finds temporary download files that are left behind (e.g., because a download was interrupted).
+    """
+    if not directory.is_dir():
+        return []
+    return sorted(directory.glob(f"*{DOWNLOAD_TMP_SUFFIX}"))
+
+
+# Previous versions of a downloaded file are kept as `<file>.old.<N>`, where a larger N is more recent.
+DOWNLOAD_OLD_RE = re.compile(r'^(?P<name>.+)\.old\.(?P<index>\d+)$')
+
+
+def find_old_downloads(directory: Path, keep: int) -> List[Path]:
+    """
+    Finds previous versions of downloaded files, except for the `keep` most recent ones of each file.
+    """
+    if not directory.is_dir():
+        return []
+
+    versions: dict[str, List[tuple[int, Path]]] = {}
+    for path in directory.iterdir():
+        match = DOWNLOAD_OLD_RE.match(path.name)
+        if match and path.is_file():
+            versions.setdefault(match.group('name'), []).append((int(match.group('index')), path))
+
+    paths = []
+    for file_versions in versions.values():
+        file_versions.sort()
+        paths.extend(path for _, path in file_versions[:max(0, len(file_versions) - keep)])
+    return sorted(paths)
+
+
 class Downloader:
     """
     This code is based on the implementation found at:
@@ -493,7 +1935,7 @@ class Downloader:
     ) -> None:
         self._url = url
         self._file = Path(file).resolve()
-        self._tmp_file = self._file.with_suffix(".tmp")
+        self._tmp_file = self._file.with_suffix(DOWNLOAD_TMP_SUFFIX)
         self._client = client
         self._overwrite_existing = overwrite_existing
 
@@ -551,9 +1993,12 @@ class Downloader:
         file = self._file
         tmp_file = self._tmp_file
         if file.exists() and self._overwrite_existing:
+            # Use the index after the most recent version, since older versions may have been removed.
             i = 0
-            while file.with_suffix(f"{file.suffix}.old.{i}").exists():
-                i += 1
+            for path in file.parent.iterdir():
+                match = DOWNLOAD_OLD_RE.match(path.name)
+                if match and match.group('name') == file.name:
+                    i = max(i, int(match.group('index')) + 1)
             file.rename(file.with_suffix(f"{file.suffix}.old.{i}"))
         tmp_file.rename(file)
         echo(f"File {self._file} downloaded in {elapsed}.")
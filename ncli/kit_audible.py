@@ -5,17 +5,120 @@
 A module for processing and managing Audible data.
 """
 
-from typing import List, Optional
+import hashlib
+import json
+import os
+import re
+import time
+from collections import Counter
+from datetime import datetime
+from typing import Dict, List, Optional
 from pathlib import Path
 
 import audible
-from click import echo
-
-from ncli.kit_amazon import Config, load_authenticator, \
-    Book, Chapter, Annotation, ExportIndex, export_to_markdown, Downloader
-from ncli.utils import extract_date, format_date
+import toml
+from click import Abort, echo
+from pydantic import BaseModel, validator  # pylint: disable=no-name-in-module
+
+from ncli.errors import ConfigError
+from ncli.kit_amazon import Config as AmazonConfig, FrontmatterConfig, load_authenticator, \
+    Book, Chapter, Annotation, ExportIndex, RunSummary, ANNOTATION_STYLES, FILE_NAME_COLLISION_POLICIES, \
+    OUTPUT_FORMATS, RUN_SUMMARY_FILE_NAME, download_cover, estimate_export_size, export_book, get_runtime_ms, \
+    load_markdown_template, Decisions, Downloader, MANAGED_SECTION_BEGIN, MANAGED_SECTION_END, PDF_APPENDIX_SECTION
+from ncli.utils import check_choice, current_datetime, echo_warning, ensure_free_space, extract_date, format_date, \
+    parse_date, sanitize_file_name, toml_dumps_with_newline
 
 EXPORT_INDEX_FILE_NAME: str = "index.toml"
+GOALS_STATE_FILE_NAME: str = "goals.toml"
+GOALS_NOTE_FILE_NAME: str = "Goals.md"
+
+GOAL_PERIODS = ("month", "year")
+
+# Number of past periods (including the current one) shown for each goal in the goals note.
+GOAL_HISTORY = {"month": 12, "year": 5}
+
+# Width of the progress bars in the goals note.
+GOAL_BAR_WIDTH: int = 10
+
+
+class Goal(BaseModel):
+    """
+    Represents a listening goal, e.g., finishing 2 books per month.
+    """
+    books: int
+    # One of the `GOAL_PERIODS`.
+    period: str = 'month'
+
+    @validator('period')
+    def check_period(cls, value):  # pylint: disable=no-self-argument
+        """
+        Checks that the period is one of the `GOAL_PERIODS`.
+        """
+        return check_choice('goal period', value, GOAL_PERIODS)
+
+
+class Config(BaseModel):
+    """
+    Config for Audible operations.
+    """
+    # Whether to include Audible in aggregate commands (e.g., `ncli export --all`).
+    enabled: bool = True
+
+    # Regex patterns to detect titles of multi-part audiobooks (e.g., "Title, Part 1"), which are exported as a
+    # single book. Each pattern must have the named groups `title` (the shared title) and `part` (the part number).
+    part_title_patterns: List[str] = [
+        r'^(?P<title>.+?),? (?:Part|Pt\.?) (?P<part>\d+)(?: of \d+)?$',
+        r'^(?P<title>.+?) \((?:Part|Pt\.?) (?P<part>\d+)(?: of \d+)?\)$',
+    ]
+
+    # Whether to subtract the duration of the brand intro (e.g., "This is Audible") from the clip positions. Clips
+    # recorded by the app include the intro, which makes them drift by a few seconds from the chapter timestamps.
+    correct_brand_intro: bool = False
+
+    # Precision of the clip positions and chapter timestamps, i.e., "seconds" (H:MM:SS), "frames" (H:MM:SS:FF), or
+    # "ms" (H:MM:SS.mmm). See `DURATION_PRECISIONS` in `utils`.
+    duration_precision: str = 'seconds'
+
+    # How to render the annotations in Markdown. See `ANNOTATION_STYLES` in `kit_amazon`.
+    annotation_style: str = 'default'
+
+    # How to name the file of a book whose title collides with another book (e.g., same title by another author). See
+    # `FILE_NAME_COLLISION_POLICIES` in `kit_amazon`.
+    file_name_collision: str = 'asin'
+
+    # Whether to download the cover image of each exported book into the `covers` directory of the target, which is
+    # then referenced in the exported note instead of relying on the image URL only.
+    download_covers: bool = False
+
+    # Whether to wrap the generated sections of the Markdown notes with markers, so that re-exporting only
+    # regenerates them while keeping the text around them (e.g., your own commentary). See `merge_managed_sections`
+    # in `kit_amazon`.
+    managed_sections: bool = False
+
+    # Path to a Jinja2 template for the Markdown notes (or its name in `TEMPLATES_DIR` of `kit_amazon`), or empty to
+    # use the built-in layout. The template variables are the same as for Kindle, with the chapters in addition.
+    template: str = ''
+
+    # Whether to extract the outline and text of the companion PDF (if any) into an appendix of the Markdown note,
+    # so that the supplementary material is searchable alongside the clips. This requires the `pypdf` package.
+    pdf_appendix: bool = False
+
+    # Listening goals to be tracked in a `Goals.md` note in the export directory, which is updated after each export.
+    goals: List[Goal] = []
+
+    @validator('annotation_style')
+    def check_annotation_style(cls, value):  # pylint: disable=no-self-argument
+        """
+        Checks that the annotation style is one of the `ANNOTATION_STYLES`.
+        """
+        return check_choice('annotation style', value, ANNOTATION_STYLES)
+
+    @validator('file_name_collision')
+    def check_file_name_collision(cls, value):  # pylint: disable=no-self-argument
+        """
+        Checks that the file name collision policy is one of the `FILE_NAME_COLLISION_POLICIES`.
+        """
+        return check_choice('file name collision policy', value, FILE_NAME_COLLISION_POLICIES)
 
 
 class Client:
@@ -72,8 +175,8 @@ class Client:
             purchase_date = format_iso_date(item.get('purchase_date', None))
 
             # Due to the design of Audible API, we need to make a separate call here to fetch the
-            # last opened time.
-            params = {'response_groups': 'last_position_heard'}
+            # last opened time (and the content version).
+            params = {'response_groups': 'last_position_heard,content_reference'}
             metadata = self.client.get(
                 f'content/{asin}/metadata', params=params)
 
@@ -84,6 +187,12 @@ class Client:
                 last_opened_date = format_date(
                     metadata['content_metadata']['last_position_heard']['last_updated'])
 
+            # Note that the content reference may be missing for some items (e.g., not downloadable).
+            content_version = None
+            content_reference = metadata['content_metadata'].get('content_reference')
+            if content_reference and content_reference.get('acr'):
+                content_version = f"{content_reference['acr']}/{content_reference.get('version', '')}"
+
             res.append(Book(
                 asin=asin,
                 title=item['title'],
@@ -94,13 +203,23 @@ class Client:
                 publication_date=publication_date,
                 purchase_date=purchase_date,
                 last_opened_date=last_opened_date,
+                content_version=content_version,
             ))
 
         return res
 
-    def get_chapters(self, book: Book) -> List[Chapter]:
+    def get_finished_asins(self) -> List[str]:
         """
-        Fetches the list of chapters for a particular book.
+        Fetches the ASINs of the books in the library that have been marked as finished.
+        """
+        params = {'response_groups': 'is_finished', 'num_results': 1000}
+        data = self.client.get('library', params=params)
+        return [item['asin'] for item in data['items'] if item.get('is_finished')]
+
+    def get_chapters(self, book: Book) -> tuple[int, List[Chapter]]:
+        """
+        Fetches the list of chapters for a particular book, together with the duration of its brand intro (in
+        milliseconds), which is 0 if unknown.
         """
         params = {'response_groups': 'chapter_info'}
         metadata = self.client.get(
@@ -122,7 +241,8 @@ class Client:
 
             return res
 
-        return parse_chapters(metadata['content_metadata']['chapter_info']['chapters'])
+        chapter_info = metadata['content_metadata']['chapter_info']
+        return chapter_info.get('brandIntroDurationMs', 0), parse_chapters(chapter_info['chapters'])
 
     def get_annotations(self, book: Book) -> tuple[str, List[Annotation]]:
         """
@@ -143,16 +263,15 @@ class Client:
         annotations_version: str = response['md5']
         annotations: List[Annotation] = []
 
-        clip_records = []
-        note_records = []
-        for record in response['payload'].get('records', []):
-            if record['type'] == 'audible.clip':
-                clip_records.append(record)
-            elif record['type'] == 'audible.note':
-                note_records.append(record)
+        # Some long audiobooks have a huge number of records. Hence, we iterate over the records lazily
+        # (once per record type) instead of keeping separate copies of the records for each type.
+        records: List[dict] = response['payload'].get('records', [])
+
+        def records_of_type(record_type: str):
+            return (record for record in records if record['type'] == record_type)
 
         # Note records are our priority, since some notes somehow only have note but not clip.
-        for record in note_records:
+        for record in records_of_type('audible.note'):
             note = record['text']
             created_at = format_date(record['creationTime'])
             updated_at = format_date(record['lastModificationTime'])
@@ -171,7 +290,7 @@ class Client:
 
         # Add clips. But if there's a note with the same start time, created time, updated time, and text,
         # we will just update it.
-        for record in clip_records:
+        for record in records_of_type('audible.clip'):
             note = None
             if 'metadata' in record and 'note' in record['metadata']:
                 note = record['metadata']['note']
@@ -208,60 +327,486 @@ class Client:
 
         # Sort the annotations based on the clip start time to make it easier to read.
         # Somehow the data fetched here are not sorted by their clip time.
-        sorted_annotations = sorted(
-            annotations, key=lambda annotation: annotation.clip_start_ms)
+        #
+        # Sorting is done in place to avoid creating another copy of the list.
+        annotations.sort(key=lambda annotation: annotation.clip_start_ms)
+
+        return annotations_version, annotations
+
+
+def correct_brand_intro(annotations: List[Annotation], brand_intro_ms: int) -> List[Annotation]:
+    """
+    Shifts the clip positions back by the duration of the brand intro, so that they match the chapter timestamps.
+    Positions within the intro are clamped to the beginning of the book.
+    """
+    if brand_intro_ms <= 0:
+        return annotations
+    return [
+        annotation.copy(update={
+            'clip_start_ms': max(0, annotation.clip_start_ms - brand_intro_ms),
+            'clip_end_ms': max(0, annotation.clip_end_ms - brand_intro_ms),
+        })
+        if annotation.clip_start_ms is not None else annotation
+        for annotation in annotations
+    ]
+
+
+# Maximum number of characters of the text extracted from each companion PDF, to keep the notes reasonably sized.
+PDF_APPENDIX_MAX_CHARS: int = 50_000
+
 
-        return annotations_version, sorted_annotations
+def import_pypdf():
+    """
+    Imports the optional `pypdf` package, which is needed for `pdf_appendix`.
+    """
+    try:
+        import pypdf  # pylint: disable=import-outside-toplevel
+    except ImportError as e:
+        raise ConfigError('the pypdf package is required for audible.pdf_appendix, please install it first '
+                          '(e.g., `pip install pypdf`)') from e
+    return pypdf
+
+
+def extract_pdf_appendix(pdf_path: Path, max_chars: int = PDF_APPENDIX_MAX_CHARS) -> str:
+    """
+    Extracts the outline (i.e., the bookmarks with their page numbers) and the text of each page of the given PDF as
+    Markdown, with the text truncated to `max_chars`.
+    """
+    reader = import_pypdf().PdfReader(pdf_path)
+
+    lines = [f'### {pdf_path.name}', '']
+
+    def add_outline(items: list, depth: int) -> None:
+        # Nested lists are the children of the preceding item.
+        for item in items:
+            if isinstance(item, list):
+                add_outline(item, depth + 1)
+                continue
+            page = reader.get_destination_page_number(item)
+            suffix = f' (page {page + 1})' if page is not None and page >= 0 else ''
+            lines.append(f"{'  ' * depth}- {item.title}{suffix}")
+
+    if reader.outline:
+        lines.extend(['#### Outline', ''])
+        add_outline(reader.outline, 0)
+        lines.append('')
+
+    remaining = max_chars
+    for i, page in enumerate(reader.pages, start=1):
+        text = (page.extract_text() or '').strip()
+        if not text:
+            continue
+        if remaining <= 0:
+            lines.extend([f'*The remaining pages are truncated after {max_chars} characters.*', ''])
+            break
+
+        # Lines that look like headings, quotes, separators, or the section markers would break the structure of the
+        # note (and how its annotations are parsed).
+        text = re.sub(r'^(#|>|<!--|---$)', r'\\\1', text[:remaining], flags=re.MULTILINE)
+        remaining -= len(text)
+        lines.extend([f'#### Page {i}', '', text, ''])
+
+    return '\n'.join(lines).rstrip('\n') + '\n'
+
+
+def write_pdf_appendix(note_path: Path, appendices: List[str]) -> None:
+    """
+    Writes the given PDF appendices into a section at the end of the note, which replaces the one from the previous
+    export (if any).
+    """
+    with open(note_path, 'r', encoding='utf-8') as f:
+        content = f.read()
+
+    begin = MANAGED_SECTION_BEGIN.format(name=PDF_APPENDIX_SECTION)
+    end = MANAGED_SECTION_END.format(name=PDF_APPENDIX_SECTION)
+    start = content.find(begin)
+    if start >= 0 and (stop := content.find(end, start)) >= 0:
+        before, after = content[:start].rstrip('\n'), content[stop + len(end):].lstrip('\n')
+        content = before + '\n\n' + after if after else before
+
+    section = begin + '## Companion PDF\n\n' + '\n'.join(appendices) + end
+    content = content.rstrip('\n') + '\n\n' + section
+    with open(note_path, 'w', encoding='utf-8') as f:
+        f.write(content)
+
+
+def hash_chapters(chapters: List[Chapter]) -> str:
+    """
+    Returns a hash of the chapters, which is used to detect changes between runs.
+    """
+    data = json.dumps([chapter.dict() for chapter in chapters], sort_keys=True)
+    return hashlib.md5(data.encode('utf-8')).hexdigest()
+
+
+def check_content_version(book: Book, indexed_book: Optional[Book]) -> Optional[str]:
+    """
+    Returns a warning if the audio content of the book has changed since it was indexed (e.g., re-encoded), in
+    which case the clip positions may not be aligned with the audio anymore.
+    """
+    if indexed_book is None or not indexed_book.content_version or not book.content_version:
+        return None
+    if indexed_book.content_version == book.content_version:
+        return None
+    return (f'audio content has changed ({indexed_book.content_version} -> {book.content_version}), '
+            'clip timestamps may be misaligned')
+
+
+class FinishedBook(BaseModel):
+    """
+    Represents a book that has been finished, for tracking the listening goals.
+    """
+    title: str
+    # Date when the book was finished (i.e., the last heard date when it was first seen as finished), as "YYYY-MM-DD".
+    finished_date: str
+
+
+class GoalsState(BaseModel):
+    """
+    Represents the books finished across runs, keyed by the book ASIN.
+    """
+    finished: Dict[str, FinishedBook] = {}
+
+    @staticmethod
+    def load_or_default(path: Path):
+        """
+        Load the state or default to an empty state if there is no such file.
+        """
+        if not os.path.exists(path):
+            return GoalsState()
+
+        with open(path, "r", encoding='utf-8') as file:
+            state_str = file.read()
+        return GoalsState(**toml.loads(state_str))
+
+    def save(self, path: Path):
+        """
+        Save the state into the specified path.
+        """
+        with open(path, "w", encoding='utf-8') as file:
+            file.write(toml_dumps_with_newline(self.dict()))
+
+    def update(self, books: List[Book], finished_asins: List[str], now: datetime) -> None:
+        """
+        Records the books that are newly seen as finished.
+
+        Since the library only tells whether a book is finished, the finish date is approximated by the last heard
+        date of the book (or the current date if it is unknown). Books that are no longer finished (e.g., because
+        they are restarted) keep their record, so that the past progress does not change.
+        """
+        for book in books:
+            if book.asin not in finished_asins or book.asin in self.finished:
+                continue
+            last_heard = parse_date(book.last_opened_date) if book.last_opened_date else None
+            # Note that this is kept in ISO format regardless of the date format, since the goal periods match on it.
+            finished_date = (last_heard or now).astimezone(now.tzinfo).strftime('%Y-%m-%d')
+            self.finished[book.asin] = FinishedBook(title=book.title, finished_date=finished_date)
+
+
+def _goal_period_keys(period: str, now: datetime) -> List[str]:
+    """
+    Returns the keys of the periods shown for a goal (most recent first), e.g., "2023-05" for months.
+    """
+    if period == 'year':
+        return [str(now.year - i) for i in range(GOAL_HISTORY['year'])]
+
+    keys = []
+    year, month = now.year, now.month
+    for _ in range(GOAL_HISTORY['month']):
+        keys.append(f'{year:04d}-{month:02d}')
+        year, month = (year, month - 1) if month > 1 else (year - 1, 12)
+    return keys
+
+
+def format_goal_progress(done: int, target: int) -> str:
+    """
+    Formats the progress towards a goal as a text progress bar, e.g., "[#####-----] 1/2".
+    """
+    filled = min(done * GOAL_BAR_WIDTH // target, GOAL_BAR_WIDTH) if target > 0 else GOAL_BAR_WIDTH
+    bar = '#' * filled + '-' * (GOAL_BAR_WIDTH - filled)
+    return f'[{bar}] {done}/{target}'
+
+
+def write_goals_note(path: Path, goals: List[Goal], state: GoalsState, now: datetime) -> None:
+    """
+    Writes the progress of each goal in the recent periods (along with the books finished in each of them).
+    """
+    lines = ['# Listening goals', '']
+    for goal in goals:
+        lines.append(f'## {goal.books} book(s) per {goal.period}')
+        lines.append('')
+        for key in _goal_period_keys(goal.period, now):
+            finished = sorted(
+                (book for book in state.finished.values() if book.finished_date.startswith(key)),
+                key=lambda book: book.finished_date,
+            )
+            lines.append(f'- {key}: `{format_goal_progress(len(finished), goal.books)}`')
+            for book in finished:
+                lines.append(f'  - {book.title} ({book.finished_date})')
+        lines.append('')
+
+    with open(path, 'w', encoding='utf-8') as f:
+        f.write('\n'.join(lines))
+
+
+def group_book_parts(books: List[Book], patterns: List[str]) -> List[tuple[str, List[Book]]]:
+    """
+    Groups the parts of multi-part audiobooks, based on their titles and authors.
+
+    Returns:
+        list[tuple[str, list[Book]]]: Pairs of the (shared) title and the books in the group, ordered by their
+        part number. Books that are not part of a multi-part audiobook are returned as a group of their own.
+    """
+    compiled_patterns = [re.compile(pattern) for pattern in patterns]
+
+    groups: List[tuple[str, List[tuple[int, Book]]]] = []
+    groups_by_key: dict[tuple[str, str], List[tuple[int, Book]]] = {}
+
+    for book in books:
+        match = next((m for m in (p.match(book.title) for p in compiled_patterns) if m), None)
+        if match is None:
+            groups.append((book.title, [(0, book)]))
+            continue
+
+        title = match.group('title').strip()
+        key = (title, book.author)
+        if key not in groups_by_key:
+            groups_by_key[key] = []
+            groups.append((title, groups_by_key[key]))
+        groups_by_key[key].append((int(match.group('part')), book))
+
+    return [(title, [book for _, book in sorted(parts, key=lambda part: part[0])]) for title, parts in groups]
+
+
+def _merge_parts(
+    title: str,
+    parts: List[tuple[Book, List[Chapter], List[Annotation]]],
+) -> tuple[Book, List[Chapter], List[Annotation]]:
+    """
+    Merges the data of a multi-part audiobook into a single book.
+
+    Each part becomes a top-level chapter. Positions of the chapters and annotations in the subsequent parts are
+    offset by the total length of the previous parts, so that they are relative to the beginning of the first part.
+    """
+    def shift_chapters(chapters: Optional[List[Chapter]], offset_ms: int) -> Optional[List[Chapter]]:
+        if chapters is None:
+            return None
+        return [
+            chapter.copy(update={
+                'start_ms': chapter.start_ms + offset_ms if chapter.start_ms is not None else None,
+                'end_ms': chapter.end_ms + offset_ms if chapter.end_ms is not None else None,
+                'subchapters': shift_chapters(chapter.subchapters, offset_ms),
+            })
+            for chapter in chapters
+        ]
+
+    merged_chapters: List[Chapter] = []
+    merged_annotations: List[Annotation] = []
+    offset_ms = 0
+
+    for i, (_, chapters, annotations) in enumerate(parts):
+        length_ms = get_runtime_ms(chapters) or 0
+        merged_chapters.append(Chapter(
+            title=f'Part {i + 1}',
+            start_ms=offset_ms,
+            end_ms=offset_ms + length_ms,
+            subchapters=shift_chapters(chapters, offset_ms),
+        ))
+        for annotation in annotations:
+            merged_annotations.append(annotation.copy(update={
+                'clip_start_ms': annotation.clip_start_ms + offset_ms,
+                'clip_end_ms': annotation.clip_end_ms + offset_ms,
+            }))
+        offset_ms += length_ms
+
+    # Note that the metadata (e.g., ASIN) of the first part is used for the merged book.
+    book = parts[0][0].copy(update={'title': title})
+    return book, merged_chapters, merged_annotations
 
 
 def export(
-    config: Config,
+    config: AmazonConfig,
     target: Path,
     renew: bool,
-):
+    audible_config: Optional[Config] = None,
+    output_format: str = 'markdown',
+    frontmatter: Optional[FrontmatterConfig] = None,
+    verify_free_space: bool = False,
+    unattended: bool = False,
+) -> RunSummary:
     """
     Exports Audible data
+
+    If `verify_free_space` is set, the export fails early if the target does not have enough free space for all books
+    in the library, including their companion PDFs (see `estimate_export_size`).
+
+    If `unattended` is set, nothing is prompted for (e.g., for scheduled runs): the modified and new books are fetched,
+    and colliding file names get the ASIN if the policy is to prompt.
+
+    Returns:
+        RunSummary: The outcome for each book, which is also saved in the target directory.
     """
+    audible_config = audible_config if audible_config is not None else Config()
+    decisions = Decisions(default='fetch') if unattended else None
+    collision_policy = audible_config.file_name_collision
+    if unattended and collision_policy == 'prompt':
+        collision_policy = 'asin'
+    template = None
+    if audible_config.template:
+        template = load_markdown_template(Path(audible_config.template).expanduser())
+    pdf_appendix = audible_config.pdf_appendix and output_format == 'markdown'
+    if pdf_appendix:
+        # Fail early if the package is missing, instead of after fetching the books.
+        import_pypdf()
+
     auth = load_authenticator(config)
     audible_client = audible.Client(auth)
     client = Client(audible_client)
 
     book_library = client.get_books()
+    if verify_free_space:
+        ensure_free_space({target: estimate_export_size(book_library, audible_config.download_covers)})
 
     index_file_path = target.joinpath(EXPORT_INDEX_FILE_NAME)
     export_index = ExportIndex.load_or_default(index_file_path)
 
-    for book in book_library:
-        if export_index.check_book(book, skip_check=renew):
-            chapters = client.get_chapters(book)
-            annotation_version, annotations = client.get_annotations(book)
-
-            # Note that we will generate the book name using its title and use the ".md" extension since it is
-            # a Markdown file.
-            book_path = target.joinpath(f"{book.title}.md")
-
-            export_to_markdown(
-                book_path,
-                book,
-                chapters=chapters,
-                annotations=annotations,
-                annotations_version=annotation_version,
+    # Books are only grouped into parts by the same author, so different books may still share the title.
+    groups = group_book_parts(book_library, audible_config.part_title_patterns)
+    title_counts = Counter(sanitize_file_name(title).casefold() for title, _ in groups)
+
+    summary = RunSummary()
+    for title, books in groups:
+        # Keep snapshots of the indexed items, so that they can be reverted if the export fails.
+        snapshots = {}
+        warnings = {}
+        for book in books:
+            snapshot = export_index.get_item(book.asin)
+            snapshots[book.asin] = snapshot.copy(deep=True) if snapshot is not None else None
+
+            # Books indexed before the content version was tracked get it recorded silently, so that they are not
+            # considered modified only because of the new field.
+            if snapshot is not None and snapshot.info.content_version is None:
+                export_index.get_item(book.asin).info.content_version = book.content_version
+
+            warning = check_content_version(book, snapshot.info if snapshot is not None else None)
+            if warning is not None:
+                echo_warning(f'Book {book.title} ({book.asin}): {warning}')
+                warnings[book.asin] = warning
+
+        # Note that each part is still tracked separately in the index. Hence, all of them need to be checked
+        # (without short-circuiting), and the merged book is exported if any of the parts needs to be fetched.
+        checks = [export_index.check_book(book, skip_check=renew, decisions=decisions) for book in books]
+        if not any(checks):
+            for book in books:
+                summary.add(book, 'skipped', warning=warnings.get(book.asin))
+            continue
+
+        # Note that the duration is measured for the entire (merged) book, which is then recorded for each part.
+        start_time = time.monotonic()
+        try:
+            parts = []
+            annotation_versions = []
+            for book in books:
+                brand_intro_ms, chapters = client.get_chapters(book)
+                annotation_version, annotations = client.get_annotations(book)
+                if audible_config.correct_brand_intro:
+                    annotations = correct_brand_intro(annotations, brand_intro_ms)
+                parts.append((book, chapters, annotations))
+                annotation_versions.append(annotation_version)
+
+            # The book is typically only re-fetched because it has been listened to, which only changes its metadata
+            # (e.g., the last heard date). Hence, if the annotations and chapters of all parts are unchanged, only the
+            # note is rewritten, while the companion PDFs are not downloaded again.
+            versions = {
+                book.asin: (annotation_version, hash_chapters(chapters))
+                for (book, chapters, _), annotation_version in zip(parts, annotation_versions)
+            }
+
+            # Note that the file name is recorded for the first part, whose ASIN is used for the merged book. Older
+            # index entries do not record the file name yet, which is then the title as is (if not ambiguous).
+            ambiguous = title_counts[sanitize_file_name(title).casefold()] > 1
+            item = export_index.get_item(books[0].asin)
+            if item is not None and not ambiguous and item.file_name is None and \
+                    target.joinpath(f'{title}.{OUTPUT_FORMATS[output_format]}').exists():
+                item.file_name = title
+            file_name = export_index.resolve_file_name(
+                books[0].copy(update={'title': title}), ambiguous=ambiguous,
+                collision_policy=collision_policy)
+            output_file = target.joinpath(f'{file_name}.{OUTPUT_FORMATS[output_format]}')
+            unchanged = not renew and output_file.exists() and all(
+                snapshots[book.asin] is not None and versions[book.asin] == (
+                    snapshots[book.asin].annotations_version, snapshots[book.asin].chapters_hash)
+                for book in books)
+
+            if len(parts) == 1:
+                merged_book, merged_chapters, merged_annotations = parts[0]
+            else:
+                merged_book, merged_chapters, merged_annotations = _merge_parts(title, parts)
+
+            if audible_config.download_covers:
+                cover_path = download_cover(merged_book, target)
+                if cover_path is not None:
+                    merged_book = merged_book.copy(update={'cover_path': cover_path})
+
+            # The file extension depends on the output format.
+            note_path = export_book(
+                target,
+                file_name,
+                output_format,
+                merged_book,
+                chapters=merged_chapters,
+                annotations=merged_annotations,
+                annotations_version=', '.join(version for version in annotation_versions if version),
+                frontmatter=frontmatter,
+                annotation_style=audible_config.annotation_style,
+                managed_sections=audible_config.managed_sections,
+                template=template,
             )
 
-            if book.pdf_url:
-                # For some reason, we can't use the recorded pdf url to download,
-                # since it would give 403 error.
-                domain = audible_client.auth.locale.domain
-                pdf_url = f'https://www.audible.{domain}/companion-file/{book.asin}'
-
-                pdf_path = target.joinpath(f'{book.title}.pdf')
-
-                downloader = Downloader(
-                    # Note: we will always overwrite existing file
-                    pdf_url, pdf_path, audible_client.session, True,
-                    ["application/octet-stream", "application/pdf"]
-                )
-                downloader.run()
+            pdf_paths = []
+            for book in books:
+                if book.pdf_url:
+                    # For some reason, we can't use the recorded pdf url to download,
+                    # since it would give 403 error.
+                    domain = audible_client.auth.locale.domain
+                    pdf_url = f'https://www.audible.{domain}/companion-file/{book.asin}'
+
+                    pdf_path = target.joinpath(f'{book.title}.pdf')
+
+                    if not (unchanged and pdf_path.is_file()):
+                        downloader = Downloader(
+                            # Note: we will always overwrite existing file
+                            pdf_url, pdf_path, audible_client.session, True,
+                            ["application/octet-stream", "application/pdf"]
+                        )
+                        downloader.run()
+                    # A previously downloaded file is still used if the download fails.
+                    if pdf_path.is_file():
+                        pdf_paths.append(pdf_path)
+
+            if pdf_appendix and pdf_paths:
+                try:
+                    write_pdf_appendix(note_path, [extract_pdf_appendix(pdf_path) for pdf_path in pdf_paths])
+                except Exception as e:  # pylint: disable=broad-except
+                    # The note itself is still complete without the appendix.
+                    echo_warning(f'Failed to extract the companion PDF of book {title}. Error: {e}')
+        except Abort:
+            # The user has aborted the run (e.g., at the file name prompt).
+            raise
+        except Exception as e:  # pylint: disable=broad-except
+            # Continue with the remaining books, so that a single failure does not abort the entire run.
+            echo_warning(f'Failed to export book {title}. Error: {e}')
+            for book in books:
+                export_index.revert_item(book.asin, snapshots[book.asin])
+                summary.add(book, 'failed', duration_s=time.monotonic() - start_time, error=str(e),
+                            warning=warnings.get(book.asin))
+            continue
+
+        duration_s = time.monotonic() - start_time
+        for book, _, annotations in parts:
+            item = export_index.get_item(book.asin)
+            item.annotations_version, item.chapters_hash = versions[book.asin]
+            summary.add(book, 'unchanged' if unchanged else 'exported', len(annotations), duration_s,
+                        warning=warnings.get(book.asin))
 
             # Print some info if all books are expected to be exported.
             if renew:
@@ -271,8 +816,25 @@ def export(
     if not renew:
         export_index.warn_unchecked_books()
 
+    if audible_config.goals:
+        try:
+            goals_state_path = target.joinpath(GOALS_STATE_FILE_NAME)
+            goals_state = GoalsState.load_or_default(goals_state_path)
+            now = current_datetime()
+            goals_state.update(book_library, client.get_finished_asins(), now)
+            goals_state.save(goals_state_path)
+            write_goals_note(target.joinpath(GOALS_NOTE_FILE_NAME), audible_config.goals, goals_state, now)
+        except Exception as e:  # pylint: disable=broad-except
+            # The goals are secondary, so failing to update them should not fail the export.
+            echo_warning(f'Failed to update the listening goals. Error: {e}')
+
     # Save back the index
     export_index.save(index_file_path)
 
+    summary.save(target.joinpath(RUN_SUMMARY_FILE_NAME))
+    summary.echo()
+
     # Close after completing the export
     client.close()
+
+    return summary
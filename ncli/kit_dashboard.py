@@ -0,0 +1,127 @@
+# This specific file is part of the 'ncli' project and it is licensed under the AGPL-3.0 License.
+# Please see the accompanying LICENSE-AGPL-3.0 file for more details.
+
+"""
+A module for generating a dashboard note that summarizes the exported data across sources.
+"""
+
+import os
+from datetime import datetime, timedelta
+from pathlib import Path
+from typing import List, Optional
+
+from click import echo
+
+from ncli.kit_amazon import ExportIndex, ExportItem, count_markdown_annotations
+from ncli.utils import current_datetime, format_stored_date, parse_date
+
+DASHBOARD_FILE_NAME: str = "Dashboard.md"
+EXPORT_INDEX_FILE_NAME: str = "index.toml"
+
+# Number of books to be listed in each section.
+MAX_BOOKS_PER_SECTION: int = 5
+
+# Time window for the "recent" sections.
+RECENT_WINDOW: timedelta = timedelta(days=30)
+
+
+class Source:
+    """
+    An exported source (e.g., Kindle or Audible) to be summarized in the dashboard.
+    """
+
+    def __init__(self, name: str, export_dir: Path):
+        self.name = name
+        self.export_dir = export_dir
+        self.index = ExportIndex.load_or_default(export_dir.joinpath(EXPORT_INDEX_FILE_NAME))
+
+    def book_path(self, item: ExportItem) -> Path:
+        """
+        Returns the path to the exported markdown file for the given item.
+        """
+        file_name = item.file_name if item.file_name else item.info.title
+        return self.export_dir.joinpath(f"{file_name}.md")
+
+
+def _sort_by_date(items: List[ExportItem], get_date) -> List[ExportItem]:
+    """
+    Sorts items by the given date (most recent first). Items with unknown dates are excluded.
+    """
+    dated = [(get_date(item), item) for item in items]
+    dated = [(date, item) for date, item in dated if date is not None]
+    dated.sort(key=lambda pair: pair[0], reverse=True)
+    return [item for _, item in dated]
+
+
+def _last_opened(item: ExportItem) -> Optional[datetime]:
+    return parse_date(item.info.last_opened_date) if item.info.last_opened_date else None
+
+
+def _last_updated(item: ExportItem) -> Optional[datetime]:
+    return parse_date(item.last_updated_time)
+
+
+def _book_link(source: Source, item: ExportItem, dashboard_dir: Path) -> str:
+    relative_path = Path(os.path.relpath(source.book_path(item), dashboard_dir))
+    return f"[{item.info.title}]({relative_path.as_posix().replace(' ', '%20')})"
+
+
+def generate(target: Path, sources: List[Source]) -> Path:
+    """
+    Generates the dashboard note in the target directory.
+
+    Args:
+        target (Path): The directory where the dashboard note should be written.
+        sources (List[Source]): The exported sources to be summarized.
+
+    Returns:
+        Path: The path to the generated dashboard note.
+    """
+    now = current_datetime()
+    dashboard_path = target.joinpath(DASHBOARD_FILE_NAME)
+
+    # Note that we don't include the generation time, to avoid producing changes when nothing else changed.
+    lines = ['# Dashboard', '']
+
+    for source in sources:
+        lines.append(f'## {source.name}')
+        lines.append('')
+
+        if not source.index.books:
+            lines.append('No exported books.')
+            lines.append('')
+            continue
+
+        # Currently reading/listening is based on the last opened date on the platform.
+        lines.append('**Last opened:**')
+        lines.append('')
+        for item in _sort_by_date(source.index.books, _last_opened)[:MAX_BOOKS_PER_SECTION]:
+            lines.append(f'- {_book_link(source, item, target)} ({format_stored_date(item.info.last_opened_date)})')
+        lines.append('')
+
+        # Recently highlighted books are approximated by the books exported recently, since a book is only
+        # (re-)exported if it has been opened again.
+        recent_items = [
+            item for item in _sort_by_date(source.index.books, _last_updated)
+            if now - _last_updated(item) <= RECENT_WINDOW
+        ]
+        lines.append(f'**Exported in the past {RECENT_WINDOW.days} days:**')
+        lines.append('')
+        total_annotations = 0
+        for i, item in enumerate(recent_items):
+            count = count_markdown_annotations(source.book_path(item))
+            total_annotations += count
+            if i < MAX_BOOKS_PER_SECTION:
+                lines.append(f'- {_book_link(source, item, target)} ({count} annotations)')
+        if not recent_items:
+            lines.append('- None')
+        lines.append('')
+        lines.append(f'Total annotations in these books: {total_annotations}')
+        lines.append('')
+
+    os.makedirs(target, exist_ok=True)
+    with open(dashboard_path, 'w', encoding='utf-8') as f:
+        f.write('\n'.join(lines))
+
+    echo(f"Dashboard has been written to '{dashboard_path}'")
+    return dashboard_path
@@ -0,0 +1,412 @@
+# This specific file is part of the 'ncli' project and it is licensed under the AGPL-3.0 License.
+# Please see the accompanying LICENSE-AGPL-3.0 file for more details.
+
+"""
+A module for importing highlights exported by other tools, so that they can be managed by ncli.
+
+The following Markdown layouts are supported:
+
+- readwise: notes written by the Readwise Obsidian plugin (with its default template), i.e., a "# Title" heading,
+  a "## Metadata" list (e.g., "- Author: [[Name]]"), and a "## Highlights" list where each item ends with its
+  location, e.g., "- Some highlight ([Location 123](https://readwise.io/to_kindle?...&asin=...&location=123))".
+  Notes are written as nested "- Note: ..." items.
+- bookcision: text exported by Bookcision, i.e., the title and the author on the first lines, followed by
+  blocks that start with a header like "Highlight (yellow) - Page 12 · Location 123" or "Note - Location 123".
+
+Besides, the "My Clippings.txt" file of Kindle devices can be imported (see `parse_clippings`).
+"""
+
+import re
+from datetime import datetime
+from pathlib import Path
+from typing import Dict, List, Optional, Tuple
+
+from click import echo
+
+from ncli.errors import ParseError
+from ncli.kit_amazon import FrontmatterConfig, Book, Annotation, ExportIndex, ExportItem, OUTPUT_FORMATS, export_book
+from ncli.utils import current_timestamp, echo_warning, format_timestamp, sanitize_file_name
+
+EXPORT_INDEX_FILE_NAME: str = "index.toml"
+
+MARKDOWN_LAYOUTS = ("auto", "readwise", "bookcision")
+
+READWISE_COVER_RE = re.compile(r'^!\[rw-book-cover\]\(([^()\s]+)\)$')
+READWISE_METADATA_RE = re.compile(r'^- ([A-Za-z ]+): (.*)$')
+# Location at the end of a highlight, with or without a link, e.g., "([Location 123](https://...))" or "(Page 12)".
+READWISE_LOCATION_RE = re.compile(r'\s*\(\[?(Location|Page) (\d+)\]?(?:\(([^()\s]+)\))?\)$')
+READWISE_ASIN_RE = re.compile(r'[?&]asin=([A-Z0-9]{10})\b')
+
+CLIPPINGS_SEPARATOR = '=========='
+# Title line of a clipping, e.g., "Title (Author)". Note that the title itself may contain parentheses.
+CLIPPINGS_TITLE_RE = re.compile(r'^(?P<title>.*?)(?: \((?P<author>[^()]*)\))?$')
+# Metadata line of a clipping, e.g., "- Your Highlight on page 12 | Location 123-125 | Added on Monday, ...". Older
+# devices use a shorter format, e.g., "- Highlight Loc. 123-25 | Added on ...".
+CLIPPINGS_METADATA_RE = re.compile(r'^- (?:Your )?(?P<type>Highlight|Note|Bookmark)\b(?P<position>.*?)'
+                                   r'(?:\| Added on (?P<added>.+))?$', re.IGNORECASE)
+CLIPPINGS_PAGE_RE = re.compile(r'\bpage (\d+)', re.IGNORECASE)
+CLIPPINGS_LOCATION_RE = re.compile(r'\b(?:Location|Loc\.) (\d+)(?:-(\d+))?', re.IGNORECASE)
+CLIPPINGS_ADDED_FORMAT = '%A, %B %d, %Y %I:%M:%S %p'
+
+BOOKCISION_HEADER_RE = re.compile(r'^(?:Highlight \((?P<color>[^)]+)\)|(?P<note>Note))(?: - (?P<position>.+))?$')
+BOOKCISION_PAGE_RE = re.compile(r'Page (\d+)')
+BOOKCISION_LOCATION_RE = re.compile(r'Location (\d+)')
+
+
+def detect_layout(content: str) -> Optional[str]:
+    """
+    Detects the layout of the given Markdown content, or returns None if it is not supported.
+    """
+    if re.search(r'^## Highlights', content, re.MULTILINE) and (
+            re.search(r'^## Metadata', content, re.MULTILINE) or 'rw-book-cover' in content):
+        return 'readwise'
+    if any(BOOKCISION_HEADER_RE.match(_strip_markup(line)) for line in content.splitlines()):
+        return 'bookcision'
+    return None
+
+
+def parse_readwise(content: str) -> Tuple[Book, List[Annotation]]:
+    """
+    Parses a note written by the Readwise Obsidian plugin.
+    """
+    book = Book()
+    annotations: List[Annotation] = []
+
+    section = None
+    for line in content.splitlines():
+        stripped = line.strip()
+        if line.startswith('# ') and not book.title:
+            book.title = line[2:].strip()
+            continue
+        if line.startswith('## '):
+            section = 'highlights' if 'highlights' in line.lower() else line[3:].strip().lower()
+            continue
+        if not stripped:
+            continue
+
+        if match := READWISE_COVER_RE.match(stripped):
+            book.image_url = match.group(1)
+        elif section == 'metadata' and (match := READWISE_METADATA_RE.match(stripped)):
+            key, value = match.group(1).lower(), match.group(2).strip()
+            if key == 'author':
+                book.author = value.replace('[[', '').replace(']]', '')
+            elif key == 'full title':
+                _set_title(book, value)
+        elif section == 'highlights':
+            if line.startswith('- '):
+                annotations.append(_parse_readwise_highlight(book, stripped[2:]))
+            elif annotations and stripped.startswith('- Note:'):
+                annotations[-1].note = stripped[len('- Note:'):].strip() or None
+            elif annotations and not stripped.startswith('- '):
+                # Continuation of a multi-line highlight (or note), which is indented.
+                last = annotations[-1]
+                if last.note is not None:
+                    last.note += '\n' + stripped
+                else:
+                    _append_readwise_highlight_line(book, last, stripped)
+
+    return book, annotations
+
+
+def _parse_readwise_highlight(book: Book, text: str) -> Annotation:
+    annotation = Annotation(highlight='')
+    _append_readwise_highlight_line(book, annotation, text)
+    return annotation
+
+
+def _append_readwise_highlight_line(book: Book, annotation: Annotation, text: str) -> None:
+    # The location is only written at the end of the (last line of the) highlight.
+    if match := READWISE_LOCATION_RE.search(text):
+        if match.group(1) == 'Location':
+            annotation.location = int(match.group(2))
+        else:
+            annotation.page = int(match.group(2))
+        if match.group(3) and not book.asin and (asin_match := READWISE_ASIN_RE.search(match.group(3))):
+            book.asin = asin_match.group(1)
+        text = text[:match.start()]
+
+    annotation.highlight = f'{annotation.highlight}\n{text}' if annotation.highlight else text
+
+
+def parse_bookcision(content: str) -> Tuple[Book, List[Annotation]]:
+    """
+    Parses the text (or Markdown) exported by Bookcision.
+    """
+    book = Book()
+    annotations: List[Annotation] = []
+
+    # The annotation that the following lines belong to, if any.
+    current: Optional[Annotation] = None
+    for line in content.splitlines():
+        stripped = _strip_markup(line)
+        match = BOOKCISION_HEADER_RE.match(stripped)
+
+        if match is None and current is None:
+            # Book information before the first annotation.
+            if not stripped or stripped.startswith('Citation'):
+                continue
+            if not book.title:
+                _set_title(book, stripped)
+            elif not book.author:
+                book.author = re.sub(r'^by:? ', '', stripped, flags=re.IGNORECASE)
+            continue
+
+        if match is None:
+            if stripped:
+                _append_bookcision_line(current, stripped)
+            continue
+
+        position = match.group('position') or ''
+        page_match = BOOKCISION_PAGE_RE.search(position)
+        location_match = BOOKCISION_LOCATION_RE.search(position)
+        page = int(page_match.group(1)) if page_match else None
+        location = int(location_match.group(1)) if location_match else None
+
+        if match.group('note'):
+            # A note belongs to the previous highlight at the same location (if any).
+            previous = annotations[-1] if annotations else None
+            if previous is not None and previous.note is None and previous.location == location:
+                current = previous
+                current.note = ''
+            else:
+                current = Annotation(note='', location=location, page=page)
+                annotations.append(current)
+        else:
+            current = Annotation(highlight='', highlight_color=match.group('color').strip().capitalize(),
+                                 location=location, page=page)
+            annotations.append(current)
+
+    for annotation in annotations:
+        annotation.highlight = annotation.highlight or None
+        annotation.note = annotation.note or None
+    return book, [annotation for annotation in annotations if annotation.highlight or annotation.note]
+
+
+def _append_bookcision_line(annotation: Annotation, text: str) -> None:
+    if annotation.note is not None:
+        annotation.note = f'{annotation.note}\n{text}' if annotation.note else text
+    else:
+        annotation.highlight = f'{annotation.highlight}\n{text}' if annotation.highlight else text
+
+
+def parse_clippings(content: str) -> Tuple[List[Tuple[Book, List[Annotation]]], int]:
+    """
+    Parses the "My Clippings.txt" file of a Kindle device, which has the clippings of all books separated by
+    "==========". Each clipping has a title line (e.g., "Title (Author)"), a metadata line with the type, position,
+    and date (e.g., "- Your Highlight on page 12 | Location 123-125 | Added on ..."), and its text.
+
+    Since the file is append-only, a highlight that is edited on the device appears more than once. Hence, only the
+    last highlight that starts at the same location is kept. Notes are attached to the highlight that ends at their
+    location (if any).
+
+    Returns:
+        The books (in order of their first clipping) with their annotations (in order of their location), and the
+        number of bookmarks, which are skipped since they have no content.
+    """
+    books: Dict[Tuple[str, str], Tuple[Book, List[Annotation]]] = {}
+    # End location of each highlight (by its ID), which is only needed to attach the notes.
+    end_locations: Dict[int, Optional[int]] = {}
+    bookmarks = 0
+
+    for clipping in content.lstrip('\ufeff').split(CLIPPINGS_SEPARATOR):
+        lines = [line.strip('\ufeff \r') for line in clipping.strip().splitlines()]
+        if len(lines) < 2:
+            continue
+        metadata_match = CLIPPINGS_METADATA_RE.match(lines[1])
+        if metadata_match is None:
+            echo_warning(f"Skipping a clipping of '{lines[0]}' with unknown metadata: {lines[1]}")
+            continue
+
+        clipping_type = metadata_match.group('type').lower()
+        if clipping_type == 'bookmark':
+            bookmarks += 1
+            continue
+
+        text = '\n'.join(lines[2:]).strip()
+        if not text:
+            continue
+
+        title_match = CLIPPINGS_TITLE_RE.match(lines[0])
+        author = (title_match.group('author') or '').strip()
+        key = (title_match.group('title').strip(), author)
+        if key not in books:
+            book = Book(author=author)
+            _set_title(book, key[0])
+            books[key] = (book, [])
+        annotations = books[key][1]
+
+        position = metadata_match.group('position')
+        page_match = CLIPPINGS_PAGE_RE.search(position)
+        location_match = CLIPPINGS_LOCATION_RE.search(position)
+        annotation = Annotation(
+            page=int(page_match.group(1)) if page_match else None,
+            location=int(location_match.group(1)) if location_match else None,
+            created_at=_format_clippings_date(metadata_match.group('added')),
+        )
+        if clipping_type == 'highlight':
+            annotation.highlight = text
+            annotations[:] = [a for a in annotations if not (a.highlight and a.location == annotation.location)]
+            end = location_match.group(2) if location_match else None
+            end_locations[id(annotation)] = _expand_location(annotation.location, end)
+            annotations.append(annotation)
+        else:
+            highlight = next((a for a in reversed(annotations) if a.highlight and a.note is None
+                              and annotation.location in (a.location, end_locations.get(id(a)))), None)
+            if highlight is not None:
+                highlight.note = text
+            else:
+                annotation.note = text
+                annotations.append(annotation)
+
+    for _, annotations in books.values():
+        annotations.sort(key=lambda a: a.location or 0)
+    return list(books.values()), bookmarks
+
+
+def _expand_location(start: Optional[int], end: Optional[str]) -> Optional[int]:
+    if start is None or not end:
+        return start
+    # Abbreviated end locations only have the trailing digits, e.g., "123-25" means 123 to 125.
+    if len(end) < len(str(start)):
+        end = str(start)[:len(str(start)) - len(end)] + end
+    return int(end)
+
+
+def _format_clippings_date(added: Optional[str]) -> Optional[str]:
+    if not added:
+        return None
+    try:
+        return format_timestamp(datetime.strptime(added.strip(), CLIPPINGS_ADDED_FORMAT))
+    except ValueError:
+        # Devices in other languages use localized dates, which are kept as is.
+        return added.strip()
+
+
+def _strip_markup(line: str) -> str:
+    """
+    Strips the Markdown markup that may be added around the lines (e.g., headings or bold text).
+    """
+    line = re.sub(r'^(?:#+|>|-)\s+', '', line.strip())
+    return line.strip('*_ ')
+
+
+def _set_title(book: Book, full_title: str) -> None:
+    # Same as the Kindle export, the subtitle (if any) is separated from the title.
+    title_parts = full_title.split(':', 1)
+    book.title = title_parts[0].strip()
+    book.subtitle = title_parts[1].strip() if len(title_parts) > 1 else None
+
+
+def import_markdown(
+    source: Path,
+    target: Path,
+    layout: str = 'auto',
+    output_format: str = 'markdown',
+    frontmatter: Optional[FrontmatterConfig] = None,
+) -> None:
+    """
+    Imports Markdown files exported by other tools into the target directory, adding the books to its index.
+
+    Books that are already in the index are skipped, so that notes managed by ncli are not overwritten. Books
+    without a known ASIN are exported, but cannot be added to the index.
+
+    Args:
+        source (Path): A Markdown file, or a directory that contains Markdown files.
+        target (Path): The directory for the exported files (e.g., the Kindle export directory).
+        layout (str): One of the `MARKDOWN_LAYOUTS`.
+        output_format (str): One of the `OUTPUT_FORMATS` in `kit_amazon`.
+        frontmatter (FrontmatterConfig): The frontmatter config, which is only used for Markdown.
+    """
+    paths = sorted(source.rglob('*.md')) if source.is_dir() else [source]
+
+    target.mkdir(parents=True, exist_ok=True)
+    index_file_path = target.joinpath(EXPORT_INDEX_FILE_NAME)
+    export_index = ExportIndex.load_or_default(index_file_path)
+
+    # The books imported so far are still indexed if a file fails, since their files would be skipped on a retry.
+    try:
+        for path in paths:
+            with open(path, 'r', encoding='utf-8') as f:
+                content = f.read()
+
+            file_layout = detect_layout(content) if layout == 'auto' else layout
+            if file_layout is None:
+                echo_warning(f"Skipping '{path}', which does not match any supported layout.")
+                continue
+
+            book, annotations = parse_readwise(content) if file_layout == 'readwise' else parse_bookcision(content)
+            if not book.title:
+                raise ParseError(f"unable to find the book title in '{path}'")
+            if not annotations:
+                echo_warning(f"Skipping '{path}', which does not contain any annotations.")
+                continue
+
+            _import_book(target, export_index, book, annotations, f"'{path}'", output_format, frontmatter)
+    finally:
+        export_index.save(index_file_path)
+
+
+def import_clippings(
+    source: Path,
+    target: Path,
+    output_format: str = 'markdown',
+    frontmatter: Optional[FrontmatterConfig] = None,
+) -> None:
+    """
+    Imports the "My Clippings.txt" file of a Kindle device into the target directory.
+
+    Since the clippings do not have the ASIN of the books, they cannot be added to the index. Books that are already
+    in the index (by their title) are skipped, same as `import_markdown`.
+
+    Args:
+        source (Path): The "My Clippings.txt" file, which is in the "documents" directory of the device.
+        target (Path): The directory for the exported files (e.g., the Kindle export directory).
+        output_format (str): One of the `OUTPUT_FORMATS` in `kit_amazon`.
+        frontmatter (FrontmatterConfig): The frontmatter config, which is only used for Markdown.
+    """
+    # The file is written with a BOM by the device.
+    with open(source, 'r', encoding='utf-8-sig') as f:
+        books, bookmarks = parse_clippings(f.read())
+
+    target.mkdir(parents=True, exist_ok=True)
+    index_file_path = target.joinpath(EXPORT_INDEX_FILE_NAME)
+    export_index = ExportIndex.load_or_default(index_file_path)
+
+    for book, annotations in books:
+        item = next((item for item in export_index.books if item.info.title == book.title), None)
+        if item is not None:
+            book.asin = item.info.asin
+        _import_book(target, export_index, book, annotations, f'the clippings of book {book.title}', output_format,
+                     frontmatter)
+
+    if bookmarks:
+        echo(f'Skipped {bookmarks} bookmark(s), which do not have any content.')
+    export_index.save(index_file_path)
+
+
+def _import_book(
+    target: Path,
+    export_index: ExportIndex,
+    book: Book,
+    annotations: List[Annotation],
+    source_name: str,
+    output_format: str,
+    frontmatter: Optional[FrontmatterConfig],
+) -> None:
+    if book.asin and export_index.get_item(book.asin) is not None:
+        echo(f'Skipping book {book.title} ({book.asin}), which is already in the index.')
+        return
+
+    file_name = sanitize_file_name(book.title)
+    if target.joinpath(f'{file_name}.{OUTPUT_FORMATS[output_format]}').exists():
+        echo_warning(f"Skipping {source_name}, since a file for book {book.title} already exists in the target.")
+        return
+
+    output_file = export_book(target, file_name, output_format, book, annotations=annotations,
+                              frontmatter=frontmatter)
+    echo(f"Imported {len(annotations)} annotation(s) from {source_name} to '{output_file}'.")
+
+    if book.asin:
+        export_index.books.append(ExportItem(last_updated_time=current_timestamp(), info=book, file_name=file_name))
+    else:
+        echo_warning(f'Unable to find the ASIN of book {book.title}, so it is not added to the index.')
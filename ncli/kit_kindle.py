@@ -5,20 +5,449 @@
 A module for processing and managing Kindle data.
 """
 
-from typing import List
+import csv
+import json
+import os
+import re
+import threading
+import time
+from collections import Counter
+from concurrent.futures import Future, ThreadPoolExecutor
+from datetime import datetime
+from difflib import SequenceMatcher
+from typing import Any, Dict, List, Optional, Tuple
 from pathlib import Path
 
+import click
 import requests
+import toml
 
-from bs4 import BeautifulSoup
+from bs4 import BeautifulSoup, SoupStrainer, Tag
 from click import echo
+from pydantic import BaseModel, ValidationError, validator  # pylint: disable=no-name-in-module
 
-from ncli.kit_amazon import Config, Authenticator, load_authenticator, \
-    Book, Annotation, ExportIndex, export_to_markdown
+from ncli import constants
+from ncli.errors import AccountLockedError, AuthError, CaptchaError, ConfigError, NetworkError, \
+    PasswordResetError, SignInRequiredError
+from ncli.kit_amazon import Config as AmazonConfig, FrontmatterConfig, Authenticator, load_authenticator, \
+    Book, Annotation, AnnotationGroup, Decisions, ExportIndex, ExportItem, RunSummary, FILE_NAME_COLLISION_POLICIES, \
+    OUTPUT_FORMATS, RUN_SUMMARY_FILE_NAME, download_cover, estimate_export_size, export_book, format_book_dates, \
+    load_markdown_template, merge_into_markdown, parse_markdown_annotations, parse_markdown_removed_annotations, \
+    split_frontmatter, write_markdown, write_markdown_template
+from ncli.utils import check_choice, current_datetime, echo_warning, ensure_free_space, format_simple_date, \
+    format_timestamp, heading_anchor, parse_date, sanitize_file_name
 
 KINDLE_HIGHLIGHTS_URL: str = 'https://read.amazon.com/notebook'
+KINDLE_CLOUD_READER_URL: str = 'https://read.amazon.com/'
 EXPORT_INDEX_FILE_NAME: str = "index.toml"
 
+# Minimum similarity ratio for two highlights (from different editions) to be considered the same.
+EDITION_MATCH_RATIO: float = 0.9
+
+ACTIONS_FILE_NAME: str = "Actions.md"
+
+# Part of the form on the captcha page that Amazon shows instead of the product page for automated requests.
+PRODUCT_CAPTCHA_MARKER: bytes = b'/errors/validateCaptcha'
+
+# ASINs of Kindle books, i.e., Amazon's own ASINs (e.g., "B0123ABCDE") or ISBN-10s for older books. Personal
+# documents (e.g., PDFs sent to Kindle) have other formats, typically 32 characters long.
+BOOK_ASIN_RE = re.compile(r'^(?:B[0-9A-Z]{9}|[0-9]{9}[0-9X])$')
+PERSONAL_DOC_TYPE: str = 'personal'
+
+# Cache of the metadata from the Amazon product pages, which is shared across export targets.
+PRODUCT_CACHE_PATH: Path = constants.BASE_PATH.joinpath('cache', 'kindle-products.toml')
+
+# Amazon redirects to its sign-in flow (instead of returning an error status) if the session cannot be used, which is
+# served under these paths.
+AUTH_PAGE_PATHS: List[str] = ['/ap/', '/errors/']
+
+# Each entry maps the error to be raised to the markers (in the URL or the lower-cased page content) of a page in the
+# sign-in flow. The entries are checked in order, and the last one is used if none of the markers match.
+AUTH_PAGE_MARKERS: List[Tuple[type, List[str], str]] = [
+    (CaptchaError, ['validatecaptcha', 'auth-captcha-image', 'captchacharacters'],
+     'Amazon asks to solve a captcha. Please sign in to Amazon in your browser, then re-run '
+     '`ncli config amazon-auth` (preferably with the external browser login).'),
+    (PasswordResetError, ['/ap/forgotpassword', 'password reset required', 'auth-password-reset'],
+     'Amazon requires your password to be reset. Please reset it in your browser, then re-run '
+     '`ncli config amazon-auth`.'),
+    (AccountLockedError, ['account has been locked', 'account on hold', 'temporarily locked'],
+     'Your Amazon account is locked or on hold. Please follow the instructions from Amazon to unlock it, then '
+     're-run `ncli config amazon-auth`.'),
+    (SignInRequiredError, [],
+     'The Amazon session has expired. Please re-run `ncli config amazon-auth`.'),
+]
+
+
+def check_auth_page(url: str, content: bytes) -> None:
+    """
+    Raises a specific `AuthError` if the given page is part of Amazon's sign-in flow (e.g., a captcha).
+    """
+    lowered_url = url.lower()
+    if not any(path in lowered_url for path in AUTH_PAGE_PATHS):
+        return
+
+    lowered_content = content.decode('utf-8', errors='ignore').lower()
+    for error_class, markers, message in AUTH_PAGE_MARKERS:
+        if not markers or any(marker in lowered_url or marker in lowered_content for marker in markers):
+            raise error_class(message)
+
+
+# How to order the annotations in each exported book.
+#
+# - none: sequentially, by their location in the book.
+# - color: in a section per highlight color, and by their location within each section.
+GROUP_BY_MODES = ("none", "color")
+
+# How to sort the annotations of each exported book.
+#
+# - none: in the order of the Kindle notebook.
+# - location: by their location, with the annotations without a location at the end.
+# - page: by their page (and by their location for the same page), with the annotations without a page at the end.
+SORT_MODES = ("none", "location", "page")
+
+# Where to open a book.
+#
+# - app: the Kindle app, using the `kindle://` deep link.
+# - cloud: the Kindle Cloud Reader in the browser.
+OPEN_WITH_MODES = ("app", "cloud")
+
+
+def group_annotations_by_color(
+    annotations: List[Annotation],
+    color_meanings: Dict[str, str],
+) -> List[AnnotationGroup]:
+    """
+    Groups the annotations by their highlight color. Notes without highlights are grouped at the end.
+    """
+    meanings = {color.lower(): meaning for color, meaning in color_meanings.items()}
+    order = list(meanings)
+
+    annotations_by_color: Dict[str, List[Annotation]] = {}
+    titles: Dict[str, str] = {}
+    for annotation in annotations:
+        color = annotation.highlight_color if annotation.highlight else None
+        key = color.lower() if color else ''
+        annotations_by_color.setdefault(key, []).append(annotation)
+        if key not in order and key:
+            order.append(key)
+        if key not in titles:
+            titles[key] = f'{color}: {meanings[key]}' if key in meanings else (color or 'Notes')
+
+    order.append('')
+    return [
+        AnnotationGroup(title=titles[key], annotations=annotations_by_color[key])
+        for key in order if key in annotations_by_color
+    ]
+
+
+def get_notebook_url(asin: str) -> str:
+    """
+    Returns the URL that opens the Kindle notebook for the given book.
+
+    Note that the notebook only supports filtering the annotations (e.g., by highlight color) on the page itself,
+    so the URL can only select the book.
+    """
+    return f'{KINDLE_HIGHLIGHTS_URL}?asin={asin}'
+
+
+def get_book_url(asin: str, location: Optional[int] = None, open_with: str = 'app') -> str:
+    """
+    Returns the URL that opens the given book, optionally at the given location.
+
+    Note that the Cloud Reader does not support opening a specific location, so the location is only used for the app.
+    """
+    if open_with == 'cloud':
+        return f'{KINDLE_CLOUD_READER_URL}?asin={asin}'
+    if open_with != 'app':
+        raise ConfigError(f'Unknown value for kindle.open_with: {open_with}')
+
+    url = f'kindle://book?action=open&asin={asin}'
+    if location is not None:
+        url += f'&location={location}'
+    return url
+
+
+class Selectors(BaseModel):
+    """
+    CSS selectors used to parse the Kindle notebook pages.
+
+    These can be overridden in the config (`[kindle.selectors]`) to work around markup changes on Amazon's side
+    without waiting for a new release.
+    """
+    # Library page. Note that the book selectors (except `library_book`) are relative to each book entry.
+    library_book: str = 'div.kp-notebook-library-each-book'
+    library_book_title: str = 'h2'
+    library_book_author: str = 'p'
+    library_book_image: str = 'img'
+    library_book_last_opened_date: str = 'input'
+    library_next_page_token: str = 'input.kp-notebook-library-next-page-start'
+
+    # Annotations page. Note that the annotation selectors (except `annotation_row`) are relative to each row.
+    annotation_row: str = 'div.kp-notebook-row-separator'
+    annotation_highlight: str = 'span#highlight'
+    annotation_highlight_header: str = 'span#annotationHighlightHeader'
+    annotation_note: str = 'span#note'
+    annotation_note_header: str = 'span#annotationNoteHeader'
+    annotation_location: str = 'input#kp-annotation-location'
+    # Element with the time when the annotation was made (its text, or its value for an input element). Not every
+    # marketplace shows it in the notebook, in which case the time is left empty. Set it to empty to disable it.
+    annotation_created_at: str = 'span#annotationCreationDate'
+    annotations_next_page_token: str = 'input.kp-notebook-annotations-next-page-start'
+    annotations_content_limit_state: str = 'input.kp-notebook-content-limit-state'
+
+    # Product page (only used if `fetch_product_metadata` is enabled).
+    product_description: str = '#bookDescription_feature_div .a-expander-content'
+    product_publisher: str = '#rpi-attribute-book_details-publisher .rpi-attribute-value'
+    product_publication_date: str = '#rpi-attribute-book_details-publication_date .rpi-attribute-value'
+
+
+class TitleSplit(BaseModel):
+    """
+    How the library titles in the "<title>: <subtitle>" format are split, since only the title is used for the file
+    names (see `split_title`).
+    """
+    enabled: bool = True
+    # Full titles that are never split (case-insensitive), e.g., "1984: The Graphic Novel".
+    keep_titles: List[str] = []
+    # Minimum number of characters of the title and the subtitle, below which the title is kept as is.
+    min_title_length: int = 2
+    min_subtitle_length: int = 3
+
+
+def split_title(full_title: str, title_split: Optional[TitleSplit] = None) -> Tuple[str, Optional[str]]:
+    """
+    Splits a library title into the title and subtitle, e.g., "Title: Subtitle".
+
+    Only a colon that is followed by a space and is not enclosed in brackets or quotes counts as a separator, so that
+    titles like "Re:Zero", "10:04", or "Volume 1 (Part 2: The End)" are kept as they are.
+    """
+    title_split = title_split if title_split is not None else TitleSplit()
+    if not title_split.enabled or full_title.casefold() in {title.casefold() for title in title_split.keep_titles}:
+        return full_title, None
+
+    depth = 0
+    in_quote = False
+    for i, char in enumerate(full_title):
+        if char in '([{':
+            depth += 1
+        elif char in ')]}':
+            depth = max(0, depth - 1)
+        elif char in '"“”':
+            in_quote = not in_quote
+        elif char == ':' and depth == 0 and not in_quote and full_title[i + 1:i + 2].isspace():
+            title, subtitle = full_title[:i].strip(), full_title[i + 1:].strip()
+            if len(title) < title_split.min_title_length or len(subtitle) < title_split.min_subtitle_length:
+                return full_title, None
+            return title, subtitle
+    return full_title, None
+
+
+class Pacing(BaseModel):
+    """
+    Pacing of the requests to the Kindle notebook, to keep long exports reliable.
+    """
+    # Minimum time between two consecutive requests, in seconds.
+    request_interval_s: float = 0.5
+
+    # Maximum number of retries for a request that fails because of a network error, rate limiting (HTTP 429), or a
+    # server error (HTTP 5xx).
+    max_retries: int = 3
+
+    # Factor by which the interval grows with each retry, i.e., the n-th retry waits for
+    # `request_interval_s * backoff_factor ** n` seconds (capped at `max_backoff_s`).
+    backoff_factor: float = 2.0
+    max_backoff_s: float = 60.0
+
+    def get_retry_interval(self, attempt: int) -> float:
+        """
+        Returns the minimum interval before the given attempt (starting from 0 for the first request), in seconds.
+        """
+        if attempt == 0:
+            return self.request_interval_s
+        # Note that the backoff never goes below the interval between normal requests.
+        return max(self.request_interval_s, min(self.request_interval_s * self.backoff_factor ** attempt,
+                                                self.max_backoff_s))
+
+
+# Default pacing per marketplace (i.e., Amazon country code). Marketplaces that are not listed use `Pacing()`.
+#
+# Note that these are conservative defaults, since some marketplaces tend to rate limit earlier on long exports.
+MARKETPLACE_PACING: Dict[str, Pacing] = {
+    'us': Pacing(request_interval_s=0.5, max_retries=3),
+    'uk': Pacing(request_interval_s=1.0, max_retries=5),
+    'de': Pacing(request_interval_s=1.0, max_retries=5),
+    'jp': Pacing(request_interval_s=1.0, max_retries=5),
+    'in': Pacing(request_interval_s=1.5, max_retries=5),
+}
+
+
+class Config(BaseModel):
+    """
+    Config for Kindle operations.
+    """
+    # Whether to include Kindle in aggregate commands (e.g., `ncli export --all`).
+    enabled: bool = True
+
+    selectors: Selectors = Selectors()
+
+    # Pacing per marketplace (i.e., Amazon country code), which overrides the defaults in `MARKETPLACE_PACING`.
+    pacing: Dict[str, Pacing] = {}
+
+    title_split: TitleSplit = TitleSplit()
+
+    def get_pacing(self, country_code: str) -> Pacing:
+        """
+        Returns the pacing for the given marketplace.
+        """
+        if country_code in self.pacing:
+            return self.pacing[country_code]
+        return MARKETPLACE_PACING.get(country_code, Pacing())
+
+    # Mapping of an old edition's ASIN to the ASIN of the edition that replaces it.
+    #
+    # Annotations of the old edition are merged into the note of the new edition (with locations remapped),
+    # instead of being exported as a separate book.
+    edition_aliases: Dict[str, str] = {}
+
+    # Keywords (case-insensitive) that mark a note as an action item, e.g., "TODO" or "followup".
+    #
+    # If set, all notes containing any of the keywords are collected into a single note after each export.
+    action_keywords: List[str] = []
+
+    # Mapping of a highlight color (case-insensitive) to its meaning, e.g., "yellow" -> "Key ideas".
+    #
+    # This is used to name the sections when grouping annotations by color. The sections follow the order of this
+    # mapping, followed by the remaining colors.
+    color_meanings: Dict[str, str] = {}
+
+    # Whether to add the metadata of each annotation (e.g., color, page, and location) as inline fields for Obsidian
+    # Dataview in the Markdown notes, so that the highlights can be queried across books.
+    dataview_fields: bool = False
+
+    # Mapping of a highlight color (case-insensitive) to a tag, e.g., "blue" -> "#idea".
+    #
+    # The tag is written after the header of each highlight with that color (e.g., "**Blue highlight:** #idea"), so
+    # that the highlights can be found by their meaning in note-taking apps.
+    color_tags: Dict[str, str] = {}
+
+    # How to name the file of a book whose title collides with another book (e.g., another edition). See
+    # `FILE_NAME_COLLISION_POLICIES` in `kit_amazon`.
+    file_name_collision: str = 'prompt'
+
+    # Where to open a book with `ncli kindle open`. See `OPEN_WITH_MODES`.
+    open_with: str = 'app'
+
+    # Whether to keep highlights that have been deleted on Amazon (e.g., from a device) in a "Removed highlights"
+    # section of the exported note, instead of dropping them. Only supported for the Markdown format.
+    keep_deleted: bool = False
+
+    # Maximum number of words of highlighted text per book (e.g., for sharing the exported notes publicly), or 0 to
+    # disable it. Books over the limit are reported at the end of each export, see also `--redact-over-limit`.
+    max_highlight_words: int = 0
+
+    # Whether to fetch the description, publisher, and publication date of each exported book from its Amazon
+    # product page. The results are cached (see `PRODUCT_CACHE_PATH`), so each page is only visited once.
+    fetch_product_metadata: bool = False
+
+    # Whether to download the cover image of each exported book into the `covers` directory of the target, which is
+    # then referenced in the exported note instead of relying on the image URL only.
+    download_covers: bool = False
+
+    # Whether to include personal documents (e.g., PDFs sent to Kindle), which are marked with the "personal"
+    # document type in the exported notes.
+    personal_documents: bool = True
+
+    # Whether to wrap the generated sections of the Markdown notes with markers, so that re-exporting only
+    # regenerates them while keeping the text around them (e.g., your own commentary). See `merge_managed_sections`
+    # in `kit_amazon`.
+    managed_sections: bool = False
+
+    # Path to a Jinja2 template for the Markdown notes (or its name in `TEMPLATES_DIR` of `kit_amazon`), or empty to
+    # use the built-in layout. See `DEFAULT_KINDLE_TEMPLATE_PATH` in `kit_amazon` for the default template and the
+    # available variables.
+    #
+    # Note that the features that read the exported notes back (e.g., `keep_deleted` and `action_keywords`) rely on
+    # the built-in layout.
+    template: str = ''
+
+    @validator('file_name_collision')
+    def check_file_name_collision(cls, value):  # pylint: disable=no-self-argument
+        """
+        Checks that the file name collision policy is one of the `FILE_NAME_COLLISION_POLICIES`.
+        """
+        return check_choice('file name collision policy', value, FILE_NAME_COLLISION_POLICIES)
+
+
+class BookFilter(BaseModel):
+    """
+    Filters the books to be exported. A book needs to match all the given criteria.
+    """
+    # Case-insensitive substrings of the title and author.
+    title: Optional[str] = None
+    author: Optional[str] = None
+    # If not empty, only the books with these ASINs.
+    asins: List[str] = []
+    # Only the books that are last opened on or after this time.
+    since: Optional[datetime] = None
+
+    def matches(self, book: Book) -> bool:
+        """
+        Returns whether the given book matches the filter.
+        """
+        if self.title and self.title.casefold() not in book.title.casefold():
+            return False
+        if self.author and self.author.casefold() not in book.author.casefold():
+            return False
+        if self.asins and book.asin not in self.asins:
+            return False
+        if self.since is not None:
+            # Books with an unknown last opened date cannot be confirmed to match.
+            last_opened = parse_date(book.last_opened_date)
+            if last_opened is None or last_opened < self.since.astimezone():
+                return False
+        return True
+
+
+class ProductMetadata(BaseModel):
+    """
+    Represents the metadata of a book from its Amazon product page.
+    """
+    description: Optional[str] = None
+    publisher: Optional[str] = None
+    publication_date: Optional[str] = None
+
+
+class ProductCache(BaseModel):
+    """
+    Represents the cached product metadata, keyed by the book ASIN.
+    """
+    products: Dict[str, ProductMetadata] = {}
+
+    @staticmethod
+    def load_or_default(path: Path):
+        """
+        Load the cache or default to an empty state if there is no such file (or it cannot be parsed).
+        """
+        if not os.path.exists(path):
+            return ProductCache()
+
+        with open(path, "r", encoding='utf-8') as file:
+            cache_str = file.read()
+        try:
+            return ProductCache(**toml.loads(cache_str))
+        except (toml.TomlDecodeError, ValidationError) as e:
+            # The cache can always be rebuilt, so it is fine to start over.
+            echo_warning(f'Ignoring invalid product cache {path}. Error: {e}')
+            return ProductCache()
+
+    def save(self, path: Path):
+        """
+        Save the cache into the specified path.
+        """
+        os.makedirs(path.parent, exist_ok=True)
+        with open(path, "w", encoding='utf-8') as file:
+            file.write(toml.dumps(self.dict(exclude_none=True)))
+
 
 class Client:
     """
@@ -29,78 +458,122 @@ class Client:
         password (str): The password for the Amazon account.
     """
 
-    def __init__(self, auth: Authenticator):
+    def __init__(
+        self,
+        auth: Authenticator,
+        selectors: Optional[Selectors] = None,
+        pacing: Optional[Pacing] = None,
+        title_split: Optional[TitleSplit] = None,
+    ):
         if auth.website_cookies is None:
-            raise ValueError('unexpected: auth does not have website_cookies')
+            raise AuthError('auth does not have website cookies, please re-run `ncli config amazon-auth`')
 
         self.auth = auth
+        self.selectors = selectors if selectors is not None else Selectors()
+        self.pacing = pacing if pacing is not None else Pacing()
+        self.title_split = title_split if title_split is not None else TitleSplit()
+
+        # Note that the session honors the system proxy settings (e.g., the `HTTPS_PROXY` env var).
         self.session = requests.Session()
         self.session.cookies.update(auth.website_cookies)
 
+        self._last_request_time: Optional[float] = None
+
     def close(self):
         """
         Close the client connection.
         """
         self.session.close()
 
+    def _get(self, url: str) -> requests.Response:
+        """
+        Sends a GET request using the authenticated session.
+
+        Requests are spaced according to the pacing, and retried (with an exponential backoff) if they fail because of
+        transient errors.
+        """
+        attempt = 0
+        while True:
+            self._wait(self.pacing.get_retry_interval(attempt))
+            try:
+                response = self.session.get(url)
+                response.raise_for_status()
+                break
+            except requests.RequestException as e:
+                status_code = e.response.status_code if e.response is not None else None
+                transient = status_code is None or status_code == 429 or status_code >= 500
+                if not transient or attempt >= self.pacing.max_retries:
+                    raise NetworkError(f'Failed to fetch {url}. Error: {e}') from e
+
+                attempt += 1
+                echo_warning(f'Failed to fetch {url}, retrying ({attempt}/{self.pacing.max_retries}). Error: {e}')
+
+        check_auth_page(response.url, response.content)
+        return response
+
+    def _wait(self, interval_s: float) -> None:
+        """
+        Waits until at least the given interval has passed since the last request.
+        """
+        if self._last_request_time is not None:
+            remaining_s = self._last_request_time + interval_s - time.monotonic()
+            if remaining_s > 0:
+                time.sleep(remaining_s)
+        self._last_request_time = time.monotonic()
+
     def get_books(self) -> List[Book]:
         """
         Fetches the list of books from the Kindle Highlights website.
 
+        Large libraries are split into multiple pages, which are fetched until there is no next page token.
+
         Returns:
             list[Book]: A list of Book instances.
         """
-        kindle_highlights_response = self.session.get(KINDLE_HIGHLIGHTS_URL)
+        books = []
+        seen_tokens = set()
 
-        soup = BeautifulSoup(kindle_highlights_response.content, 'html.parser')
+        url = KINDLE_HIGHLIGHTS_URL
+        while True:
+            kindle_highlights_response = self._get(url)
+            page_token, page_books = _parse_library_page(kindle_highlights_response.content, self.selectors,
+                                                        self.title_split)
+            books.extend(page_books)
 
-        book_entries = soup.find_all(
-            'div', {'class': 'kp-notebook-library-each-book'})
-        books = []
+            # Guard against a token that is repeated, which would otherwise fetch the same page forever.
+            if not page_token or page_token in seen_tokens:
+                break
+            seen_tokens.add(page_token)
+            url = f'{KINDLE_HIGHLIGHTS_URL}?library=list&token={page_token}'
 
-        for book_entry in book_entries:
-            # Retrieve the Amazon Standard Identification Number (ASIN)
-            #
-            # We need this value if we want to fetch other information about the book from Amazon (e.g., highlights).
-            asin = book_entry['id']
+        return books
 
-            # Retrieve the book title and subtitle if present
-            #
-            # Note that some books have the following format for the title: "<title>: <subtitle>".
-            # Hence, we want to identify the subtitle and separate it from the main title if there is any.
-            # The reason is because we want to save a book only based on its title as the file name.
-            book_title = book_entry.find('h2').get_text(strip=True)
-            title_parts = book_title.split(":", 1)
-            title = title_parts[0].strip()
-            subtitle = title_parts[1].strip() if len(title_parts) > 1 else None
-
-            # Retrieve the author
-            #
-            # In the website, the author is written in the following format: "By: <author>".
-            # Hence, we need to remove the "By: " prefix.
-            book_author = book_entry.find('p').get_text(strip=True)
-            author_parts = book_author.split(":", 1)
-            author = author_parts[1].strip() if len(
-                author_parts) > 1 else author_parts[0].strip()
-
-            # Retrieve the image URL
-            #
-            # Note that the url will be using Amazon CDN and it is not guaranteed for long time use as they could
-            # change over time.
-            image_url = book_entry.find('img')['src']
+    def get_product_metadata(self, book: Book) -> Optional[ProductMetadata]:
+        """
+        Fetches the metadata of a book from its Amazon product page.
 
-            # Retrieve the last opened date
-            #
-            # Note that we keep it as a string, since this value is probably not that useful given that we may
-            # occasionally open a book, but not adding any new annotations.
-            last_opened_date = book_entry.find('input')['value']
+        Returns None if the page cannot be fetched or has no metadata (e.g., Amazon shows a captcha for automated
+        requests), since the metadata is only nice to have. This way, the book is retried in the next runs instead of
+        caching the empty metadata.
+        """
+        url = f'https://www.amazon.{self.auth.locale.domain}/dp/{book.asin}'
+        try:
+            response = self._get(url)
+        except (AuthError, NetworkError) as e:
+            echo_warning(f'Failed to fetch the product page of book {book.title} ({book.asin}). Error: {e}')
+            return None
 
-            # Construct the book object based on all the information that we have
-            book = Book(asin=asin, title=title, subtitle=subtitle, author=author,
-                        image_url=image_url, last_opened_date=last_opened_date)
-            books.append(book)
+        # The captcha page is served with a successful status, so it has to be detected from the content.
+        if PRODUCT_CAPTCHA_MARKER in response.content:
+            echo_warning(f'Amazon asked to solve a captcha for the product page of book {book.title} ({book.asin}), '
+                         f'skipping its metadata.')
+            return None
 
-        return books
+        metadata = _parse_product_page(response.content, self.selectors)
+        if all(value is None for value in metadata.dict().values()):
+            echo_warning(f'No metadata found on the product page of book {book.title} ({book.asin}).')
+            return None
+        return metadata
 
     def get_annotations(self, book: Book) -> List[Annotation]:
         """
@@ -111,9 +584,7 @@ class Client:
         first_page = True
         page_token = None
         page_limit_state = None
-
-        session = requests.Session()
-        session.cookies.update(self.auth.website_cookies)
+        limit_reached = False
 
         result = []
 
@@ -124,129 +595,1033 @@ class Client:
             else:
                 url = f'https://read.amazon.com/notebook?asin={book_asin}&token={page_token}&contentLimitState={page_limit_state}&='
 
-            annotations_response = session.get(url)
+            annotations_response = self._get(url)
 
-            soup = BeautifulSoup(annotations_response.content, 'html.parser')
+            page_token, page_limit_state, annotations = _parse_annotations_page(
+                annotations_response.content, self.selectors)
 
-            # Next page token and limit state
-            page_token = soup.find(
-                'input', {'class': 'kp-notebook-annotations-next-page-start'}).get('value', default=None)
-            page_limit_state = soup.find(
-                'input', {'class': 'kp-notebook-content-limit-state'}).get('value', default=None)
+            # Once the clipping limit is reached, Amazon cuts the highlights short on the following pages. Note that
+            # the limit state of a page is the one sent with the request for the next page.
+            if limit_reached:
+                for annotation in annotations:
+                    annotation.truncated = annotation.highlight is not None
+            limit_reached = limit_reached or is_content_limit_reached(page_limit_state)
+            result.extend(annotations)
+
+        return result
+
+
+def _parse_product_page(content: bytes, selectors: Selectors) -> ProductMetadata:
+    """
+    Parses the metadata from an Amazon product page. Fields that cannot be found are left empty.
+    """
+    soup = BeautifulSoup(content, 'html.parser')
+
+    def text(selector: str) -> Optional[str]:
+        element = soup.select_one(selector)
+        if element is None:
+            return None
+        return element.get_text('\n', strip=True) or None
+
+    return ProductMetadata(
+        description=text(selectors.product_description),
+        publisher=text(selectors.product_publisher),
+        publication_date=text(selectors.product_publication_date),
+    )
+
+
+def _parse_library_page(
+    content: bytes,
+    selectors: Selectors,
+    title_split: Optional[TitleSplit] = None,
+) -> Tuple[Optional[str], List[Book]]:
+    """
+    Parses a single page of the Kindle notebook library.
+
+    Returns:
+      - Token for the next page, if any.
+      - Books found on the page.
+    """
+    soup = BeautifulSoup(content, 'html.parser')
+
+    page_token = _get_input_value(soup, selectors.library_next_page_token)
+
+    book_entries = soup.select(selectors.library_book)
+    books = []
+
+    for book_entry in book_entries:
+        # Retrieve the Amazon Standard Identification Number (ASIN)
+        #
+        # We need this value if we want to fetch other information about the book from Amazon (e.g., highlights).
+        asin = book_entry['id']
+
+        # Retrieve the book title and subtitle if present
+        #
+        # Note that some books have the following format for the title: "<title>: <subtitle>".
+        # Hence, we want to identify the subtitle and separate it from the main title if there is any.
+        # The reason is because we want to save a book only based on its title as the file name.
+        book_title = book_entry.select_one(selectors.library_book_title).get_text(strip=True)
+        title, subtitle = split_title(book_title, title_split)
+
+        # Retrieve the author
+        #
+        # In the website, the author is written in the following format: "By: <author>".
+        # Hence, we need to remove the "By: " prefix.
+        #
+        # Personal documents may not have any author.
+        author_element = book_entry.select_one(selectors.library_book_author)
+        book_author = author_element.get_text(strip=True) if author_element is not None else ''
+        author_parts = book_author.split(":", 1)
+        author = author_parts[1].strip() if len(
+            author_parts) > 1 else author_parts[0].strip()
+
+        # Retrieve the image URL
+        #
+        # Note that the url will be using Amazon CDN and it is not guaranteed for long time use as they could
+        # change over time. Personal documents may not have any image.
+        image_element = book_entry.select_one(selectors.library_book_image)
+        image_url = image_element.get('src', '') if image_element is not None else ''
+
+        # Retrieve the last opened date
+        #
+        # The date is normalized to the "YYYY-MM-DD" format (if it can be parsed), so that it can be compared and
+        # sorted reliably. Note that this value is probably not that useful given that we may occasionally open a
+        # book, but not adding any new annotations.
+        last_opened_date = book_entry.select_one(selectors.library_book_last_opened_date)['value']
+        last_opened = parse_date(last_opened_date)
+        if last_opened is not None:
+            last_opened_date = last_opened.date().isoformat()
+
+        # Construct the book object based on all the information that we have
+        doc_type = PERSONAL_DOC_TYPE if not BOOK_ASIN_RE.match(asin) else None
+        book = Book(asin=asin, title=title, subtitle=subtitle, author=author, doc_type=doc_type,
+                    image_url=image_url, last_opened_date=last_opened_date)
+        books.append(book)
+
+    return page_token, books
+
+
+# Only the annotation rows and the pagination inputs are needed from an annotations page. Restricting the parser to
+# these elements avoids building the tree for the rest of the page (e.g., scripts, navigation, library sidebar),
+# which matters for books with thousands of highlights since each page is parsed separately.
+#
+# Note that this is only valid for the default selectors. Custom selectors may refer to other elements.
+ANNOTATIONS_PAGE_STRAINER = SoupStrainer(class_=[
+    'kp-notebook-row-separator',
+    'kp-notebook-annotations-next-page-start',
+    'kp-notebook-content-limit-state',
+])
+
+
+# Field of the content limit state (see `is_content_limit_reached`) that is set once the clipping limit is reached.
+CONTENT_LIMIT_REACHED_KEY = 'isLimitReached'
+
+
+def _parse_annotations_page(
+    content: bytes,
+    selectors: Selectors,
+) -> Tuple[Optional[str], Optional[str], List[Annotation]]:
+    """
+    Parses a single page of the Kindle notebook annotations.
+
+    Returns:
+      - Token for the next page, if any.
+      - Content limit state for the next page, if any.
+      - Annotations found on the page.
+    """
+    parse_only = ANNOTATIONS_PAGE_STRAINER if selectors == Selectors() else None
+    soup = BeautifulSoup(content, 'html.parser', parse_only=parse_only)
+
+    # Next page token and limit state
+    page_token = _get_input_value(soup, selectors.annotations_next_page_token)
+    page_limit_state = _get_input_value(soup, selectors.annotations_content_limit_state)
+
+    annotations = [
+        _parse_annotation_row(row, selectors)
+        for row in soup.select(selectors.annotation_row)
+    ]
+
+    # Release the parsed tree eagerly, since the rows have been converted into our own model.
+    soup.decompose()
+
+    return page_token, page_limit_state, annotations
+
+
+def is_content_limit_reached(limit_state: Optional[str]) -> bool:
+    """
+    Checks whether the content limit state of a notebook page indicates that the clipping limit has been reached.
+
+    The state is a JSON object that is sent back with the request for the next page (and is empty until the limit
+    comes into play). Only `CONTENT_LIMIT_REACHED_KEY` is checked, since the other fields are not about the limit.
+    """
+    if not limit_state:
+        return False
+
+    try:
+        state = json.loads(limit_state)
+    except ValueError:
+        return False
+    return isinstance(state, dict) and state.get(CONTENT_LIMIT_REACHED_KEY) is True
+
+
+def _get_input_value(soup: Tag, selector: str) -> Optional[str]:
+    """
+    Returns the value of the input element matching the selector, if any.
+    """
+    element = soup.select_one(selector)
+    if element is None:
+        return None
+    return element.get('value', default=None)
 
-            annotations_element = soup.find(id='kp-notebook-annotations')
-            if annotations_element:
-                annotations = annotations_element.find_all(
-                    'div', {'class': 'kp-notebook-row-separator'})
-            else:
-                annotations = soup.find_all(
-                    'div', {'class': 'kp-notebook-row-separator'})
 
+def _parse_annotation_row(row: Tag, selectors: Selectors) -> Annotation:
+    """
+    Parses a single annotation row (highlight and/or note) from the Kindle notebook.
+    """
+    highlight = None
+    highlight_color = None
+    note = None
+    page = None
+
+    # Retrieve the highlight
+    highlight_element = row.select_one(selectors.annotation_highlight)
+    if highlight_element:
+        highlight = highlight_element.get_text(strip=True)
+
+        # Retrieve the highlight header
+        #
+        # The header will be one of the following formats:
+        # 1. "<color> annotation | Page: <page>" if there's a page number
+        # 2. "<color> annotation | Location: <location>" if there's no page number
+        #
+        # However, since we can always get the location from another field, we won't retrieve the location
+        # for the second case.
+        highlight_header = row.select_one(selectors.annotation_highlight_header).get_text(strip=True)
+        header_parts = highlight_header.split("|", 1)
+        color_parts = header_parts[0].strip().split(" ", 1)
+
+        # We can retrieve highlight color and potentially the page number here
+        highlight_color = color_parts[0].strip()
+        page = _parse_header_page(header_parts)
+
+    # Retrieve the note
+    #
+    # Note that the Kindle notebook page is a bit weird since it will always have the note element.
+    # In order to find out about its existence, we need to check the length.
+    note_str = row.select_one(selectors.annotation_note).get_text(strip=True)
+    if note_str:
+        note = note_str
+
+        # If there is no highlight, check the page number using the note header
+        if highlight is None:
+            # Similar with the highlight header, it will be one of the following formats:
+            # 1. "Note | Page: <page>" if there's a page number
+            # 2. "Note | Location: <location>" if there's no page number
+            #
+            # Only the first case is useful.
+            note_header = row.select_one(selectors.annotation_note_header).get_text(strip=True)
+            page = _parse_header_page(note_header.split("|", 1))
+
+    # Retrieve the location
+    #
+    # Note that some personal documents (e.g., PDFs) do not have any location.
+    location_element = row.select_one(selectors.annotation_location)
+    location_value = location_element.get('value', '') if location_element is not None else ''
+    location = int(location_value) if location_value.isdigit() else None
+
+    # Retrieve the creation time, if enabled
+    created_at = None
+    created_at_element = row.select_one(selectors.annotation_created_at) if selectors.annotation_created_at else None
+    if created_at_element is not None:
+        created_at = _parse_created_at(created_at_element)
+
+    return Annotation(
+        highlight=highlight,
+        highlight_color=highlight_color,
+        note=note,
+        page=page,
+        location=location,
+        created_at=created_at,
+    )
+
+
+def _parse_created_at(element: Tag) -> Optional[str]:
+    """
+    Parses the creation time of an annotation into the same format as the other annotation timestamps. Values in an
+    unknown format are kept as is, so that they are not lost.
+    """
+    value = element.get('value', '') if element.name == 'input' else element.get_text(strip=True)
+    if not value:
+        return None
+
+    created_at = parse_date(value)
+    return format_timestamp(created_at) if created_at is not None else value
+
+
+def apply_color_tags(annotations: List[Annotation], color_tags: Dict[str, str]) -> List[Annotation]:
+    """
+    Returns the annotations with the tag of their highlight color (if any) set.
+    """
+    tags = {color.lower(): tag for color, tag in color_tags.items()}
+    return [
+        annotation.copy(update={'tag': tags[annotation.highlight_color.lower()]})
+        if annotation.highlight and annotation.highlight_color and annotation.highlight_color.lower() in tags
+        else annotation
+        for annotation in annotations
+    ]
+
+
+def _parse_header_page(header_parts: List[str]) -> Optional[int]:
+    # The page is only written after the separator, e.g., "Page:\xa0<page>". Personal documents may not have it.
+    if len(header_parts) < 2:
+        return None
+    page_parts = header_parts[1].strip().split(":\xa0", 1)
+    if page_parts[0] == "Page" and len(page_parts) > 1 and page_parts[1].strip().isdigit():
+        return int(page_parts[1].strip())
+    return None
+
+
+def merge_edition_annotations(old: List[Annotation], new: List[Annotation]) -> List[Annotation]:
+    """
+    Merges annotations from an old edition of a book into the annotations of a new edition.
+
+    Highlights that also exist in the new edition (based on fuzzy text matching) are dropped, while the remaining
+    ones are added with their location remapped to the new edition. The remapping uses the matched highlights as
+    anchors and interpolates linearly between the closest anchors. Page numbers are dropped for the remapped
+    annotations, since they are specific to an edition. Annotations without a location (e.g., in personal documents)
+    are added as they are.
+
+    Returns:
+        List[Annotation]: The merged annotations, sorted by location.
+    """
+    # Pairs of (old location, new location) from the highlights that exist in both editions.
+    anchors: List[Tuple[int, int]] = []
+    unmatched: List[Annotation] = []
+
+    for old_annotation in old:
+        match = None
+        if old_annotation.highlight:
+            for new_annotation in new:
+                if not new_annotation.highlight:
+                    continue
+                ratio = SequenceMatcher(None, old_annotation.highlight, new_annotation.highlight).ratio()
+                if ratio >= EDITION_MATCH_RATIO:
+                    match = new_annotation
+                    break
+
+        if match is not None:
+            # Highlights without a location cannot be used as anchors, but they still exist in the new edition.
+            if old_annotation.location is not None and match.location is not None:
+                anchors.append((old_annotation.location, match.location))
+        else:
+            unmatched.append(old_annotation)
+
+    anchors.sort()
+
+    def remap(location: int) -> int:
+        if not anchors:
+            return location
+
+        lower = None
+        upper = None
+        for anchor in anchors:
+            if anchor[0] <= location:
+                lower = anchor
+            elif upper is None:
+                upper = anchor
+
+        if lower is None:
+            return max(1, location + upper[1] - upper[0])
+        if upper is None or upper[0] == lower[0]:
+            return location + lower[1] - lower[0]
+
+        ratio = (location - lower[0]) / (upper[0] - lower[0])
+        return round(lower[1] + ratio * (upper[1] - lower[1]))
+
+    merged = list(new)
+    for annotation in unmatched:
+        if annotation.location is None:
+            merged.append(annotation)
+        else:
+            merged.append(annotation.copy(update={'location': remap(annotation.location), 'page': None}))
+
+    merged.sort(key=lambda annotation: (annotation.location is None, annotation.location or 0))
+    return merged
+
+
+def find_removed_annotations(
+    previous: List[Annotation],
+    current: List[Annotation],
+    removed: List[Annotation],
+    removed_at: str,
+) -> List[Annotation]:
+    """
+    Finds the highlights that have been exported previously, but no longer exist in the current annotations.
+
+    Highlights are matched by their location and text (ignoring whitespace differences). Newly removed highlights
+    are marked with the given date, while previously removed ones keep their date unless they exist again. Note
+    that annotations without a highlight (i.e., standalone notes) are not tracked, since their text may be edited.
+    Highlights that were redacted in the previous export (see `redact_highlights`) are not tracked either, since
+    their text cannot be compared.
+
+    Returns:
+        List[Annotation]: The removed highlights, sorted by location.
+    """
+    def key(annotation: Annotation) -> Tuple[Optional[int], str]:
+        return annotation.location, ' '.join(annotation.highlight.split())
+
+    current_keys = {key(annotation) for annotation in current if annotation.highlight}
+
+    result: Dict[Tuple[Optional[int], str], Annotation] = {}
+    for annotation in removed:
+        if annotation.highlight and key(annotation) not in current_keys:
+            result.setdefault(key(annotation), annotation)
+    for annotation in previous:
+        if annotation.highlight and annotation.highlight.endswith(REDACTION_MARKER):
+            continue
+        if annotation.highlight and key(annotation) not in current_keys:
+            result.setdefault(key(annotation), annotation.copy(update={'removed_at': removed_at}))
+
+    return sorted(result.values(), key=lambda annotation: annotation.location or 0)
+
+
+# Marker for the part of a highlight that is redacted because of the word limit.
+REDACTION_MARKER = '[…]'
+
+
+def sort_annotations(annotations: List[Annotation], sort_by: str) -> List[Annotation]:
+    """
+    Sorts the annotations by one of the `SORT_MODES`. The sort is stable, so annotations at the same position keep
+    their original order.
+    """
+    if sort_by == 'location':
+        return sorted(annotations, key=lambda a: (a.location is None, a.location or 0))
+    if sort_by == 'page':
+        return sorted(annotations, key=lambda a: (a.page is None, a.page or 0, a.location is None, a.location or 0))
+    return list(annotations)
+
+
+def dedupe_annotations(annotations: List[Annotation]) -> List[Annotation]:
+    """
+    Drops the highlights with exactly the same text and position as a previous one, which happens when a highlight
+    is edited on the device. The note of a dropped duplicate is kept if the remaining highlight does not have any.
+
+    Note that the same text at another position (e.g., a refrain) is a highlight on its own, so it is kept.
+    """
+    def key(annotation: Annotation) -> Tuple[Optional[int], Optional[int], str]:
+        return annotation.location, annotation.page, annotation.highlight
+
+    result: List[Annotation] = []
+    positions: Dict[Tuple[Optional[int], Optional[int], str], int] = {}
+    for annotation in annotations:
+        if not annotation.highlight:
+            result.append(annotation)
+            continue
+
+        position = positions.get(key(annotation))
+        if position is None:
+            positions[key(annotation)] = len(result)
+            result.append(annotation)
+        elif annotation.note and not result[position].note:
+            result[position] = result[position].copy(update={'note': annotation.note})
+    return result
+
+
+def count_highlight_words(annotations: List[Annotation]) -> int:
+    """
+    Counts the words of highlighted text in the given annotations.
+    """
+    return sum(len(annotation.highlight.split()) for annotation in annotations if annotation.highlight)
+
+
+def redact_highlights(annotations: List[Annotation], max_words: int) -> List[Annotation]:
+    """
+    Truncates the highlights so that their total number of words stays within the limit, keeping the highlights in
+    order (i.e., the ones at the end of the book are redacted first). The annotations themselves are kept, so that
+    their notes and locations are not lost.
+
+    Returns:
+        List[Annotation]: The annotations with the redacted highlights replaced by truncated copies.
+    """
+    result = []
+    remaining = max_words
+    for annotation in annotations:
+        words = annotation.highlight.split() if annotation.highlight else []
+        if len(words) <= remaining:
+            remaining -= len(words)
+            result.append(annotation)
+            continue
+
+        kept = ' '.join(words[:remaining] + [REDACTION_MARKER])
+        remaining = 0
+        result.append(annotation.copy(update={'highlight': kept}))
+    return result
+
+
+class AnnotationFetcher:
+    """
+    Fetches the annotations of the books to be exported, including the ones of their old editions (see
+    `merge_edition_annotations`).
+
+    With a concurrency of more than 1, the annotations are fetched ahead by a pool of workers, each with its own
+    client (i.e., session and pacing), while the results are still consumed one by one in the order of the books.
+    Otherwise, they are only fetched when requested.
+    """
+
+    def __init__(self, client: Client, old_editions: Dict[str, List[Book]], books: List[Book], concurrency: int = 1):
+        self.client = client
+        self.old_editions = old_editions
+
+        self._worker_clients: List[Client] = []
+        self._worker_state = threading.local()
+        self._lock = threading.Lock()
+        self._executor: Optional[ThreadPoolExecutor] = None
+        self._futures: Dict[str, Future] = {}
+        if concurrency > 1 and len(books) > 1:
+            self._executor = ThreadPoolExecutor(max_workers=concurrency)
+            self._futures = {book.asin: self._executor.submit(self._fetch_in_worker, book) for book in books}
+
+    def get(self, book: Book) -> Tuple[List[Annotation], float]:
+        """
+        Returns the annotations of the given book, together with the time spent on fetching them (in seconds).
+        """
+        future = self._futures.pop(book.asin, None)
+        if future is not None:
+            return future.result()
+        return self._fetch(self.client, book)
+
+    def close(self) -> None:
+        """
+        Cancels the pending fetches and closes the clients of the workers.
+        """
+        if self._executor is not None:
+            self._executor.shutdown(cancel_futures=True)
+            self._executor = None
+        for client in self._worker_clients:
+            client.close()
+        self._worker_clients = []
+
+    def _fetch_in_worker(self, book: Book) -> Tuple[List[Annotation], float]:
+        client = getattr(self._worker_state, 'client', None)
+        if client is None:
+            client = Client(self.client.auth, self.client.selectors, self.client.pacing, self.client.title_split)
+            self._worker_state.client = client
+            with self._lock:
+                self._worker_clients.append(client)
+        return self._fetch(client, book)
+
+    def _fetch(self, client: Client, book: Book) -> Tuple[List[Annotation], float]:
+        start_time = time.monotonic()
+        annotations = client.get_annotations(book)
+        for old_book in self.old_editions.get(book.asin, []):
+            annotations = merge_edition_annotations(client.get_annotations(old_book), annotations)
+        return annotations, time.monotonic() - start_time
+
+
+def export_actions(target: Path, export_index: ExportIndex, keywords: List[str]) -> Path:
+    """
+    Collects notes that contain any of the action keywords across all exported books into a single note.
+
+    Note that this reads the exported Markdown files, so that books that are not fetched in the current run
+    are included as well.
+
+    Returns:
+        Path: The path to the generated note.
+    """
+    lowered_keywords = [keyword.lower() for keyword in keywords]
+    actions_path = target.joinpath(ACTIONS_FILE_NAME)
+
+    with open(actions_path, 'w', encoding='utf-8') as f:
+        f.write('# Actions\n')
+
+        for item in export_index.books:
+            file_name = item.file_name if item.file_name else item.info.title
+            annotations = [
+                annotation for annotation in parse_markdown_annotations(target.joinpath(f'{file_name}.md'))
+                if annotation.note and any(keyword in annotation.note.lower() for keyword in lowered_keywords)
+            ]
+            if not annotations:
+                continue
+
+            f.write(f"\n## [{item.info.title}]({file_name.replace(' ', '%20')}.md)\n\n")
             for annotation in annotations:
-                highlight = None
-                highlight_color = None
-                note = None
-                page = None
-
-                # Retrieve the highlight
-                highlight_element = annotation.find(
-                    'span', {'id': 'highlight'})
-                if highlight_element:
-                    highlight = highlight_element.get_text(strip=True)
-
-                    # Retrieve the highlight header
-                    #
-                    # The header will be one of the following formats:
-                    # 1. "<color> annotation | Page: <page>" if there's a page number
-                    # 2. "<color> annotation | Location: <location>" if there's no page number
-                    #
-                    # However, since we can always get the location from another field, we won't retrieve the location
-                    # for the second case.
-                    highlight_header = annotation.find(
-                        'span', {'id': 'annotationHighlightHeader'}).get_text(strip=True)
-                    header_parts = highlight_header.split("|", 1)
-                    color_parts = header_parts[0].strip().split(" ", 1)
-                    page_parts = header_parts[1].strip().split(":\xa0", 1)
-
-                    # We can retrieve highlight color and potentially the page number here
-                    highlight_color = color_parts[0].strip()
-                    if page_parts[0] == "Page":
-                        page = int(page_parts[1].strip())
-
-                # Retrieve the note
-                #
-                # Note that the Kindle notebook page is a bit weird since it will always have the note element.
-                # In order to find out about its existence, we need to check the length.
-                note_str = annotation.find(
-                    'span', {'id': 'note'}).get_text(strip=True)
-                if note_str:
-                    note = note_str
-
-                    # If there is no highlight, check the page number using the note header
-                    if highlight is None:
-                        # Similar with the highlight header, it will be one of the following formats:
-                        # 1. "Note | Page: <page>" if there's a page number
-                        # 2. "Note | Location: <location>" if there's no page number
-                        #
-                        # Only the first case is useful.
-                        note_header = annotation.find(
-                            'span', {'id': 'annotationNoteHeader'}).get_text(strip=True)
-                        header_parts = note_header.split("|", 1)
-                        page_parts = header_parts[1].strip().split(":\xa0", 1)
-
-                        if page_parts[0] == "Page":
-                            page = int(page_parts[1].strip())
-
-                # Retrieve the location
-                location = int(annotation.find(
-                    'input', {'id': 'kp-annotation-location'})['value'])
-
-                result.append(Annotation(
-                    highlight=highlight,
-                    highlight_color=highlight_color,
-                    note=note,
-                    page=page,
-                    location=location
-                ))
+                note = annotation.note.replace('\n', ' ')
+                if annotation.location is None:
+                    # Some personal documents only have the page, which cannot be linked to.
+                    f.write(f'- [ ] {note} (page {annotation.page})\n')
+                    continue
+                f.write(f'- [ ] {note} ([location {annotation.location}]'
+                        f'(kindle://book?action=open&asin={item.info.asin}&location={annotation.location}))\n')
 
-        return result
+    return actions_path
+
+
+def export_single_file(path: Path, target: Path, export_index: ExportIndex) -> Path:
+    """
+    Combines the exported Markdown notes of all books into a single document, with a table of contents and a section
+    per book (i.e., the note without its frontmatter, and with its headings one level deeper).
+
+    Similar to `export_actions`, this reads the exported Markdown files, so that books that are not fetched in the
+    current run are included as well.
+
+    Returns:
+        Path: The path to the combined document.
+    """
+    sections = []
+    anchors: Counter = Counter()
+    for item in sorted(export_index.books, key=lambda item: item.info.title.casefold()):
+        file_name = item.file_name if item.file_name else item.info.title
+        book_path = target.joinpath(f'{file_name}.md')
+        if not book_path.is_file():
+            continue
+
+        with open(book_path, 'r', encoding='utf-8') as f:
+            _, content = split_frontmatter(f.read())
+
+        # Duplicate headings get a numbered anchor, e.g., "title-1" (as on GitHub).
+        anchor = heading_anchor(item.info.title)
+        anchors[anchor] += 1
+        if anchors[anchor] > 1:
+            anchor = f'{anchor}-{anchors[anchor] - 1}'
+        sections.append((item.info.title, anchor, _demote_headings(content.strip('\n'))))
+
+    with open(path, 'w', encoding='utf-8') as f:
+        f.write('# Kindle highlights\n\n')
+        f.write('## Contents\n\n')
+        for title, anchor, _ in sections:
+            f.write(f'- [{title}](#{anchor})\n')
+        for _, _, content in sections:
+            f.write(f'\n{content}\n')
+
+    return path
+
+
+# Columns of the CSV file for the bulk import into Readwise (see https://readwise.io/import_bulk).
+READWISE_CSV_COLUMNS = ['Highlight', 'Title', 'Author', 'URL', 'Note', 'Location', 'Date']
+READWISE_DATE_FORMAT = '%Y-%m-%d %H:%M:%S'
+
+
+def export_readwise_csv(path: Path, target: Path, export_index: ExportIndex) -> Path:
+    """
+    Writes the highlights of all books into a single CSV file in the layout of the Readwise bulk import (see
+    `READWISE_CSV_COLUMNS`), with the notebook URL of each book as its URL.
+
+    Same as `export_single_file`, this reads the exported Markdown files, so that books that are not fetched in the
+    current run are included as well. Notes without a highlight are skipped, since Readwise requires one.
+
+    Returns:
+        Path: The path to the CSV file.
+    """
+    with open(path, 'w', encoding='utf-8', newline='') as f:
+        writer = csv.writer(f)
+        writer.writerow(READWISE_CSV_COLUMNS)
+        for item in sorted(export_index.books, key=lambda item: item.info.title.casefold()):
+            file_name = item.file_name if item.file_name else item.info.title
+            book_path = target.joinpath(f'{file_name}.md')
+            if not book_path.is_file():
+                continue
+
+            for annotation in parse_markdown_annotations(book_path):
+                if not annotation.highlight:
+                    continue
+
+                created_at = parse_date(annotation.created_at) if annotation.created_at else None
+                writer.writerow([
+                    annotation.highlight,
+                    item.info.title,
+                    item.info.author,
+                    get_notebook_url(item.info.asin),
+                    annotation.note or '',
+                    annotation.location or '',
+                    created_at.strftime(READWISE_DATE_FORMAT) if created_at is not None else '',
+                ])
+
+    return path
+
+
+def _demote_headings(content: str) -> str:
+    """
+    Moves all headings (outside of code blocks) one level deeper.
+    """
+    lines = content.split('\n')
+    in_code_block = False
+    for i, line in enumerate(lines):
+        if line.startswith('```'):
+            in_code_block = not in_code_block
+        elif not in_code_block and re.match(r'^#{1,5} ', line):
+            lines[i] = f'#{line}'
+    return '\n'.join(lines)
 
 
 def export(
-    config: Config,
+    config: AmazonConfig,
     target: Path,
     renew: bool,
-) -> None:
+    kindle_config: Optional[Config] = None,
+    output_format: str = 'markdown',
+    decisions_path: Optional[Path] = None,
+    group_by: str = 'none',
+    frontmatter: Optional[FrontmatterConfig] = None,
+    new_only: bool = False,
+    book_filter: Optional[BookFilter] = None,
+    redact_over_limit: bool = False,
+    merge: bool = False,
+    sort_by: str = 'none',
+    dedupe: bool = False,
+    single_file: Optional[Path] = None,
+    verify_free_space: bool = False,
+    concurrency: int = 1,
+    readwise_csv: Optional[Path] = None,
+    unattended: bool = False,
+) -> RunSummary:
     """
     Exports kindle data
+
+    If a decisions file is given, it is used to answer the index prompts, and it is updated with the answers
+    for the books that are not covered by it yet.
+
+    If `new_only` is set, the index is fully trusted: books that are already indexed are skipped, and only new
+    books are fetched, both without any prompts (i.e., colliding file names get the ASIN if the policy is to prompt).
+
+    If a book filter is given, the books that do not match it are skipped without any prompts. The books that are
+    requested by their ASIN are always fetched without comparing them with the index, and the other indexed books are
+    left as they are (i.e., not reported as unchecked).
+
+    Books whose highlights exceed `max_highlight_words` are reported in the summary, and their highlights are
+    truncated to the limit if `redact_over_limit` is set.
+
+    If `merge` is set, only the annotations with new locations are appended to the existing Markdown notes (see
+    `merge_into_markdown`), instead of regenerating them.
+
+    The annotations are sorted by `sort_by` (see `SORT_MODES`), and exact-duplicate highlights are dropped if `dedupe`
+    is set (see `dedupe_annotations`).
+
+    If `single_file` is given, all exported Markdown notes are also combined into that file (see
+    `export_single_file`). Similarly, if `readwise_csv` is given, all highlights are also written into that file for
+    the bulk import into Readwise (see `export_readwise_csv`).
+
+    If `verify_free_space` is set, the export fails early if the target does not have enough free space for all books
+    in the library (see `estimate_export_size`).
+
+    If `concurrency` is more than 1, the annotations of that many books are fetched concurrently (see
+    `AnnotationFetcher`).
+
+    If `unattended` is set, nothing is prompted for (e.g., for scheduled runs): the modified and new books are fetched
+    (unless a decisions file covers them), and colliding file names get the ASIN if the policy is to prompt.
+
+    Returns:
+        RunSummary: The outcome for each book, which is also saved in the target directory.
     """
+    kindle_config = kindle_config if kindle_config is not None else Config()
+    decisions = Decisions.load_or_default(decisions_path) if decisions_path is not None else None
+    if unattended and decisions is None:
+        decisions = Decisions(default='fetch')
+    elif unattended and decisions.default is None:
+        # Note that the default is only used for this run, so that it is not saved into the decisions file.
+        decisions_path, decisions = None, decisions.copy(update={'default': 'fetch'})
+    product_cache = ProductCache.load_or_default(PRODUCT_CACHE_PATH) if kindle_config.fetch_product_metadata else None
+    template = None
+    if kindle_config.template:
+        template = load_markdown_template(Path(kindle_config.template).expanduser())
+
     auth = load_authenticator(config)
-    client = Client(auth)
+    client = Client(auth, kindle_config.selectors, kindle_config.get_pacing(config.country_code),
+                    kindle_config.title_split)
     book_library = client.get_books()
+    if verify_free_space:
+        ensure_free_space({target: estimate_export_size(book_library, kindle_config.download_covers)})
 
     index_file_path = target.joinpath(EXPORT_INDEX_FILE_NAME)
     export_index = ExportIndex.load_or_default(index_file_path)
 
+    # Different books (e.g., different editions) may share the same title, which would make them overwrite each
+    # other's file. Hence, we need to find such titles (after sanitizing them) beforehand.
+    title_counts = Counter(sanitize_file_name(book.title).casefold() for book in book_library)
+
+    # Old editions are merged into the new ones, keyed by the ASIN of the new edition.
+    old_editions: Dict[str, List[Book]] = {}
     for book in book_library:
-        if export_index.check_book(book, skip_check=renew):
-            annotations = client.get_annotations(book)
+        new_asin = kindle_config.edition_aliases.get(book.asin)
+        if new_asin:
+            old_editions.setdefault(new_asin, []).append(book)
 
-            # Note that we will generate the book name using its title and use the ".md" extension since it is
-            # a Markdown file.
-            book_path = target.joinpath(f"{book.title}.md")
+    # Books that are explicitly requested by their ASIN are always fetched, without any prompts.
+    requested_asins = set(book_filter.asins) if book_filter is not None else set()
+    for asin in requested_asins - {book.asin for book in book_library}:
+        echo_warning(f'Book {asin} is not found in the library')
 
-            export_to_markdown(book_path, book, annotations=annotations)
+    # Colliding file names get the ASIN instead of prompting in the modes that promise no prompts (i.e., unattended,
+    # new books only, or requested books, including the retried ones).
+    collision_policy = kindle_config.file_name_collision
+    if (unattended or new_only or requested_asins) and collision_policy == 'prompt':
+        collision_policy = 'asin'
+
+    # The books to be fetched are selected first (which may prompt), so that their annotations can be fetched
+    # concurrently, while the books are still written one by one.
+    summary = RunSummary()
+    selected: List[Tuple[Book, Optional[ExportItem]]] = []
+    for book in book_library:
+        if book.asin in kindle_config.edition_aliases:
+            echo(f'Skipping book {book.title} ({book.asin}), which is merged into '
+                 f'{kindle_config.edition_aliases[book.asin]}')
+            summary.add(book, 'skipped')
+            continue
+
+        excluded = book.doc_type == PERSONAL_DOC_TYPE and not kindle_config.personal_documents
+        if excluded or (book_filter is not None and not book_filter.matches(book)):
+            # The book is still in the library, so it should not be reported as unchecked.
+            if export_index.get_item(book.asin) is not None:
+                export_index.get_item(book.asin).checked = True
+            summary.add(book, 'skipped')
+            continue
+
+        # Keep a snapshot of the indexed item, so that it can be reverted if the export fails.
+        snapshot = export_index.get_item(book.asin)
+        snapshot = snapshot.copy(deep=True) if snapshot is not None else None
+
+        if new_only and snapshot is not None:
+            export_index.get_item(book.asin).checked = True
+            summary.add(book, 'skipped')
+            continue
+
+        skip_check = renew or new_only or book.asin in requested_asins
+        if not export_index.check_book(book, skip_check=skip_check, decisions=decisions):
+            summary.add(book, 'skipped')
+            continue
+
+        selected.append((book, snapshot))
+
+    fetcher = AnnotationFetcher(client, old_editions, [book for book, _ in selected], concurrency)
+    try:
+        for i, (book, snapshot) in enumerate(selected):
+            start_time = time.monotonic()
+            try:
+                annotations, fetch_duration_s = fetcher.get(book)
+                # Count the time spent on fetching (possibly ahead) towards the book, instead of the time spent on
+                # waiting for it.
+                start_time = time.monotonic() - fetch_duration_s
+                if dedupe:
+                    annotations = dedupe_annotations(annotations)
+                annotations = sort_annotations(annotations, sort_by)
+
+                # The title may change between runs (e.g., if Amazon tweaks it, or it is split differently), which
+                # should not rename the exported file. Older index entries do not record the file name yet, which is
+                # then the indexed title as is (if that file exists and the title is not ambiguous), as written before
+                # the file names were sanitized.
+                title_changed = snapshot is not None and snapshot.info.title != book.title
+                if snapshot is not None and snapshot.file_name is None:
+                    legacy_name = snapshot.info.title
+                    legacy_file = target.joinpath(f'{legacy_name}.{OUTPUT_FORMATS[output_format]}')
+                    if title_counts[sanitize_file_name(legacy_name).casefold()] <= 1 and legacy_file.exists():
+                        export_index.get_item(book.asin).file_name = legacy_name
+                    elif title_changed:
+                        export_index.get_item(book.asin).file_name = sanitize_file_name(legacy_name)
+
+                # Note that we will generate the book name using its title (unless it needs to be disambiguated).
+                # The file extension depends on the output format.
+                file_name = export_index.resolve_file_name(
+                    book, ambiguous=title_counts[sanitize_file_name(book.title).casefold()] > 1,
+                    collision_policy=collision_policy)
+
+                warnings = []
+                if title_changed:
+                    warnings.append(f"title changed from '{snapshot.info.title}' to '{book.title}', keeping the file "
+                                    f"name '{file_name}'")
+
+                removed_annotations = None
+                if kindle_config.keep_deleted and output_format == 'markdown':
+                    previous_file = target.joinpath(f'{file_name}.md')
+                    removed_annotations = find_removed_annotations(
+                        parse_markdown_annotations(previous_file), annotations,
+                        parse_markdown_removed_annotations(previous_file), format_simple_date(current_datetime()))
+
+                # Note that this needs to be done after finding the removed annotations, which compares the full text.
+                words = count_highlight_words(annotations)
+                if 0 < kindle_config.max_highlight_words < words:
+                    warning = f'highlights have {words} words, over the limit of {kindle_config.max_highlight_words}'
+                    if redact_over_limit:
+                        annotations = redact_highlights(annotations, kindle_config.max_highlight_words)
+                        warning += ' (redacted)'
+                    warnings.append(warning)
+                truncated = sum(1 for annotation in annotations if annotation.truncated)
+                if truncated:
+                    warnings.append(f'{truncated} highlight(s) are truncated by the clipping limit')
+                warning = '; '.join(warnings) or None
+
+                annotations = apply_color_tags(annotations, kindle_config.color_tags)
+                annotation_groups = None
+                if group_by == 'color':
+                    annotation_groups = group_annotations_by_color(annotations, kindle_config.color_meanings)
+
+                # The product metadata is only added to the exported copy of the book, so that it does not affect the
+                # comparison with the library data in the next runs.
+                exported_book = book
+                # Personal documents do not have any product page.
+                if product_cache is not None and book.doc_type != PERSONAL_DOC_TYPE:
+                    metadata = product_cache.products.get(book.asin)
+                    if metadata is None:
+                        metadata = client.get_product_metadata(book)
+                        if metadata is not None:
+                            product_cache.products[book.asin] = metadata
+                    if metadata is not None:
+                        exported_book = book.copy(update=metadata.dict(exclude_none=True))
+
+                if kindle_config.download_covers:
+                    cover_path = download_cover(book, target)
+                    if cover_path is not None:
+                        exported_book = exported_book.copy(update={'cover_path': cover_path})
+
+                output_file = target.joinpath(f'{file_name}.md')
+                if merge and output_format == 'markdown' and output_file.is_file():
+                    count = merge_into_markdown(output_file, exported_book, annotations, get_notebook_url(book.asin),
+                                                kindle_config.dataview_fields)
+                else:
+                    export_book(target, file_name, output_format, exported_book, annotations=annotations,
+                                notebook_url=get_notebook_url(book.asin), annotation_groups=annotation_groups,
+                                frontmatter=frontmatter, removed_annotations=removed_annotations, template=template,
+                                managed_sections=kindle_config.managed_sections,
+                                dataview_fields=kindle_config.dataview_fields)
+                    count = len(annotations)
+            except (AuthError, click.Abort) as e:
+                # The remaining books would fail in the same way, or the user has aborted the run (e.g., at the file
+                # name prompt). The books that are not exported yet are reverted, so that they are fetched again in
+                # the next run.
+                for pending_book, pending_snapshot in selected[i:]:
+                    export_index.revert_item(pending_book.asin, pending_snapshot)
+                summary.add(book, 'failed', duration_s=time.monotonic() - start_time, error=str(e) or 'aborted')
+                raise
+            except Exception as e:  # pylint: disable=broad-except
+                # Continue with the remaining books, so that a single failure does not abort the entire run.
+                echo_warning(f'Failed to export book {book.title} ({book.asin}). Error: {e}')
+                export_index.revert_item(book.asin, snapshot)
+                summary.add(book, 'failed', duration_s=time.monotonic() - start_time, error=str(e))
+                continue
+
+            summary.add(book, 'exported', count, time.monotonic() - start_time, warning=warning)
 
             # Print some info if all books are expected to be exported.
-            if renew:
+            if renew or new_only:
                 echo(f'Exported book: {book}')
+    finally:
+        fetcher.close()
+
+        # Save back the index (and the rest of the progress), even if the run is stopped midway (e.g., by an auth
+        # error), so that the books that have been exported are not fetched again in the next run.
+        export_index.save(index_file_path)
+        if decisions is not None and decisions_path is not None:
+            decisions.save(decisions_path)
+        if product_cache is not None:
+            product_cache.save(PRODUCT_CACHE_PATH)
+        summary.save(target.joinpath(RUN_SUMMARY_FILE_NAME))
+        client.close()
 
     # Log warning(s) for book(s) that are left unchecked.
-    if not renew:
+    if not renew and not requested_asins:
         export_index.warn_unchecked_books()
 
-    # Save back the index
-    export_index.save(index_file_path)
+    if kindle_config.action_keywords and output_format == 'markdown':
+        export_actions(target, export_index, kindle_config.action_keywords)
+
+    if single_file is not None and output_format == 'markdown':
+        export_single_file(single_file, target, export_index)
+    if readwise_csv is not None and output_format == 'markdown':
+        export_readwise_csv(readwise_csv, target, export_index)
+
+    summary.echo()
+    return summary
+
+
+def list_pending(
+    config: AmazonConfig,
+    target: Path,
+    kindle_config: Optional[Config] = None,
+    book_filter: Optional[BookFilter] = None,
+) -> List[Dict[str, Any]]:
+    """
+    Lists the books that `export` would prompt for (i.e., the ones that are new or modified according to the index),
+    without prompting, fetching their annotations, or updating the index.
+
+    This is meant for external tools, which can answer the prompts with a decisions file (see `Decisions`).
+
+    Returns:
+        List[Dict[str, Any]]: The book data and the reason for each book, i.e., 'new' or 'modified' (together with
+            the indexed data).
+    """
+    kindle_config = kindle_config if kindle_config is not None else Config()
+    export_index = ExportIndex.load_or_default(target.joinpath(EXPORT_INDEX_FILE_NAME))
+
+    pending = []
+    for book in list_books(config, kindle_config):
+        if book.asin in kindle_config.edition_aliases:
+            continue
+        if book.doc_type == PERSONAL_DOC_TYPE and not kindle_config.personal_documents:
+            continue
+        if book_filter is not None and not book_filter.matches(book):
+            continue
+
+        reason = export_index.get_pending_reason(book)
+        if reason is None:
+            continue
+
+        entry: Dict[str, Any] = {'reason': reason, **book.dict(exclude_none=True)}
+        if reason == 'modified':
+            entry['indexed'] = export_index.get_item(book.asin).info.dict(exclude_none=True)
+        pending.append(entry)
+    return pending
+
+
+def list_books(config: AmazonConfig, kindle_config: Optional[Config] = None) -> List[Book]:
+    """
+    Fetches the books in the Kindle library, without exporting anything.
+    """
+    kindle_config = kindle_config if kindle_config is not None else Config()
+
+    auth = load_authenticator(config)
+    client = Client(auth, kindle_config.selectors, kindle_config.get_pacing(config.country_code),
+                    kindle_config.title_split)
+    try:
+        return client.get_books()
+    finally:
+        client.close()
+
+
+def show(
+    config: AmazonConfig,
+    asin: str,
+    kindle_config: Optional[Config] = None,
+    group_by: str = 'none',
+    frontmatter: Optional[FrontmatterConfig] = None,
+) -> None:
+    """
+    Fetches the annotations of a single book and prints them as Markdown to stdout.
+
+    Unlike `export`, this does not write to the target directory or update the index.
+    """
+    kindle_config = kindle_config if kindle_config is not None else Config()
+
+    auth = load_authenticator(config)
+    client = Client(auth, kindle_config.selectors, kindle_config.get_pacing(config.country_code),
+                    kindle_config.title_split)
+    try:
+        book = next((book for book in client.get_books() if book.asin == asin), None)
+        if book is None:
+            raise ConfigError(f'Book {asin} is not found in the Kindle library')
+
+        annotations = apply_color_tags(client.get_annotations(book), kindle_config.color_tags)
+        annotation_groups = None
+        if group_by == 'color':
+            annotation_groups = group_annotations_by_color(annotations, kindle_config.color_meanings)
 
-    # Close after completing the export
-    client.close()
+        stdout = click.get_text_stream('stdout')
+        book = format_book_dates(book)
+        if kindle_config.template:
+            template = load_markdown_template(Path(kindle_config.template).expanduser())
+            write_markdown_template(stdout, template, book, annotations=annotations,
+                                    notebook_url=get_notebook_url(book.asin), annotation_groups=annotation_groups,
+                                    frontmatter=frontmatter, dataview_fields=kindle_config.dataview_fields)
+        else:
+            write_markdown(stdout, book, annotations=annotations, notebook_url=get_notebook_url(book.asin),
+                           annotation_groups=annotation_groups, frontmatter=frontmatter,
+                           dataview_fields=kindle_config.dataview_fields)
+    finally:
+        client.close()
@@ -5,24 +5,32 @@ This module provides functions and utilities for handling Notion data, including
 exporting, converting, and organizing Notion content.
 """
 
+import hashlib
+import json
 import os
 import re
 import shutil
+import sqlite3
 import zipfile
-from datetime import datetime
+import zlib
+from concurrent.futures import ThreadPoolExecutor, as_completed
 from pathlib import Path
-from typing import Optional
+from typing import Callable, Optional
+from urllib.parse import unquote
 
 import chardet
 import pandas
 import yaml
 
-from click import echo
-from pydantic import BaseModel, Field  # pylint: disable=no-name-in-module
+from click import echo, progressbar
+from pydantic import BaseModel, Field, ValidationError  # pylint: disable=no-name-in-module
 
-from ncli.utils import prompt_user
+from ncli import constants
+from ncli.errors import IoError, ParseError
+from ncli.utils import echo_table, echo_warning, ensure_free_space, format_size, heading_anchor, prompt_user
 
 TMP_DIR = "/tmp/ncli"
+TMP_EXPORT_DIR_PREFIX = "notion-export-"
 INDEX_FILE_NAME = "index.yaml"
 
 # We assume all page files are formatted in UTF-8.
@@ -36,6 +44,10 @@ UUID_32_PATTERN = r"([0-9a-f]{32})"
 EXPORT_FULL_NAME_RE = re.compile(rf"^{UUID_36_PATTERN}_Export-{UUID_36_PATTERN}\.zip$")
 EXPORT_PART_NAME_RE = re.compile(rf"^Export-{UUID_36_PATTERN}-Part-[0-9]+\.zip$")
 
+# Maximum number of nested zip levels, e.g., when the part zip files are wrapped in another zip file (which happens
+# for very large exports, or when the export is zipped again after downloading it).
+MAX_NESTED_ZIP_DEPTH = 3
+
 # Name of export item, except assets (e.g., images).
 EXPORT_ITEM_NAME_RE = re.compile(rf"^(.*) {UUID_32_PATTERN}(_all)?(?:\.(md|csv))?$")
 
@@ -52,14 +64,68 @@ LINK_ITEM_NAME_RE = re.compile(r"(\[.+\]\(| \(|\/)([^/\n\0]+)%20([0-9a-f]{32})")
 
 ASSET_IMAGE_LINK_RE = re.compile(r"\!\[[^\n\0]+\]\([^\n\0]+\)")
 
+# Target of a markdown link, i.e., "](<href>)". This is used to find the full relative path of a link, as opposed
+# to `LINK_ITEM_NAME_RE` which matches each path segment individually.
+LINK_HREF_RE = re.compile(r"\]\(([^()\n\0]+)\)")
+LINK_HREF_UID_RE = re.compile(r"%20([0-9a-f]{32})(?:\.md|\.csv)?$")
+
+# Absolute link to a Notion page, e.g., "](https://www.notion.so/workspace/Title-<uid>?pvs=4)". Pages that are part of
+# the export are linked to their exported file instead. Note that the fragment (i.e., a block within the page) is
+# dropped in that case, since it cannot be resolved in the exported file.
+NOTION_URL_LINK_RE = re.compile(r"\]\((https?://(?:www\.)?notion\.so/[^()\s]*?([0-9a-f]{32})(?:[?#][^()\s]*)?)\)")
+
+# How to handle links to pages that are referenced from a location other than where the page is exported.
+# Notion only exports such pages once, which leaves links from the other locations dead.
+#
+# - none: keep the link as is.
+# - relative: rewrite the link to point to the actual location of the page.
+# - symlink: create a symlink at the linked location that points to the actual file.
+# - stub: create a small note at the linked location that links to the actual page.
+ALIAS_MODES = ("none", "relative", "symlink", "stub")
+
+# Inline databases (and linked views) are exported as a line with a link to the CSV file, which is not readable
+# within the page itself.
+#
+# - link: keep the link as is.
+# - table: add a summary table with the first rows of the database below the link.
+INLINE_DATABASE_MODES = ("link", "table")
+INLINE_DATABASE_LINK_RE = re.compile(r"^\[[^\]\n\0]+\]\(([^()\n\0]+\.csv)\)$", re.MULTILINE)
+INLINE_DATABASE_MAX_ROWS = 10
+
 # Note that this is length before extra prefix (e.g., database id) and suffix (e.g., because of duplicate names).
 MAX_PAGE_NAME_LENGTH = 128
 
+# Headings that start a new section when splitting large pages (i.e., H1 and H2).
+SECTION_HEADING_RE = re.compile(r"^#{1,2} (.+)$")
+
+# Link to a heading within a page, e.g., "](Page.md#Heading)", which is pointed to the section file instead if the
+# page is split.
+LINK_ANCHOR_RE = re.compile(r"\]\(([^()#\n\0]+\.md)#([^()\n\0]+)\)")
+
+# Number of workers used for extracting zip parts and copying files into the target directory.
+# This follows the default of `ThreadPoolExecutor`, since the work is mostly IO-bound.
+DEFAULT_WORKERS = min(32, (os.cpu_count() or 1) + 4)
+
+# The search indices of the exported data are cached outside of the target, keyed by the target path.
+SEARCH_INDEX_DIR = constants.BASE_PATH.joinpath("notion-search")
+SEARCH_TOKEN_RE = re.compile(r"\w+")
+# How much more a match in the title of a page counts than a match in its content.
+SEARCH_TITLE_WEIGHT = 5
+SEARCH_SNIPPET_LENGTH = 120
+
 
 def export(
     source: Path,
     target: Path,
     force: bool,
+    workers: int = DEFAULT_WORKERS,
+    alias_mode: str = "none",
+    inline_database_mode: str = "link",
+    dry_run: bool = False,
+    flatten_root: int = 0,
+    sqlite_path: Optional[Path] = None,
+    split_threshold: int = 0,
+    verify_free_space: bool = False,
 ) -> None:
     """
     Performs the export operation.
@@ -68,19 +134,45 @@ def export(
         source (Path): The path to the source zip file.
         target (Path): The path to the target directory for the export.
         force (bool): A flag to indicate whether to overwrite the target directory if it exists.
+        workers (int): The maximum number of threads used for extraction and copying.
+        alias_mode (str): How to handle links to pages outside of the linking location. See `ALIAS_MODES`.
+        inline_database_mode (str): How to render inline databases in a page. See `INLINE_DATABASE_MODES`.
+        dry_run (bool): If True, only prints the planned target structure without writing the target.
+        flatten_root (int): The number of leading directory levels to drop (e.g., the top-level wrapper folders of
+            a workspace export), so that the target starts at the content.
+        sqlite_path (Path): If given, all exported databases are also loaded into a SQLite file at this path, which
+            is overwritten if it exists. See `_write_sqlite`.
+        split_threshold (int): If positive, pages larger than this (in bytes) are split into a file per H1/H2
+            section, while the page itself becomes an index of the sections. See `_split_page`.
+        verify_free_space (bool): If True, fails early if the temporary directory or the target does not have enough
+            free space for the extracted data. See `estimate_required_space`.
 
     Returns:
         None, raises exceptions in case of errors.
     """
+    if verify_free_space and source.exists():
+        ensure_free_space(estimate_required_space(source, target))
+
     root_dir = Directory()
-    export_uid, export_dir, exported_data_dir = _validate_source(source)
-    _build_directory_info(root_dir, exported_data_dir)
+    export_uid, export_dir, exported_data_dir = _validate_source(source, workers)
+    for data_dir in _find_flattened_roots(exported_data_dir, flatten_root):
+        _build_directory_info(root_dir, data_dir)
 
     # Create mapping of entries by their uid. This is to help with linking later.
     # This will also set up name_suffix on each entry if needed (for name dedup in the same directory).
     entries_by_uid: dict[str, Entry] = {}
     _build_entries_map_by_uid(entries_by_uid, root_dir)
 
+    # Report the size of each top-level section, to help spotting unexpectedly large ones.
+    size_report = _build_size_report(root_dir)
+    _print_size_report(size_report)
+
+    if dry_run:
+        echo(f"Planned structure for '{target}':")
+        _print_target_tree(root_dir, 1)
+        shutil.rmtree(export_dir)
+        return
+
     if target.exists():
         if not force and not prompt_user(
             f"Target path '{target}' already exists. Delete current data?"
@@ -96,7 +188,28 @@ def export(
 
     echo(f"Exporting data to '{target}' ...")
     os.makedirs(target, exist_ok=True)
-    _build_target_directory(target, export_uid, root_dir, entries_by_uid)
+
+    # The directory structure and index files are created upfront, while copying files (and fixing their links)
+    # is collected as independent tasks to be executed by the worker pool.
+    tasks: list[Callable[[], None]] = []
+    aliases: dict[Path, Path] = {}
+    split_sections: dict[Path, list[tuple[str, Path]]] = {}
+    # Links are relative to the original structure. Hence, they need to be rewritten if it is flattened.
+    relink = flatten_root > 0
+    _build_target_directory(
+        target, export_uid, root_dir, entries_by_uid, tasks, alias_mode, aliases, inline_database_mode, relink,
+        split_threshold, split_sections,
+    )
+    _run_tasks(tasks, workers, "Copying files")
+
+    # Aliases are only created after all files have been copied, to avoid writing through a symlink or having
+    # a stub note overwritten by the actual page.
+    _create_aliases(target, aliases, alias_mode)
+
+    _write_size_report(target, size_report)
+
+    if sqlite_path is not None:
+        _write_sqlite(target, sqlite_path, entries_by_uid)
 
     # Clean up the tmp directory
     shutil.rmtree(export_dir)
@@ -104,7 +217,115 @@ def export(
     echo("Export operation has been executed successfully")
 
 
-def _validate_source(path: Path) -> tuple[str, Path, Path]:
+def estimate_required_space(source: Path, target: Path) -> dict[Path, int]:
+    """
+    Estimates the number of bytes written to the temporary directory (i.e., the part zip files and their content)
+    and to the target (i.e., the content) by exporting the given source zip file.
+
+    Note that only the part zip files at the top level of the source are inspected, so the content of nested zip files
+    is estimated by their compressed size.
+    """
+    archive_size = 0
+    content_size = 0
+    with zipfile.ZipFile(source, "r") as zip_ref:
+        for info in zip_ref.infolist():
+            archive_size += info.file_size
+            if not EXPORT_PART_NAME_RE.match(info.filename):
+                content_size += info.file_size
+                continue
+            with zip_ref.open(info) as part_file, zipfile.ZipFile(part_file) as part_ref:
+                content_size += sum(part_info.file_size for part_info in part_ref.infolist())
+
+    return {Path(TMP_DIR): archive_size + content_size, target: content_size}
+
+
+def find_leftover_exports() -> list[Path]:
+    """
+    Finds temporary export directories that are left behind (e.g., because an export was interrupted).
+    """
+    tmp_dir = Path(TMP_DIR)
+    if not tmp_dir.is_dir():
+        return []
+    return sorted(tmp_dir.glob(f"{TMP_EXPORT_DIR_PREFIX}*"))
+
+
+def _run_tasks(tasks: list[Callable[[], None]], workers: int, label: str) -> None:
+    """
+    Runs the given tasks using a bounded thread pool, while showing a progress bar.
+
+    The first error raised by any of the tasks will be re-raised after the remaining tasks are cancelled.
+    """
+    if not tasks:
+        return
+
+    with ThreadPoolExecutor(max_workers=max(1, workers)) as executor:
+        futures = [executor.submit(task) for task in tasks]
+        try:
+            with progressbar(length=len(futures), label=label) as bar:
+                for future in as_completed(futures):
+                    future.result()
+                    bar.update(1)
+        except BaseException:
+            for future in futures:
+                future.cancel()
+            raise
+
+
+def _extract_zip(path: Path, target: Path) -> list[Path]:
+    """
+    Extracts a zip file to the target directory.
+
+    Parent directories are created upfront with `exist_ok`, since multiple zip parts may contain the same
+    directories and they can be extracted concurrently.
+
+    Files that are already extracted (i.e., with the same size and CRC) are skipped, so that an interrupted
+    extraction can be resumed without starting over.
+
+    Returns:
+        list[Path]: The extracted files at the top level of the zip file (e.g., nested zip files).
+    """
+    top_level_files = []
+    with zipfile.ZipFile(path, "r") as zip_ref:
+        for info in zip_ref.infolist():
+            member_dir = info.filename if info.is_dir() else os.path.dirname(info.filename)
+            if member_dir:
+                os.makedirs(target.joinpath(member_dir), exist_ok=True)
+            if info.is_dir():
+                continue
+
+            member_path = target.joinpath(info.filename)
+            if not member_dir:
+                top_level_files.append(member_path)
+            if not _is_extracted(member_path, info):
+                zip_ref.extract(info, target)
+    return top_level_files
+
+
+def _extract_part_zip(path: Path, target: Path) -> list[Path]:
+    """
+    Extracts a part zip file to the target directory.
+
+    Returns:
+        list[Path]: The part zip files that are nested in the given one, if any.
+    """
+    return [member for member in _extract_zip(path, target) if EXPORT_PART_NAME_RE.match(member.name)]
+
+
+def _is_extracted(path: Path, info: zipfile.ZipInfo) -> bool:
+    """
+    Checks whether a zip member has been fully extracted to the given path, based on its size and CRC.
+    """
+    if not path.is_file() or path.stat().st_size != info.file_size:
+        return False
+
+    crc = 0
+    with open(path, "rb") as f:
+        for chunk in iter(lambda: f.read(1 << 20), b""):
+            crc = zlib.crc32(chunk, crc)
+    return crc == info.CRC
+
+
+def _validate_source(path: Path, workers: int = DEFAULT_WORKERS) -> tuple[str, Path, Path]:
     """
     Validates the source zip file and extracts it to a temporary directory.
 
@@ -114,55 +335,78 @@ def _validate_source(path: Path) -> tuple[str, Path, Path]:
       - Path to the exported notion data (after extracting all zip parts)
     """
     if not path.exists():
-        raise ValueError("Source path does not exist")
+        raise IoError("Source path does not exist")
     if not EXPORT_FULL_NAME_RE.match(path.name):
-        raise ValueError("Invalid export file name")
+        raise ParseError("Invalid export file name")
 
-    # Prepare the export directory
-    date_string = datetime.now().strftime("%Y-%m-%d-%H-%M-%S")
-    export_dir = Path(TMP_DIR).joinpath(f"notion-export-{date_string}")
+    # Prepare the export directory. It is named after the source, so that the extraction of a previous run (e.g.,
+    # one that was interrupted) is resumed instead of started over.
+    export_dir = Path(TMP_DIR).joinpath(f"{TMP_EXPORT_DIR_PREFIX}{path.stem}")
     if export_dir.exists():
         if export_dir.is_dir():
-            echo(f"Removing dir '{export_dir}' to avoid conflict")
-            shutil.rmtree(export_dir)
+            echo(f"Resuming the extraction in '{export_dir}'")
         else:
             echo(f"Removing file '{export_dir}' to avoid conflict")
             os.remove(export_dir)
     os.makedirs(export_dir, exist_ok=True)
 
-    with zipfile.ZipFile(path, "r") as zip_ref:
-        zip_ref.extractall(export_dir)
-
+    # The part zip files may be wrapped in other zip files, which are unwrapped level by level.
     part_zip_files: list[Path] = []
-    for part_zip_file in export_dir.iterdir():
-        if not part_zip_file.is_file() or not part_zip_file.name.endswith(".zip"):
-            raise ValueError(f"found unexpected non-zip file: {part_zip_file}")
-        part_zip_files.append(part_zip_file)
+    archives = [path]
+    for _ in range(MAX_NESTED_ZIP_DEPTH):
+        nested_archives = []
+        for archive in archives:
+            for member in _extract_zip(archive, export_dir):
+                if EXPORT_PART_NAME_RE.match(member.name):
+                    part_zip_files.append(member)
+                elif member.name.endswith(".zip"):
+                    nested_archives.append(member)
+                else:
+                    raise ParseError(f"found unexpected non-zip file: {member}")
+        archives = nested_archives
+        if not archives:
+            break
+    else:
+        raise ParseError(f"found more than {MAX_NESTED_ZIP_DEPTH} levels of nested zip files")
     if len(part_zip_files) == 0:
-        raise ValueError("unable to find any export part zip file")
+        raise ParseError("unable to find any export part zip file")
 
     export_uid = None
     for path in part_zip_files:
         match = EXPORT_PART_NAME_RE.match(path.name)
-        if not match:
-            raise ValueError(f"part zip file does not match name format: {path}")
-
-        echo(f"Extracting: {path.name}")
 
         # Sanity check for consistency.
         uid = match.group(1)
         if export_uid is not None and export_uid != uid:
-            raise ValueError(
+            raise ParseError(
                 f"inconsistent export uid. prev: {export_uid}. cur: {uid}."
             )
         export_uid = uid
 
-        with zipfile.ZipFile(path, "r") as zip_ref:
-            zip_ref.extractall(export_dir)
-
+    # A previous run may have been interrupted after renaming the exported data dir (see below), in which case it is
+    # renamed back, so that its files are reused.
     exported_data_dir = export_dir.joinpath(f"Export-{export_uid}")
+    renamed_data_dir = export_dir.joinpath(f"Export {export_uid.replace('-', '')}")
+    if renamed_data_dir.exists() and not exported_data_dir.exists():
+        os.rename(renamed_data_dir, exported_data_dir)
+
+    # Parts may contain further part zip files at their top level, which are extracted in the next round.
+    for _ in range(MAX_NESTED_ZIP_DEPTH):
+        nested_part_zip_files: list[Path] = []
+        # Bind the path eagerly, otherwise all lambdas will refer to the last part.
+        _run_tasks(
+            [lambda p=p: nested_part_zip_files.extend(_extract_part_zip(p, export_dir)) for p in part_zip_files],
+            workers,
+            f"Extracting {len(part_zip_files)} part(s)",
+        )
+        part_zip_files = nested_part_zip_files
+        if not part_zip_files:
+            break
+    else:
+        raise ParseError(f"found more than {MAX_NESTED_ZIP_DEPTH} levels of nested zip files")
+
     if not exported_data_dir.exists():
-        raise ValueError(f"Unexpected: exported dir {exported_data_dir} does not exist")
+        raise ParseError(f"Unexpected: exported dir {exported_data_dir} does not exist")
 
     # Rename to follow semantic with other files
     export_uid = export_uid.replace("-", "")
@@ -174,6 +418,25 @@ def _validate_source(path: Path) -> tuple[str, Path, Path]:
     return export_uid, export_dir, expected_export_data_dir
 
 
+def _find_flattened_roots(path: Path, levels: int) -> list[Path]:
+    """
+    Returns the directories that are left after dropping the given number of leading directory levels.
+
+    Note that files above these directories (e.g., the page of a wrapper folder) are not exported.
+    """
+    roots = [path]
+    for _ in range(levels):
+        next_roots = []
+        for root in roots:
+            for child in sorted(root.iterdir()):
+                if child.is_dir():
+                    next_roots.append(child)
+                else:
+                    echo_warning(f"skipped '{child.relative_to(path)}' since it is above the flattened root.")
+        roots = next_roots
+    return roots
+
+
 class Entry:
     """
     A class representing an entry in the export directory structure.
@@ -201,6 +464,9 @@ class Entry:
     # pages that have ID column (since the file name will be prefixed with their unique ID).
     name_suffix: Optional[str]
 
+    # Path of the exported file in the target directory. This is only set once the target directory is built.
+    target_path: Optional[Path] = None
+
     def __init__(
         self, uid: str, name: str, path: Path, name_ori=None, name_suffix=None
     ):
@@ -222,33 +488,9 @@ class Entry:
         """
         Returns exported name. Note that this does NOT include any extension.
         """
-        # Whitespace at the end of the name (before extension) may not work well on some
-        # system or app (e.g., Obsidian).
-        #
-        # Also, we will remove unexpected characters like slash and null char.
-        exported_name = self.name.strip()
-
-        # Non-printable char: \0
-        # Forbidden in Unix: /
-        # Forbidden in Markdown/Obsidian linking: #, ^, [, ], |
-        # Forbidden by Obsidian: \, :
-        #
-        # Note that Windows could have more forbidden chars and reserved words.
-        # Reference: https://en.wikipedia.org/wiki/Filename
-
-        exported_name = re.sub(r"[\0#^]", "", exported_name)
-        exported_name = re.sub(r"\/", " or ", exported_name)
-        exported_name = re.sub(r"\[", "- ", exported_name)
-        exported_name = re.sub(r"[\]:]", " -", exported_name)
-        exported_name = re.sub(r"\|", "-", exported_name)
-
-        # Replace multiple whitespaces with a single whitespace
-        exported_name = re.sub(r"\s+", " ", exported_name)
-
         # Note that the actual file name may still have extra prefix (if there's any database ID)
         # and suffix (if there's duplicate name in the directory).
-        if len(exported_name) > MAX_PAGE_NAME_LENGTH:
-            exported_name = exported_name[:MAX_PAGE_NAME_LENGTH]
+        exported_name = _sanitize_name(self.name)
 
         # Since we set the name suffix here, we assume that it's already safe.
         if self.name_suffix:
@@ -257,6 +499,39 @@ class Entry:
         return exported_name
 
 
+def _sanitize_name(name: str) -> str:
+    """
+    Sanitizes the given name to be used as a file name (without extension).
+    """
+    # Whitespace at the end of the name (before extension) may not work well on some
+    # system or app (e.g., Obsidian).
+    #
+    # Also, we will remove unexpected characters like slash and null char.
+    name = name.strip()
+
+    # Non-printable char: \0
+    # Forbidden in Unix: /
+    # Forbidden in Markdown/Obsidian linking: #, ^, [, ], |
+    # Forbidden by Obsidian: \, :
+    #
+    # Note that Windows could have more forbidden chars and reserved words.
+    # Reference: https://en.wikipedia.org/wiki/Filename
+
+    name = re.sub(r"[\0#^]", "", name)
+    name = re.sub(r"\/", " or ", name)
+    name = re.sub(r"\[", "- ", name)
+    name = re.sub(r"[\]:]", " -", name)
+    name = re.sub(r"\|", "-", name)
+
+    # Replace multiple whitespaces with a single whitespace
+    name = re.sub(r"\s+", " ", name)
+
+    if len(name) > MAX_PAGE_NAME_LENGTH:
+        name = name[:MAX_PAGE_NAME_LENGTH]
+
+    return name
+
+
 class Asset:
     """
     An asset file.
@@ -434,7 +709,7 @@ def _build_directory_info(
 
                 db_page_id = _find_database_id_from_md(child, parent_database.id_prefix)
                 if db_page_id is None:
-                    raise ValueError(
+                    raise ParseError(
                         f"Unable to find db id with prefix '{parent_database.id_prefix}' on file '{child}'."
                     )
 
@@ -469,14 +744,14 @@ def _build_directory_info(
                         )
                     )
         else:
-            raise ValueError(f"unexpected file extension: {extension}")
+            raise ParseError(f"unexpected file extension: {extension}")
 
     for uid, name, path in subdirs:
         entry = directory.get_entry_by_uid(uid)
         if entry is None:
-            raise ValueError(f"unable to find entry for directory with path: {path}")
+            raise ParseError(f"unable to find entry for directory with path: {path}")
         if name != entry.get_name_ori():
-            raise ValueError(
+            raise ParseError(
                 f"Directory '{path}' name '{name}' does not match entry name '{entry.name}'."
             )
 
@@ -493,7 +768,7 @@ def _find_heading_from_md_page(path: Path) -> Optional[str]:
     heading = open(path, "r", encoding=PAGE_FILE_ENCODING).readline().rstrip()
 
     if not heading.startswith("# "):
-        raise ValueError(
+        raise ParseError(
             f"failed to find page heading from '{path}', first line: '{heading}'"
         )
 
@@ -558,10 +833,10 @@ def _find_database_id_from_md(path: Path, id_prefix: Optional[str]) -> Optional[
 
             if line_count == 1:
                 if not line.startswith("# "):
-                    raise ValueError("markdown file does not start with heading")
+                    raise ParseError("markdown file does not start with heading")
             elif line_count == 2:
                 if line.strip() != "":
-                    raise ValueError(
+                    raise ParseError(
                         "markdown file does not have newline after heading"
                     )
             else:
@@ -575,7 +850,7 @@ def _find_database_id_from_md(path: Path, id_prefix: Optional[str]) -> Optional[
                 if DATABASE_ID_RE.match(id_str):
                     return id_str
                 else:
-                    echo(f"WARN: ignored db id candidate '{id_str}'")
+                    echo_warning(f"ignored db id candidate '{id_str}'")
 
     return None
 
@@ -588,7 +863,7 @@ def _build_entries_map_by_uid(entries_map: dict[str, Entry], directory: Director
         entry = directory.get_entry_by_uid(uid)
         existing_entry = entries_map.get(uid)
         if existing_entry:
-            raise ValueError(
+            raise ParseError(
                 f"found two entries with the same uid: '{existing_entry.path}', '{entry.path}'"
             )
 
@@ -612,6 +887,112 @@ def _build_entries_map_by_uid(entries_map: dict[str, Entry], directory: Director
         name_counts[entry.name] = count + 1
 
 
+class SectionReport(BaseModel):
+    """
+    Size report of a top-level section (i.e., a top-level entry and everything nested under it).
+    """
+
+    name: str
+    pages: int = 0
+    databases: int = 0
+    assets: int = 0
+    size_bytes: int = 0
+
+
+def _build_size_report(directory: Directory) -> list[SectionReport]:
+    """
+    Builds the size report for each top-level entry of the given directory, based on the extracted files.
+    """
+    def add_assets(report: SectionReport, assets: list[Asset]) -> None:
+        for asset in assets:
+            report.assets += 1
+            report.size_bytes += asset.path.stat().st_size
+
+    def add_directory(report: SectionReport, subdir: Directory) -> None:
+        add_assets(report, subdir.assets)
+        for entry_uid in subdir.sorted_entry_uids():
+            add_entry(report, subdir.get_entry_by_uid(entry_uid))
+
+    def add_entry(report: SectionReport, entry: Entry) -> None:
+        if isinstance(entry, DatabaseView):
+            report.databases += 1
+        else:
+            report.pages += 1
+        report.size_bytes += entry.path.stat().st_size
+        if entry.subdir:
+            add_directory(report, entry.subdir)
+
+    reports = []
+    for entry_uid in directory.sorted_entry_uids():
+        entry = directory.get_entry_by_uid(entry_uid)
+        report = SectionReport(name=entry.get_exported_name())
+        add_entry(report, entry)
+        reports.append(report)
+
+    # Assets at the top level do not belong to any section.
+    if directory.assets:
+        report = SectionReport(name=".")
+        add_assets(report, directory.assets)
+        reports.append(report)
+
+    return reports
+
+
+def _print_size_report(reports: list[SectionReport]) -> None:
+    echo("Size per top-level section:")
+    echo_table(
+        ["Section", "Pages", "Databases", "Assets", "Size"],
+        [
+            [report.name, report.pages, report.databases, report.assets, format_size(report.size_bytes)]
+            for report in sorted(reports, key=lambda report: report.size_bytes, reverse=True)
+        ],
+    )
+
+
+def _write_size_report(target: Path, reports: list[SectionReport]) -> None:
+    """
+    Adds the size report to the index file of the target root, which serves as the manifest of the export.
+    """
+    index_file = target.joinpath(INDEX_FILE_NAME)
+    with open(index_file, "r", encoding=PAGE_FILE_ENCODING) as file:
+        index = yaml.safe_load(file)
+
+    index["sections"] = [report.dict() for report in reports]
+    with open(index_file, "w", encoding=PAGE_FILE_ENCODING) as file:
+        file.write(yaml.dump(index))
+
+
+def _print_target_tree(directory: Directory, depth: int) -> None:
+    """
+    Prints the target structure of the given directory, following the same naming as `_build_target_directory`.
+
+    Entries that are renamed to avoid duplicate names, or that would still overwrite another file (e.g., because
+    their names only differ in removed characters), are marked.
+    """
+    indent = "  " * depth
+    names: set[str] = set()
+
+    def echo_name(name: str, remark: Optional[str] = None) -> None:
+        remarks = [remark] if remark else []
+        if name in names:
+            remarks.append("collides with another entry")
+        names.add(name)
+        echo(f"{indent}{name}" + (f"  [{', '.join(remarks)}]" if remarks else ""))
+
+    for asset in directory.assets:
+        echo_name(asset.get_exported_name())
+
+    for entry_uid in directory.sorted_entry_uids():
+        entry = directory.get_entry_by_uid(entry_uid)
+        exported_name = entry.get_exported_name()
+        extension = ".csv" if isinstance(entry, DatabaseView) else ".md"
+
+        echo_name(exported_name + extension, "renamed from duplicate name" if entry.name_suffix else None)
+        if entry.subdir:
+            echo_name(exported_name + "/")
+            _print_target_tree(entry.subdir, depth + 1)
+
+
 def _detect_file_encoding(file_path):
     with open(file_path, "rb") as f:
         result = chardet.detect(f.read())
@@ -628,7 +1009,16 @@ def _update_md_file_heading(file_path: Path, heading: str):
         file.writelines(lines)
 
 
-def _update_links_on_file(file_path: Path, entries_by_uid: dict[str, Entry]):
+def _update_links_on_file(
+    file_path: Path,
+    entries_by_uid: dict[str, Entry],
+    page: Optional[Entry] = None,
+    alias_mode: str = "none",
+    aliases: Optional[dict[Path, Path]] = None,
+    inline_database_mode: str = "link",
+    relink: bool = False,
+    split_sections: Optional[dict[Path, list[tuple[str, Path]]]] = None,
+):
     # Somehow exported files from Notion could have encodings such as 'ascii', 'Windows-1252', and 'Windows-1254'.
     # However, if we use such encoding to read the file, sometimes there could be errors.
     # Hence, we will just print some warnings here if we are about to change the encoding.
@@ -660,15 +1050,15 @@ def _update_links_on_file(file_path: Path, entries_by_uid: dict[str, Entry]):
             #
             # In such scenario, this link could still end up working, but it would point to the
             # exported view instead of the view recorded in Notion.
-            echo(
-                f"WARN: found link to entry with non-existent uid {uid} in file '{file_path}'."
+            echo_warning(
+                f"found link to entry with non-existent uid {uid} in file '{file_path}'."
             )
             return prefix + name
 
         # Sanity check for name consistency.
         # Note that in a Markdown link we must use "%20" instead of whitespace.
         if name != entry.get_name_ori().replace(" ", "%20"):
-            raise ValueError(
+            raise ParseError(
                 f"found inconsistent name for entry {uid} in file '{file_path}', "
                 f"expected name: '{entry.name}', found: '{name}'."
             )
@@ -676,14 +1066,157 @@ def _update_links_on_file(file_path: Path, entries_by_uid: dict[str, Entry]):
 
         return prefix + exported_name
 
+    def href_replacement(m: re.Match) -> str:
+        href = m.group(1)
+        uid_match = LINK_HREF_UID_RE.search(href)
+        if uid_match is None:
+            return m.group(0)
+
+        entry = entries_by_uid.get(uid_match.group(1))
+        if entry is None or entry.target_path is None:
+            return m.group(0)
+
+        # Nothing to do if the link already points to the location of the exported entry, unless the structure
+        # has been changed (in which case all links are rewritten).
+        linked_path = Path(os.path.normpath(page.path.parent.joinpath(unquote(href))))
+        if linked_path == Path(os.path.normpath(entry.path)) and not relink:
+            return m.group(0)
+
+        if alias_mode == "relative" or relink:
+            relative_path = os.path.relpath(entry.target_path, file_path.parent)
+            return f"]({relative_path.replace(' ', '%20')})"
+
+        # For the other modes, keep the link and create an alias at the location it points to. Note that the
+        # alias location needs to use the exported names as well, which is done by the same replacement as
+        # below. The leading slash is needed for the regex to match the first path segment.
+        exported_href = LINK_ITEM_NAME_RE.sub(replacement, "/" + href)[1:]
+        alias_path = Path(os.path.normpath(file_path.parent.joinpath(unquote(exported_href))))
+        aliases[alias_path] = entry.target_path
+        return m.group(0)
+
+    def inline_database_replacement(m: re.Match) -> str:
+        uid_match = LINK_HREF_UID_RE.search(m.group(1))
+        entry = entries_by_uid.get(uid_match.group(1)) if uid_match else None
+        if not isinstance(entry, DatabaseView):
+            return m.group(0)
+
+        return m.group(0) + "\n\n" + _database_summary_table(entry.path)
+
+    def notion_url_replacement(m: re.Match) -> str:
+        entry = entries_by_uid.get(m.group(2))
+        if entry is None or entry.target_path is None:
+            return m.group(0)
+
+        relative_path = os.path.relpath(entry.target_path, file_path.parent)
+        return f"]({relative_path.replace(' ', '%20')})"
+
+    def anchor_replacement(m: re.Match) -> str:
+        linked_path = Path(os.path.normpath(file_path.parent.joinpath(unquote(m.group(1)))))
+        anchor = heading_anchor(unquote(m.group(2)))
+        for heading, section_path in split_sections.get(linked_path, []):
+            if heading_anchor(heading) == anchor:
+                relative_path = os.path.relpath(section_path, file_path.parent)
+                return f"]({relative_path.replace(' ', '%20')})"
+        return m.group(0)
+
+    # Note that this needs to be done before fixing the links, since the summary table may contain links as well
+    # (e.g., relations to other pages).
+    if inline_database_mode == "table":
+        data = INLINE_DATABASE_LINK_RE.sub(inline_database_replacement, data)
+
+    if page is not None and (alias_mode != "none" or relink):
+        data = LINK_HREF_RE.sub(href_replacement, data)
+
+    data = NOTION_URL_LINK_RE.sub(notion_url_replacement, data)
+
     # Fix the link, basically for each uid find if it should be replaced to empty string or a certain name suffix.
     data = LINK_ITEM_NAME_RE.sub(replacement, data)
 
+    # Note that this needs to be done after fixing the names, so that the links point to the exported pages.
+    if split_sections:
+        data = LINK_ANCHOR_RE.sub(anchor_replacement, data)
+
     # Write the data back to the file
     with open(file_path, "w", encoding=target_enc) as file:
         file.write(data)
 
 
+# Name of the table in the SQLite file that lists the loaded databases.
+SQLITE_DATABASES_TABLE_NAME = "notion_databases"
+
+
+def _write_sqlite(target: Path, sqlite_path: Path, entries_by_uid: dict[str, Entry]) -> None:
+    """
+    Loads all exported databases (i.e., their CSV files) into a SQLite file, with a table per database.
+
+    The column types are inferred from the values (i.e., integer, real, or text). Each table is named after the
+    exported database, with a suffix (e.g., " (2)") if the name is used by another database. The tables are listed
+    in `SQLITE_DATABASES_TABLE_NAME` along with the uid and the path of their database within the target.
+    """
+    if sqlite_path.exists():
+        os.remove(sqlite_path)
+    os.makedirs(sqlite_path.parent, exist_ok=True)
+
+    databases = sorted(
+        (entry for entry in entries_by_uid.values() if isinstance(entry, DatabaseView) and entry.target_path),
+        key=lambda entry: str(entry.target_path),
+    )
+
+    # Table names in SQLite are case-insensitive, and the name of the table that lists the databases is reserved.
+    table_names: set[str] = {SQLITE_DATABASES_TABLE_NAME.casefold()}
+    rows = []
+    with sqlite3.connect(sqlite_path) as connection:
+        for entry in databases:
+            try:
+                df = pandas.read_csv(entry.target_path, encoding="utf-8-sig")
+            except (pandas.errors.EmptyDataError, pandas.errors.ParserError) as e:
+                echo_warning(f"skipping database '{entry.target_path}' in the SQLite file, which cannot be read: {e}")
+                continue
+
+            table_name = entry.target_path.stem
+            counter = 2
+            while table_name.casefold() in table_names:
+                table_name = f"{entry.target_path.stem} ({counter})"
+                counter += 1
+            table_names.add(table_name.casefold())
+
+            df.to_sql(table_name, connection, index=False)
+            rows.append({
+                "table_name": table_name,
+                "uid": entry.uid,
+                "path": entry.target_path.relative_to(target).as_posix(),
+            })
+
+        pandas.DataFrame(rows, columns=["table_name", "uid", "path"]).to_sql(
+            SQLITE_DATABASES_TABLE_NAME, connection, index=False)
+    connection.close()
+
+    echo(f"Loaded {len(rows)} database(s) into '{sqlite_path}'")
+
+
+def _database_summary_table(path: Path, max_rows: int = INLINE_DATABASE_MAX_ROWS) -> str:
+    """
+    Returns a Markdown table with the first rows of the given database (CSV) file.
+    """
+    df = pandas.read_csv(path, dtype=str).fillna("")
+
+    def cell(value: str) -> str:
+        return value.replace("|", "\\|").replace("\n", " ")
+
+    lines = [
+        "| " + " | ".join(cell(str(column)) for column in df.columns) + " |",
+        "|" + " --- |" * len(df.columns),
+    ]
+    for _, row in df.head(max_rows).iterrows():
+        lines.append("| " + " | ".join(cell(value) for value in row) + " |")
+
+    if len(df) > max_rows:
+        lines.append("")
+        lines.append(f"_Showing {max_rows} of {len(df)} rows._")
+
+    return "\n".join(lines)
+
+
 def _build_target_directory(
     path: Path,
     uid: str,
@@ -691,12 +1224,38 @@ def _build_target_directory(
     # To help with fixing links. This contains entries across all export data,
     # not only this directory.
     entries_by_uid: dict[str, Entry],
+    # File operations to be executed later by the worker pool.
+    tasks: list[Callable[[], None]],
+    alias_mode: str = "none",
+    # Mapping of alias path to the actual target path, which is populated while fixing links.
+    aliases: Optional[dict[Path, Path]] = None,
+    inline_database_mode: str = "link",
+    # Whether to rewrite all links to the target location of the entries.
+    relink: bool = False,
+    # Size (in bytes) above which pages are split into sections, or 0 to disable it.
+    split_threshold: int = 0,
+    # Mapping of the target path of each split page to the heading and target path of its sections, which is
+    # populated while building the directory and used to fix links to the headings of the split pages.
+    split_sections: Optional[dict[Path, list[tuple[str, Path]]]] = None,
 ) -> None:
     """
     Builds the target directory structure.
+
+    Note that only directories and index files are written here. Copying files is appended to `tasks`, since each
+    of them only touches its own target file and hence can be executed in parallel.
     """
     index_dir = IndexDir(uid=uid)
 
+    # Names that are taken in this directory (case-insensitive, for the file systems that are), which the section
+    # files of the split pages must not collide with.
+    taken_names = {asset.get_exported_name().casefold() for asset in directory.assets}
+    for entry_uid in directory.sorted_entry_uids():
+        entry = directory.get_entry_by_uid(entry_uid)
+        extension = ".csv" if isinstance(entry, DatabaseView) else ".md"
+        taken_names.add((entry.get_exported_name() + extension).casefold())
+        if entry.subdir:
+            taken_names.add(entry.get_exported_name().casefold())
+
     # Guaranteed to be unique by the export format.
     for asset in directory.assets:
         exported_name = asset.get_exported_name()
@@ -704,7 +1263,7 @@ def _build_target_directory(
         index_dir.assets.append(IndexItemAsset(name=exported_name))
 
         # Move from the tmp dir to the target dir
-        shutil.copy(asset.path, path.joinpath(exported_name))
+        tasks.append(lambda src=asset.path, dst=path.joinpath(exported_name): shutil.copy(src, dst))
 
     for entry_uid in directory.sorted_entry_uids():
         entry = directory.get_entry_by_uid(entry_uid)
@@ -716,17 +1275,17 @@ def _build_target_directory(
             target_path = path.joinpath(exported_name + ".md")
             index_dir.pages.append(IndexItemPage(name=target_path.name, uid=entry.uid))
 
-            shutil.copy(entry.path, target_path)
-            _update_links_on_file(target_path, entries_by_uid)
+            sections = None
+            if 0 < split_threshold < entry.path.stat().st_size:
+                sections = _plan_page_sections(entry.path, target_path, taken_names)
+                if sections:
+                    split_sections[target_path] = sections
+                    index_dir.sections.extend(
+                        IndexItemSection(page_uid=entry.uid, name=section_path.name) for _, section_path in sections)
 
-            # If it's a database page with an id, we want the heading to have ID prefix like the file name.
-            if isinstance(entry, DatabasePage) and entry.db_id:
-                # Note that this updated heading may not be equal to the file name, since the file name may
-                # be trimmed if exceeding certain length and have unexpected chars (e.g., "/") removed.
-                _update_md_file_heading(
-                    target_path,
-                    f"{entry.db_id}{DATABASE_ID_SEPARATOR_CHAR} {entry.name}",
-                )
+            tasks.append(lambda entry=entry, target_path=target_path, sections=sections: _copy_page(
+                entry, target_path, entries_by_uid, alias_mode, aliases, inline_database_mode, relink,
+                sections, split_sections))
 
         elif isinstance(entry, DatabaseView):
             target_path = path.joinpath(exported_name + ".csv")
@@ -734,15 +1293,18 @@ def _build_target_directory(
                 IndexItemDatabase(name=target_path.name, uid=entry.uid)
             )
 
-            shutil.copy(entry.path, target_path)
+            tasks.append(lambda src=entry.path, dst=target_path: shutil.copy(src, dst))
         else:
             raise ValueError(f"unknown entry type: {entry}")
 
+        entry.target_path = target_path
+
         if entry.subdir:
             target_path = path.joinpath(exported_name)
             os.makedirs(target_path, exist_ok=True)
             _build_target_directory(
-                target_path, entry.uid, entry.subdir, entries_by_uid
+                target_path, entry.uid, entry.subdir, entries_by_uid, tasks, alias_mode, aliases,
+                inline_database_mode, relink, split_threshold, split_sections,
             )
 
     # Write the index file.
@@ -752,6 +1314,154 @@ def _build_target_directory(
         file.write(index_str)
 
 
+def _copy_page(
+    entry: Entry,
+    target_path: Path,
+    entries_by_uid: dict[str, Entry],
+    alias_mode: str = "none",
+    aliases: Optional[dict[Path, Path]] = None,
+    inline_database_mode: str = "link",
+    relink: bool = False,
+    sections: Optional[list[tuple[str, Path]]] = None,
+    split_sections: Optional[dict[Path, list[tuple[str, Path]]]] = None,
+) -> None:
+    """
+    Copies a page to the target path and fixes its content (links, inline databases, and heading). The page is
+    then split into the given sections (see `_plan_page_sections`), if any.
+    """
+    shutil.copy(entry.path, target_path)
+    _update_links_on_file(target_path, entries_by_uid, entry, alias_mode, aliases, inline_database_mode, relink,
+                          split_sections)
+
+    # If it's a database page with an id, we want the heading to have ID prefix like the file name.
+    if isinstance(entry, DatabasePage) and entry.db_id:
+        # Note that this updated heading may not be equal to the file name, since the file name may
+        # be trimmed if exceeding certain length and have unexpected chars (e.g., "/") removed.
+        _update_md_file_heading(
+            target_path,
+            f"{entry.db_id}{DATABASE_ID_SEPARATOR_CHAR} {entry.name}",
+        )
+
+    # Note that this is done after fixing the links, so that the sections can be moved as is. Since the section
+    # files are written next to the page, their relative links stay valid.
+    if sections:
+        _split_page(target_path, sections)
+
+
+def _split_page_sections(content: str) -> tuple[str, list[tuple[str, str]]]:
+    """
+    Splits the content of a page by its H1/H2 headings, excluding the title on the first line and the lines
+    within code blocks.
+
+    Returns:
+        The content before the first section, and the heading and content of each section.
+    """
+    intro: list[str] = []
+    sections: list[tuple[str, list[str]]] = []
+    in_code_block = False
+    for i, line in enumerate(content.splitlines(keepends=True)):
+        if line.startswith("```"):
+            in_code_block = not in_code_block
+
+        match = SECTION_HEADING_RE.match(line.rstrip("\n")) if i > 0 and not in_code_block else None
+        if match:
+            sections.append((match.group(1).strip(), [line]))
+        elif sections:
+            sections[-1][1].append(line)
+        else:
+            intro.append(line)
+
+    return "".join(intro), [(heading, "".join(lines)) for heading, lines in sections]
+
+
+def _plan_page_sections(source_path: Path, target_path: Path, taken_names: set[str]) -> list[tuple[str, Path]]:
+    """
+    Plans the section files (named "<page> - <heading>") of a page that is split, next to the page itself. Names that
+    are already taken in the directory are disambiguated with a suffix, and are then taken by the sections.
+
+    Returns:
+        The heading and target path of each section, or an empty list if the page has less than two sections.
+    """
+    with open(source_path, "r", encoding=PAGE_FILE_ENCODING) as file:
+        _, sections = _split_page_sections(file.read())
+    if len(sections) < 2:
+        return []
+
+    result = []
+    for heading, _ in sections:
+        name = _sanitize_name(f"{target_path.stem} - {heading}")
+        # Same as the entries, duplicate names are disambiguated with a suffix.
+        suffix = 2
+        unique_name = name
+        while f"{unique_name}.md".casefold() in taken_names:
+            unique_name = f"{name} ({suffix})"
+            suffix += 1
+        taken_names.add(f"{unique_name}.md".casefold())
+        result.append((heading, target_path.with_name(unique_name + ".md")))
+    return result
+
+
+def _split_page(path: Path, sections: list[tuple[str, Path]]) -> None:
+    """
+    Splits a page into the given section files (see `_plan_page_sections`). The page keeps the content before the
+    first section, followed by links to the sections.
+    """
+    with open(path, "r", encoding=PAGE_FILE_ENCODING) as file:
+        intro, contents = _split_page_sections(file.read())
+    if len(contents) != len(sections):
+        echo_warning(f"the sections of '{path}' have changed while fixing its links, so it is not split.")
+        return
+
+    links = []
+    for (heading, content), (_, section_path) in zip(contents, sections):
+        # Each section becomes a page on its own, so its heading is promoted to the title.
+        _, _, body = content.partition("\n")
+        with open(section_path, "w", encoding=PAGE_FILE_ENCODING) as file:
+            file.write(f"# {heading}\n{body}")
+        links.append(f"- [{heading}]({section_path.name.replace(' ', '%20')})\n")
+
+    with open(path, "w", encoding=PAGE_FILE_ENCODING) as file:
+        file.write(intro.rstrip("\n") + "\n\n" + "".join(links))
+
+
+def _create_aliases(target: Path, aliases: dict[Path, Path], alias_mode: str) -> None:
+    """
+    Creates symlinks or stub notes at the alternate locations of pages.
+
+    Existing files are never overwritten, and aliases outside of the target directory are ignored.
+    """
+    root = Path(os.path.normpath(target.absolute()))
+    for alias_path, entry_path in sorted(aliases.items()):
+        if root not in Path(os.path.normpath(alias_path.absolute())).parents:
+            echo_warning(f"skipped alias '{alias_path}' since it is outside of the target directory.")
+            continue
+        if alias_path.exists() or alias_path.is_symlink():
+            continue
+
+        os.makedirs(alias_path.parent, exist_ok=True)
+        relative_path = os.path.relpath(entry_path, alias_path.parent)
+
+        if alias_mode == "symlink":
+            os.symlink(relative_path, alias_path)
+        elif alias_mode == "stub":
+            if alias_path.suffix != ".md":
+                echo_warning(f"skipped stub for '{alias_path}' since it is not a page.")
+                continue
+            with open(alias_path, "w", encoding=PAGE_FILE_ENCODING) as file:
+                file.write(f"# {entry_path.stem}\n\n")
+                file.write(f"See [{entry_path.stem}]({relative_path.replace(' ', '%20')}).\n")
+
+
+class IndexItemSection(BaseModel):
+    """
+    Index representation for a section file of a split page (Markdown).
+    """
+
+    # Uid of the page that the section is split from.
+    page_uid: str
+    name: str
+
+
 class IndexItemPage(BaseModel):
     """
     Index representation for a page file (Markdown).
@@ -788,3 +1498,136 @@ class IndexDir(BaseModel):
     assets: list[IndexItemAsset] = Field(default_factory=list)
     databases: list[IndexItemDatabase] = Field(default_factory=list)
     pages: list[IndexItemPage] = Field(default_factory=list)
+    sections: list[IndexItemSection] = Field(default_factory=list)
+
+
+class SearchResult(BaseModel):
+    """
+    A page that matches a search query.
+    """
+
+    # Path of the page, relative to the target directory.
+    path: str
+    title: str
+    score: int
+    snippet: str
+
+
+class SearchIndex(BaseModel):
+    """
+    Inverted index over the pages of an exported target, which is rebuilt whenever the manifest (i.e., the index
+    file at the root of the target) changes, e.g., after a new export.
+    """
+
+    manifest_mtime_ns: int
+    # Path (relative to the target) and title of each page.
+    pages: list[tuple[str, str]] = Field(default_factory=list)
+    # Mapping of each token to the (weighted) number of its occurrences per page, keyed by the page position.
+    tokens: dict[str, dict[int, int]] = Field(default_factory=dict)
+
+
+def _tokenize(text: str) -> list[str]:
+    return SEARCH_TOKEN_RE.findall(text.casefold())
+
+
+def _build_search_index(target: Path, manifest_mtime_ns: int) -> SearchIndex:
+    index = SearchIndex(manifest_mtime_ns=manifest_mtime_ns)
+    for path in sorted(target.rglob("*.md")):
+        # Aliases (see `ALIAS_MODES`) would duplicate the results of the actual pages.
+        if path.is_symlink():
+            continue
+
+        with open(path, "r", encoding=PAGE_FILE_ENCODING, errors="replace") as file:
+            content = file.read()
+        first_line = content.partition("\n")[0]
+        title = first_line[2:].strip() if first_line.startswith("# ") else path.stem
+
+        page_id = len(index.pages)
+        index.pages.append((path.relative_to(target).as_posix(), title))
+        counts: dict[str, int] = {}
+        for token in _tokenize(content):
+            counts[token] = counts.get(token, 0) + 1
+        for token in _tokenize(title):
+            counts[token] = counts.get(token, 0) + SEARCH_TITLE_WEIGHT
+        for token, count in counts.items():
+            index.tokens.setdefault(token, {})[page_id] = count
+    return index
+
+
+def _load_search_index(target: Path, rebuild: bool = False) -> SearchIndex:
+    manifest = target.joinpath(INDEX_FILE_NAME)
+    if not manifest.is_file():
+        raise IoError(f"'{target}' is not an exported Notion target, since it does not have an {INDEX_FILE_NAME} file")
+    manifest_mtime_ns = manifest.stat().st_mtime_ns
+
+    key = hashlib.sha1(str(target.resolve()).encode("utf-8")).hexdigest()
+    index_path = SEARCH_INDEX_DIR.joinpath(f"{key}.json")
+    if not rebuild and index_path.is_file():
+        with open(index_path, "r", encoding="utf-8") as file:
+            try:
+                index = SearchIndex(**json.load(file))
+            except (json.JSONDecodeError, ValidationError):
+                # Rebuild the index if it is written by an incompatible version.
+                index = None
+        if index is not None and index.manifest_mtime_ns == manifest_mtime_ns:
+            return index
+
+    echo(f"Indexing pages in '{target}' ...", err=True)
+    index = _build_search_index(target, manifest_mtime_ns)
+    os.makedirs(SEARCH_INDEX_DIR, exist_ok=True)
+    with open(index_path, "w", encoding="utf-8") as file:
+        json.dump(index.dict(), file)
+    return index
+
+
+def _find_snippet(path: Path, tokens: list[str]) -> str:
+    """
+    Returns the first line (excluding the title) that contains the most tokens, shortened around the first match.
+    """
+    with open(path, "r", encoding=PAGE_FILE_ENCODING, errors="replace") as file:
+        lines = file.read().splitlines()
+
+    best_line, best_positions = "", []
+    for line in lines[1:]:
+        line = line.strip()
+        folded = line.casefold()
+        positions = [folded.find(token) for token in tokens if token in folded]
+        if len(positions) > len(best_positions):
+            best_line, best_positions = line, positions
+            if len(positions) == len(tokens):
+                break
+    if not best_positions:
+        return ""
+
+    start = max(0, min(best_positions) - SEARCH_SNIPPET_LENGTH // 3)
+    snippet = best_line[start:start + SEARCH_SNIPPET_LENGTH]
+    return ("..." if start > 0 else "") + snippet + ("..." if start + SEARCH_SNIPPET_LENGTH < len(best_line) else "")
+
+
+def search(target: Path, query: str, limit: int = 20, rebuild: bool = False) -> list[SearchResult]:
+    """
+    Searches the pages of an exported target for the pages that contain all words of the query, ranked by the number
+    of their occurrences (where the ones in the title weigh more, see `SEARCH_TITLE_WEIGHT`).
+
+    The index is cached and only rebuilt if the target is exported again (or if `rebuild` is set).
+    """
+    tokens = list(dict.fromkeys(_tokenize(query)))
+    if not tokens:
+        return []
+
+    index = _load_search_index(target, rebuild)
+    scores: Optional[dict[int, int]] = None
+    for token in tokens:
+        counts = index.tokens.get(token, {})
+        if scores is None:
+            scores = dict(counts)
+        else:
+            scores = {page_id: score + counts[page_id] for page_id, score in scores.items() if page_id in counts}
+
+    ranked = sorted(scores.items(), key=lambda item: (-item[1], index.pages[item[0]][0]))[:limit]
+    results = []
+    for page_id, score in ranked:
+        path, title = index.pages[page_id]
+        results.append(SearchResult(path=path, title=title, score=score,
+                                    snippet=_find_snippet(target.joinpath(path), tokens)))
+    return results
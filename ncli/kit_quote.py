@@ -0,0 +1,138 @@
+# This specific file is part of the 'ncli' project and it is licensed under the AGPL-3.0 License.
+# Please see the accompanying LICENSE-AGPL-3.0 file for more details.
+
+"""
+A module for picking a highlight from the exported books and formatting it as a quote for sharing.
+"""
+
+import random
+import textwrap
+from string import Template
+from typing import List, Optional
+from xml.sax.saxutils import escape
+
+from pydantic import BaseModel  # pylint: disable=no-name-in-module
+
+from ncli.errors import ConfigError
+from ncli.kit_amazon import Annotation, Book, parse_markdown_annotations
+from ncli.kit_dashboard import Source
+from ncli.utils import format_duration_from_ms
+
+QUOTE_FORMATS = ("plain", "markdown", "svg")
+
+# Maximum number of characters per line in the SVG card.
+SVG_LINE_WIDTH: int = 48
+SVG_LINE_HEIGHT: int = 32
+
+SVG_TEMPLATE = Template("""\
+<svg xmlns="http://www.w3.org/2000/svg" width="800" height="$height" viewBox="0 0 800 $height">
+  <rect width="100%" height="100%" rx="24" fill="#fdf6e3"/>
+  <text x="64" y="96" font-family="Georgia, serif" font-size="24" fill="#073642">
+$lines
+  </text>
+  <text x="736" y="$attribution_y" text-anchor="end" font-family="Georgia, serif" font-size="18" font-style="italic"
+        fill="#586e75">— $attribution</text>
+</svg>
+""")
+
+
+class Quote(BaseModel):
+    """
+    Represents a highlight from an exported book, together with the book that it belongs to.
+    """
+    # ID of the quote, i.e., "<ASIN>-<n>" where n is the (1-based) position of the highlight among the highlights in
+    # the exported note (i.e., note-only annotations are not counted).
+    id: str
+    book: Book
+    annotation: Annotation
+
+
+def load_quotes(sources: List[Source]) -> List[Quote]:
+    """
+    Loads the highlights from the exported Markdown notes of the given sources.
+
+    Books that are not exported as Markdown (or whose notes no longer exist) are skipped.
+    """
+    quotes = []
+    for source in sources:
+        for item in source.index.books:
+            path = source.book_path(item)
+            if not path.exists():
+                continue
+
+            highlights = [annotation for annotation in parse_markdown_annotations(path) if annotation.highlight]
+            for i, annotation in enumerate(highlights, start=1):
+                quotes.append(Quote(id=f'{item.info.asin}-{i}', book=item.info, annotation=annotation))
+    return quotes
+
+
+def select_quote(
+    quotes: List[Quote],
+    quote_id: Optional[str] = None,
+    search: Optional[str] = None,
+    rng: Optional[random.Random] = None,
+) -> Quote:
+    """
+    Selects a quote by its ID, or a random one (among the ones that contain the search text, if given).
+    """
+    if quote_id is not None:
+        quote = next((quote for quote in quotes if quote.id == quote_id), None)
+        if quote is None:
+            raise ConfigError(f'quote {quote_id} is not found')
+        return quote
+
+    if search is not None:
+        search = search.lower()
+        quotes = [
+            quote for quote in quotes
+            if search in quote.annotation.highlight.lower() or search in (quote.annotation.note or '').lower()
+        ]
+    if not quotes:
+        raise ConfigError('no matching quotes are found')
+
+    return (rng or random).choice(quotes)
+
+
+def format_quote_attribution(quote: Quote) -> str:
+    """
+    Formats the attribution of the quote, e.g., "Author, Title (Location 123)".
+    """
+    parts = [quote.book.author, quote.book.title] if quote.book.author else [quote.book.title]
+    attribution = ', '.join(parts)
+
+    annotation = quote.annotation
+    if annotation.page is not None:
+        attribution += f' (Page {annotation.page})'
+    elif annotation.location is not None:
+        attribution += f' (Location {annotation.location})'
+    elif annotation.clip_start_ms is not None:
+        attribution += f' ({format_duration_from_ms(annotation.clip_start_ms)})'
+    return attribution
+
+
+def format_quote(quote: Quote, output_format: str = 'plain') -> str:
+    """
+    Formats the quote for sharing in one of the `QUOTE_FORMATS`.
+    """
+    highlight = quote.annotation.highlight
+    attribution = format_quote_attribution(quote)
+
+    if output_format == 'markdown':
+        lines = [f'> {line}' if line else '>' for line in highlight.split('\n')]
+        return '\n'.join(lines + ['>', f'> — {attribution}']) + '\n'
+
+    if output_format == 'svg':
+        wrapped = [
+            line
+            for paragraph in highlight.split('\n')
+            for line in (textwrap.wrap(paragraph, SVG_LINE_WIDTH, break_on_hyphens=False) or [''])
+        ]
+        lines = '\n'.join(
+            f'    <tspan x="64" dy="{0 if i == 0 else SVG_LINE_HEIGHT}">{escape(line)}</tspan>'
+            for i, line in enumerate(wrapped)
+        )
+        attribution_y = 96 + SVG_LINE_HEIGHT * len(wrapped) + 32
+        return SVG_TEMPLATE.substitute(height=attribution_y + 48, lines=lines, attribution_y=attribution_y,
+                                       attribution=escape(attribution))
+
+    return f'"{highlight}"\n— {attribution}\n'
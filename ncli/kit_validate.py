@@ -0,0 +1,212 @@
+"""
+A module for validating exported vaults (i.e., directories of exported Markdown notes).
+"""
+
+import os
+import re
+from pathlib import Path
+from typing import Dict, List, Optional, Tuple
+from urllib.parse import unquote
+
+import toml
+import yaml
+from pydantic import BaseModel  # pylint: disable=no-name-in-module
+
+# Target of a markdown link, i.e., "](<href>)". Note that images use the same syntax.
+LINK_HREF_RE = re.compile(r"\]\(([^()\n\0]+)\)")
+
+# Links with a scheme (e.g., "https:", "mailto:", "kindle:") are not relative links.
+LINK_SCHEME_RE = re.compile(r"^[a-zA-Z][a-zA-Z0-9+.\-]*:")
+
+# Characters that should not be used in file names, since they do not work well with Markdown links or some apps
+# (e.g., Obsidian). This follows the rules applied when exporting Notion pages.
+FORBIDDEN_NAME_CHARS = "#^[]|\\:"
+
+# Names of the index files written by the exports.
+NOTION_INDEX_FILE_NAME = "index.yaml"
+BOOK_INDEX_FILE_NAME = "index.toml"
+
+FRONTMATTER_DELIMITER = "---"
+
+
+class Issue(BaseModel):
+    """
+    Represents a single issue found in the vault.
+    """
+
+    path: Path
+    # 1-based line number, if the issue refers to a specific line.
+    line: Optional[int] = None
+    message: str
+
+    def __str__(self) -> str:
+        location = f"{self.path}:{self.line}" if self.line else f"{self.path}"
+        return f"{location}: {self.message}"
+
+
+def validate(target: Path) -> List[Issue]:
+    """
+    Validates the vault in the given directory.
+
+    The following checks are performed:
+    - Relative links (and images) in Markdown files point to existing files.
+    - Frontmatter (if any) is a valid YAML mapping.
+    - Stable IDs (i.e., Notion uids, book ASINs, and frontmatter `id` fields) are not duplicated.
+    - File and directory names do not contain forbidden characters or trailing whitespace.
+
+    Returns:
+        List[Issue]: The issues found, ordered by path.
+    """
+    issues: List[Issue] = []
+    # Locations of each stable ID (keyed by its scope and name), used to find duplicates.
+    ids: Dict[Tuple[str, str], List[Tuple[Path, Optional[int]]]] = {}
+
+    for dir_path, dir_names, file_names in os.walk(target):
+        # Skip hidden directories (e.g., ".git" or ".obsidian").
+        dir_names[:] = sorted(name for name in dir_names if not name.startswith("."))
+
+        for name in dir_names + file_names:
+            issues.extend(_check_name(Path(dir_path).joinpath(name)))
+
+        for file_name in sorted(file_names):
+            path = Path(dir_path).joinpath(file_name)
+            if path.suffix == ".md":
+                with open(path, "r", encoding="utf-8") as file:
+                    content = file.read()
+                issues.extend(_check_links(path, content))
+                issues.extend(_check_frontmatter(path, content, ids))
+            elif file_name == NOTION_INDEX_FILE_NAME:
+                # Notion uids are unique across the whole workspace.
+                issues.extend(_collect_index_ids(path, ids, "", "uid", _load_notion_index_ids))
+            elif file_name == BOOK_INDEX_FILE_NAME:
+                # The same ASIN may be exported from different sources (e.g., Kindle and Audible).
+                issues.extend(_collect_index_ids(path, ids, str(path), "ASIN", _load_book_index_ids))
+
+    for (_, stable_id), locations in ids.items():
+        if len(locations) < 2:
+            continue
+        for path, line in locations:
+            issues.append(Issue(path=path, line=line, message=f"duplicate {stable_id}"))
+
+    issues.sort(key=lambda issue: (str(issue.path), issue.line or 0))
+    return issues
+
+
+def _line_of(content: str, index: int) -> int:
+    return content.count("\n", 0, index) + 1
+
+
+def _check_name(path: Path) -> List[Issue]:
+    issues = []
+
+    forbidden = sorted(set(path.name) & set(FORBIDDEN_NAME_CHARS))
+    if forbidden:
+        issues.append(Issue(path=path, message=f"name contains forbidden character(s): {' '.join(forbidden)}"))
+
+    stem = path.stem if path.is_file() else path.name
+    if stem != stem.strip():
+        issues.append(Issue(path=path, message="name has leading or trailing whitespace"))
+
+    return issues
+
+
+def _check_links(path: Path, content: str) -> List[Issue]:
+    issues = []
+
+    for match in LINK_HREF_RE.finditer(content):
+        href = match.group(1).strip()
+
+        # Links may have a title, e.g., "[name](path "title")".
+        href = href.split(" ", 1)[0]
+        if not href or href.startswith("#") or LINK_SCHEME_RE.match(href):
+            continue
+
+        link_path = unquote(href.split("#", 1)[0])
+        if not path.parent.joinpath(link_path).exists():
+            issues.append(Issue(path=path, line=_line_of(content, match.start()),
+                                message=f"broken link '{href}'"))
+
+    return issues
+
+
+def _check_frontmatter(
+    path: Path,
+    content: str,
+    ids: Dict[Tuple[str, str], List[Tuple[Path, Optional[int]]]],
+) -> List[Issue]:
+    lines = content.split("\n")
+    if not lines or lines[0].strip() != FRONTMATTER_DELIMITER:
+        return []
+
+    try:
+        end = next(i for i in range(1, len(lines)) if lines[i].strip() == FRONTMATTER_DELIMITER)
+    except StopIteration:
+        return [Issue(path=path, line=1, message="frontmatter is not closed")]
+
+    try:
+        frontmatter = yaml.safe_load("\n".join(lines[1:end]))
+    except yaml.YAMLError as e:
+        line = 1
+        mark = getattr(e, "problem_mark", None)
+        if mark is not None:
+            # The first line of the YAML content is the second line of the file.
+            line = mark.line + 2
+        return [Issue(path=path, line=line, message=f"malformed frontmatter: {getattr(e, 'problem', e)}")]
+
+    if frontmatter is None:
+        return []
+    if not isinstance(frontmatter, dict):
+        return [Issue(path=path, line=1, message="frontmatter is not a mapping")]
+
+    if frontmatter.get("id") is not None:
+        line = next((i + 1 for i in range(1, end) if lines[i].startswith("id:")), None)
+        ids.setdefault(("", f"id {frontmatter['id']}"), []).append((path, line))
+
+    return []
+
+
+def _load_notion_index_ids(content: str) -> List[str]:
+    index = yaml.safe_load(content) or {}
+    return [
+        str(item["uid"])
+        for key in ("pages", "databases")
+        for item in index.get(key) or []
+        if isinstance(item, dict) and item.get("uid")
+    ]
+
+
+def _load_book_index_ids(content: str) -> List[str]:
+    index = toml.loads(content)
+    return [
+        str(book["info"]["asin"])
+        for book in index.get("books", [])
+        if book.get("info", {}).get("asin")
+    ]
+
+
+def _collect_index_ids(
+    path: Path,
+    ids: Dict[Tuple[str, str], List[Tuple[Path, Optional[int]]]],
+    scope: str,
+    id_name: str,
+    load_ids,
+) -> List[Issue]:
+    with open(path, "r", encoding="utf-8") as file:
+        content = file.read()
+
+    try:
+        index_ids = load_ids(content)
+    except (yaml.YAMLError, toml.TomlDecodeError, AttributeError, TypeError) as e:
+        return [Issue(path=path, message=f"malformed index file: {e}")]
+
+    # Best effort to find the line where each ID is defined, i.e., its n-th occurrence in the file.
+    start_by_id: Dict[str, int] = {}
+    for stable_id in index_ids:
+        index = content.find(stable_id, start_by_id.get(stable_id, 0))
+        line = None
+        if index >= 0:
+            line = _line_of(content, index)
+            start_by_id[stable_id] = index + len(stable_id)
+        ids.setdefault((scope, f"{id_name} {stable_id}"), []).append((path, line))
+
+    return []
@@ -17,7 +17,7 @@ import pytube
 from click import echo
 from youtube_transcript_api import YouTubeTranscriptApi
 
-from ncli.utils import format_duration
+from ncli.utils import format_duration, format_simple_date
 
 
 class Config(BaseModel):
@@ -270,7 +270,7 @@ def export(
         f.write(f'- URL: {video.url}\n')
         f.write(f'- Author: {video.author}\n')
         f.write(f'- Length: {format_duration(video.length)}\n')
-        f.write(f'- Publish date: {video.publish_date.strftime("%Y-%m-%d")}\n')
+        f.write(f'- Publish date: {format_simple_date(video.publish_date.date())}\n')
         if video.keywords:
             f.write(f'- Keywords: {", ".join(video.keywords)}\n')
         if video.description:
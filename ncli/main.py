@@ -1,6 +1,16 @@
+import sys
+
+from click import echo
+
 from ncli.cli import cli
+from ncli.errors import NcliError
 
 
 def main():
-    # pylint: disable=no-value-for-parameter
-    cli(obj={})
+    try:
+        # pylint: disable=no-value-for-parameter
+        cli(obj={})
+    except NcliError as e:
+        # Known errors are reported without a traceback. Note that the error code is part of the message.
+        echo(f'Error: {e}', err=True)
+        sys.exit(1)
@@ -3,11 +3,24 @@
 The `utils` module contains a collection of utility functions that can be used across projects.
 """
 
-from datetime import datetime, timedelta
-
-from click import echo
+import base64
+import hashlib
+import hmac
+import os
+import re
+import shutil
+import struct
+import time
+from datetime import date, datetime, timedelta, tzinfo
+from pathlib import Path
+from typing import Dict, List, Optional, Sequence, Union
+from zoneinfo import ZoneInfo, ZoneInfoNotFoundError
+
+from click import echo, secho, style
 import toml
 
+from ncli.errors import ConfigError, IoError
+
 
 def format_duration(duration: float) -> str:
     """
@@ -38,26 +51,193 @@ def format_duration(duration: float) -> str:
     return f"{minutes}:{seconds:02d}"
 
 
-def format_duration_from_ms(value: int) -> str:
+# Precisions of the durations written to the exported data (see `configure_durations`).
+DURATION_PRECISIONS = ("seconds", "frames", "ms")
+
+# Number of frames per second for the "frames" precision, as in the 30 fps SMPTE timecodes.
+DURATION_FRAME_RATE = 30
+
+_duration_precision: str = 'seconds'
+
+
+def configure_durations(precision: Optional[str] = None) -> None:
+    """
+    Sets the precision of the durations written to the exported data (e.g., clip positions), which is one of the
+    `DURATION_PRECISIONS`, or None for seconds.
+    """
+    global _duration_precision  # pylint: disable=global-statement
+    if precision:
+        check_choice('duration precision', precision, DURATION_PRECISIONS)
+    _duration_precision = precision or 'seconds'
+
+
+def check_choice(name: str, value: str, choices: Sequence[str]) -> str:
+    """
+    Returns the value if it is one of the choices (e.g., for the config validators), or raises a ConfigError that
+    lists them otherwise.
+    """
+    if value not in choices:
+        raise ConfigError(f'unknown {name}: {value}, expected one of {", ".join(choices)}')
+    return value
+
+
+def format_duration_from_ms(value: int, precision: Optional[str] = None) -> str:
     """
     Converts a duration in milliseconds to a formatted time string.
 
     The resulting time string is formatted as 'H:MM:SS', where H is hours, MM is minutes
     (with leading zero, if required), and SS is seconds (with leading zero, if required).
+    Depending on the precision, it is followed by the milliseconds ('H:MM:SS.mmm') or the frames ('H:MM:SS:FF').
 
     Args:
         value (int): The duration in milliseconds.
+        precision (Optional[str]): One of the `DURATION_PRECISIONS`. Defaults to the configured precision (see
+            `configure_durations`).
 
     Returns:
         str: The formatted time string.
     """
+    precision = precision or _duration_precision
     sec = value // 1000  # round down to second
 
     val_sec = sec % 60
     val_min = (sec % (60 * 60)) // 60
     val_hour = sec // (60 * 60)
 
-    return f'{val_hour}:{val_min:02d}:{val_sec:02d}'
+    formatted = f'{val_hour}:{val_min:02d}:{val_sec:02d}'
+    if precision == 'ms':
+        formatted += f'.{value % 1000:03d}'
+    elif precision == 'frames':
+        formatted += f':{value % 1000 * DURATION_FRAME_RATE // 1000:02d}'
+    return formatted
+
+
+def format_size(size_bytes: int) -> str:
+    """
+    Formats a size in bytes into a human-readable string, e.g., '512 B' or '1.5 MB'.
+    """
+    if size_bytes < 1024:
+        return f'{size_bytes} B'
+
+    size = size_bytes / 1024
+    for unit in ('KB', 'MB'):
+        if size < 1024:
+            return f'{size:.1f} {unit}'
+        size /= 1024
+    return f'{size:.1f} GB'
+
+
+def ensure_free_space(requirements: Dict[Path, int]) -> None:
+    """
+    Verifies that there is enough free space for the (estimated) number of bytes to be written to each of the given
+    paths, which do not need to exist yet. Requirements of paths on the same file system are added up.
+
+    Raises:
+        IoError: If any of the file systems does not have enough free space.
+    """
+    usages: Dict[int, List] = {}
+    for path, size in requirements.items():
+        # Use the closest existing parent, since the path may only be created by the export.
+        existing = path.absolute()
+        while not existing.exists() and existing != existing.parent:
+            existing = existing.parent
+        usages.setdefault(existing.stat().st_dev, [existing, 0])[1] += size
+
+    for existing, size in usages.values():
+        free = shutil.disk_usage(existing).free
+        if free < size:
+            raise IoError(f'not enough free space in {existing}: about {format_size(size)} is needed, but only '
+                          f'{format_size(free)} is available')
+
+
+def parse_duration_to_ms(value: str) -> int:
+    """
+    Converts a time string in the format of 'H:MM:SS' or 'MM:SS' back to milliseconds, optionally followed by the
+    milliseconds ('H:MM:SS.mmm') or the frames ('H:MM:SS:FF').
+
+    This is the inverse of `format_duration_from_ms` (up to its precision).
+
+    Args:
+        value (str): The formatted time string.
+
+    Returns:
+        int: The duration in milliseconds.
+    """
+    value, _, millis = value.partition('.')
+    parts = value.split(':')
+
+    extra_ms = int(millis.ljust(3, '0')[:3]) if millis else 0
+    if len(parts) > 3:
+        extra_ms = int(parts.pop()) * 1000 // DURATION_FRAME_RATE
+
+    seconds = 0
+    for part in parts:
+        seconds = seconds * 60 + int(part)
+    return seconds * 1000 + extra_ms
+
+
+# Timezone and format of the dates written to the exported data (see `configure_dates`). By default, the local
+# timezone and `DEFAULT_DATE_FORMAT` are used.
+_date_timezone: Optional[tzinfo] = None
+_date_format: Optional[str] = None
+
+DEFAULT_DATE_FORMAT = "%Y-%m-%d"
+TIMESTAMP_FORMAT = "%a, %d %b %Y %H:%M:%S %z"
+
+
+def configure_dates(timezone: Optional[str] = None, date_format: Optional[str] = None) -> None:
+    """
+    Sets the timezone and the format of the dates written to the exported data (e.g., notes and index files).
+
+    Args:
+        timezone (Optional[str]): An IANA timezone name (e.g., 'Asia/Singapore'), or None for the local timezone.
+        date_format (Optional[str]): A strftime format for the dates without time (e.g., '%d/%m/%Y'), or None for
+            `DEFAULT_DATE_FORMAT`.
+    """
+    global _date_timezone, _date_format  # pylint: disable=global-statement
+    try:
+        _date_timezone = ZoneInfo(timezone) if timezone else None
+    except (ZoneInfoNotFoundError, ValueError) as e:
+        raise ConfigError(f'unknown timezone: {timezone}') from e
+    _date_format = date_format or None
+
+
+def current_datetime() -> datetime:
+    """
+    Returns the current time in the configured timezone (see `configure_dates`).
+    """
+    return datetime.now(_date_timezone).astimezone(_date_timezone)
+
+
+def format_simple_date(value: Union[date, datetime]) -> str:
+    """
+    Formats a date in the configured date format (see `configure_dates`). Datetimes are converted to the configured
+    timezone first, while dates are formatted as is.
+    """
+    if isinstance(value, datetime):
+        value = value.astimezone(_date_timezone)
+    return value.strftime(_date_format or DEFAULT_DATE_FORMAT)
+
+
+def format_stored_date(value: Optional[str]) -> Optional[str]:
+    """
+    Formats a date that is stored in ISO format (e.g., the last opened date in the index) in the configured date
+    format, which is only done when writing the notes, so that the stored dates do not depend on the config. Other
+    strings (e.g., timestamps) are returned as is.
+    """
+    if not value:
+        return value
+    try:
+        return format_simple_date(date.fromisoformat(value))
+    except ValueError:
+        return value
+
+
+def format_timestamp(value: datetime) -> str:
+    """
+    Formats a datetime in the `TIMESTAMP_FORMAT` (in the configured timezone), e.g., for the annotation timestamps.
+    """
+    return value.astimezone(_date_timezone).strftime(TIMESTAMP_FORMAT)
 
 
 def extract_date(date_string):
@@ -68,38 +248,129 @@ def extract_date(date_string):
         date_string (str): A date string in the format YYYY-MM-DDTHH:MM:SS.SSSZ.
 
     Returns:
-        str: The date component of the input string in YYYY-MM-DD format.
+        str: The date component of the input string in YYYY-MM-DD format (see `format_stored_date` for the notes).
 
     """
     # Convert the string to a datetime object
     date_object = datetime.fromisoformat(date_string.replace('Z', '+00:00'))
 
-    # Extract the date component from the datetime object
-    date_only = date_object.date()
-
-    # Return the date component as a string in YYYY-MM-DD format
-    return str(date_only)
+    # Extract the date component from the datetime object, which is not affected by the timezone
+    return date_object.date().isoformat()
 
 
 def format_date(date_string):
     """
-    Converts a date string in the format 'YYYY-MM-DD HH:MM:SS.sss' to the format '%a, %d %b %Y %H:%M:%S %z'.
+    Converts a date string in the format 'YYYY-MM-DD HH:MM:SS.sss' to the `TIMESTAMP_FORMAT`.
 
     Args:
         date_string (str): A date string in the format 'YYYY-MM-DD HH:MM:SS.sss'.
 
     Returns:
-        str: A string in the format '%a, %d %b %Y %H:%M:%S %z'.
+        str: A string in the format '%a, %d %b %Y %H:%M:%S %z' (in the configured timezone).
 
     """
     # Parse the date string into a datetime object
     date_object = datetime.strptime(date_string, "%Y-%m-%d %H:%M:%S.%f")
 
     # Format the datetime object into the desired string format
-    formatted_date = date_object.astimezone().strftime("%a, %d %b %Y %H:%M:%S %z")
+    return format_timestamp(date_object)
+
+
+# Date formats that may appear in the exported data, in the order of preference.
+#
+# - Index timestamps (RFC 3339), e.g., '2023-01-01T00:00:01+08:00'.
+# - Audible dates and older index timestamps, e.g., 'Wed, 01 Jan 2023 00:00:01 +0800'.
+# - Kindle last opened dates as shown in the notebook, e.g., 'Sunday January 1, 2023'.
+# - Simple dates (including the normalized Kindle last opened dates), e.g., '2023-01-01'.
+#
+# Note that the configured date format (see `configure_dates`) is tried first, so that the dates written with it can be
+# read back, while the dates written before it was set are still understood.
+KNOWN_DATE_FORMATS = (
+    "%Y-%m-%dT%H:%M:%S%z",
+    "%a, %d %b %Y %H:%M:%S %z",
+    "%A %B %d, %Y",
+    "%Y-%m-%d",
+)
+
+
+def parse_date(date_string: str) -> Optional[datetime]:
+    """
+    Parses a date string in any of the known formats.
+
+    Args:
+        date_string (str): A date string in the configured date format or one of the `KNOWN_DATE_FORMATS`.
+
+    Returns:
+        Optional[datetime]: The parsed datetime (timezone-aware, using the local timezone if the string does not
+        have any), or None if the string cannot be parsed.
+    """
+    date_formats = (_date_format,) + KNOWN_DATE_FORMATS if _date_format else KNOWN_DATE_FORMATS
+    for date_format in date_formats:
+        try:
+            date_object = datetime.strptime(date_string.strip(), date_format)
+        except ValueError:
+            continue
+        if date_object.tzinfo is None:
+            date_object = date_object.astimezone()
+        return date_object
+    return None
+
+
+def current_timestamp() -> str:
+    """
+    Returns the current time in RFC 3339 format (in the configured timezone), e.g., for the index timestamps.
+    """
+    return current_datetime().isoformat(timespec='seconds')
 
-    # Return the formatted date string
-    return formatted_date
+
+def same_date(a: str, b: str) -> bool:
+    """
+    Returns whether two date strings represent the same time, regardless of their formats (see `KNOWN_DATE_FORMATS`).
+    Strings that cannot be parsed are compared as is.
+    """
+    a_date, b_date = parse_date(a), parse_date(b)
+    if a_date is None or b_date is None:
+        return a.strip() == b.strip()
+    return a_date == b_date
+
+
+def echo_heading(text: str) -> None:
+    """
+    Prints a heading. The heading is styled unless colors are disabled (see the `--color` option).
+    """
+    secho(text, bold=True, fg='cyan')
+
+
+def echo_warning(message: str) -> None:
+    """
+    Prints a warning message. The message is styled unless colors are disabled (see the `--color` option).
+    """
+    secho(f"WARN: {message}", fg='yellow', err=True)
+
+
+def echo_table(headers: List[str], rows: List[List[object]]) -> None:
+    """
+    Prints rows as a table with aligned columns.
+
+    Args:
+        headers (List[str]): The column headers.
+        rows (List[List[object]]): The rows, each containing one value per column.
+    """
+    cells = [[str(value) for value in row] for row in rows]
+    widths = [len(header) for header in headers]
+    for row in cells:
+        for i, value in enumerate(row):
+            widths[i] = max(widths[i], len(value))
+
+    # The last column is not padded to avoid trailing whitespaces.
+    def format_row(row: List[str]) -> str:
+        return "  ".join(
+            value if i == len(row) - 1 else value.ljust(widths[i]) for i, value in enumerate(row)
+        )
+
+    echo(style(format_row(headers), bold=True))
+    for row in cells:
+        echo(format_row(row))
 
 
 def prompt_user(question: str) -> bool:
@@ -134,3 +405,97 @@ def toml_dumps_with_newline(data):
     formatted_lines.append("")  # newline at the end
 
     return "\n".join(formatted_lines)
+
+
+def remove_paths(paths: List[Path], dry_run: bool = False) -> None:
+    """
+    Removes the given files and directories, printing each of them.
+
+    Args:
+        paths (List[Path]): The paths to be removed.
+        dry_run (bool): If True, only prints the paths without removing them.
+    """
+    for path in paths:
+        if dry_run:
+            echo(f"Would remove '{path}'")
+            continue
+
+        echo(f"Removing '{path}' ...")
+        if path.is_dir() and not path.is_symlink():
+            shutil.rmtree(path)
+        else:
+            os.remove(path)
+
+
+def generate_totp(secret: str, for_time: Optional[float] = None, digits: int = 6, period: int = 30) -> str:
+    """
+    Generates a time-based one-time password (TOTP) as specified in RFC 6238, using HMAC-SHA1.
+
+    Args:
+        secret (str): The base32-encoded secret, as shown when setting up an authenticator app. Spaces and lower-case
+            letters are allowed.
+        for_time (float): The Unix time to generate the code for. Defaults to the current time.
+        digits (int): The number of digits of the code.
+        period (int): The number of seconds that each code is valid for.
+
+    Returns:
+        str: The code, padded with leading zeros.
+    """
+    key = secret.replace(' ', '').upper()
+    # Secrets are often shown without padding, which is required for decoding.
+    key = base64.b32decode(key + '=' * (-len(key) % 8))
+
+    counter = int((time.time() if for_time is None else for_time) // period)
+    digest = hmac.new(key, struct.pack('>Q', counter), hashlib.sha1).digest()
+
+    # Dynamic truncation (see RFC 4226, section 5.3).
+    offset = digest[-1] & 0x0F
+    code = struct.unpack('>I', digest[offset:offset + 4])[0] & 0x7FFFFFFF
+    return str(code % 10 ** digits).zfill(digits)
+
+
+# Maximum length of a sanitized file name (without extension), which leaves some room for the suffixes and extension
+# within the usual limit of 255 bytes.
+MAX_FILE_NAME_LENGTH: int = 200
+
+# Names reserved by Windows, regardless of the extension.
+WINDOWS_RESERVED_NAMES = {
+    'CON', 'PRN', 'AUX', 'NUL', *(f'COM{i}' for i in range(1, 10)), *(f'LPT{i}' for i in range(1, 10)),
+}
+
+
+def sanitize_file_name(name: str) -> str:
+    """
+    Sanitizes a name (e.g., a book title) to be used as a file name on all platforms and in Markdown links.
+
+    Path separators and pipes are replaced with hyphens, colons with " -", and brackets with parentheses. Other
+    characters that are forbidden on some platforms (or in Markdown links) are removed, as well as trailing dots.
+
+    Args:
+        name (str): The name to sanitize.
+
+    Returns:
+        str: The sanitized name, which is never empty.
+    """
+    name = re.sub(r'[\x00-\x1f]', '', name)
+    name = re.sub(r'[/\\|]', '-', name)
+    name = re.sub(r'\s*:', ' -', name)
+    name = name.replace('[', '(').replace(']', ')')
+    name = re.sub(r'[?*"<>#^]', '', name)
+    name = re.sub(r'\s+', ' ', name)
+
+    # Windows does not allow trailing dots and spaces.
+    name = name[:MAX_FILE_NAME_LENGTH].strip().rstrip('. ')
+    if not name:
+        return '_'
+    if name.split('.', 1)[0].upper() in WINDOWS_RESERVED_NAMES:
+        return f'{name}_'
+    return name
+
+
+def heading_anchor(heading: str) -> str:
+    """
+    Returns the anchor of a Markdown heading in the same way as most Markdown viewers (e.g., "My Heading" ->
+    "my-heading"), i.e., case-folded with spaces as dashes and without punctuation.
+    """
+    return re.sub(r'[^\w\- ]', '', heading.strip().casefold()).replace(' ', '-')
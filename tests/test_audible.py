@@ -0,0 +1,153 @@
+import io
+import tempfile
+import unittest
+from contextlib import redirect_stdout
+from datetime import datetime, timezone
+from pathlib import Path
+from unittest import mock
+
+from ncli.kit_amazon import Config as AmazonConfig, Annotation, Book, Chapter, count_markdown_annotations, \
+    export_to_markdown, isolate_bidi, parse_markdown_annotations, strip_bidi_isolates
+from ncli.kit_audible import Client, Config, Goal, GoalsState, check_content_version, correct_brand_intro, export, \
+    format_goal_progress, group_book_parts, write_goals_note, write_pdf_appendix
+
+
+class TestAudible(unittest.TestCase):
+    def test_group_book_parts(self):
+        books = [
+            Book(asin='1', title='The Stand, Part 2', author='A'),
+            Book(asin='2', title='Single', author='A'),
+            Book(asin='3', title='The Stand, Part 1', author='A'),
+            Book(asin='4', title='The Stand (Part 1 of 2)', author='B'),
+        ]
+
+        groups = group_book_parts(books, Config().part_title_patterns)
+
+        self.assertEqual(
+            [(title, [book.asin for book in parts]) for title, parts in groups],
+            [('The Stand', ['3', '1']), ('Single', ['2']), ('The Stand', ['4'])],
+        )
+
+    def test_config_validators(self):
+        self.assertEqual(Config(annotation_style='quote', file_name_collision='counter').annotation_style, 'quote')
+
+        # Test that the unknown values are rejected with the allowed ones (note that pydantic wraps the ConfigError)
+        with self.assertRaisesRegex(ValueError, 'expected one of default, quote'):
+            Config(annotation_style='fancy')
+        with self.assertRaisesRegex(ValueError, 'expected one of prompt, asin, author, counter'):
+            Config(file_name_collision='title')
+        with self.assertRaisesRegex(ValueError, 'expected one of month, year'):
+            Config.parse_obj({'goals': [{'books': 2, 'period': 'week'}]})
+
+    def test_check_content_version(self):
+        indexed = Book(asin='1', title='Book', content_version='ACR1/1')
+
+        self.assertIsNone(check_content_version(Book(asin='1', content_version='ACR1/1'), indexed))
+        self.assertIn('ACR1/1 -> ACR2/2', check_content_version(Book(asin='1', content_version='ACR2/2'), indexed))
+
+        # Test that books without a known version (e.g., indexed before it was tracked) are not reported
+        self.assertIsNone(check_content_version(Book(asin='1', content_version='ACR2/2'), None))
+        self.assertIsNone(check_content_version(Book(asin='1', content_version='ACR2/2'), Book(asin='1')))
+
+    def test_export_unchanged(self):
+        books = [Book(asin='1', title='Book', pdf_url='https://example.com/Book.pdf', last_opened_date=date)
+                 for date in ('2023-01-01', '2023-01-02')]
+        chapters = [Chapter(title='One', start_ms=0, end_ms=60000)]
+        annotations = [Annotation(note='A note', clip_start_ms=1000, clip_end_ms=2000)]
+
+        with tempfile.TemporaryDirectory() as tmp_dir, \
+                mock.patch('ncli.kit_audible.load_authenticator'), \
+                mock.patch('ncli.kit_audible.audible.Client'), \
+                mock.patch('ncli.kit_audible.Downloader') as downloader, \
+                mock.patch.object(Client, 'get_books', side_effect=[books[:1], books[1:]]), \
+                mock.patch.object(Client, 'get_chapters', return_value=(0, chapters)), \
+                mock.patch.object(Client, 'get_annotations', return_value=('md5', annotations)), \
+                redirect_stdout(io.StringIO()):
+            target = Path(tmp_dir)
+            target.joinpath('Book.pdf').write_bytes(b'%PDF')
+            self.assertEqual(export(AmazonConfig(), target, False, unattended=True).books[0].status, 'exported')
+            self.assertEqual(downloader.call_count, 1)
+
+            # Test that a book that has only been listened to gets its note rewritten with the new metadata, without
+            # downloading the PDF again
+            self.assertEqual(export(AmazonConfig(), target, False, unattended=True).books[0].status, 'unchanged')
+            self.assertEqual(downloader.call_count, 1)
+            self.assertIn('- Last opened date: 2023-01-02\n', target.joinpath('Book.md').read_text(encoding='utf-8'))
+
+    def test_correct_brand_intro(self):
+        annotations = [
+            Annotation(note='Intro', clip_start_ms=1000, clip_end_ms=3000),
+            Annotation(note='Later', clip_start_ms=60000, clip_end_ms=65000),
+        ]
+
+        corrected = correct_brand_intro(annotations, 2000)
+        self.assertEqual([(a.clip_start_ms, a.clip_end_ms) for a in corrected], [(0, 1000), (58000, 63000)])
+        self.assertEqual(correct_brand_intro(annotations, 0), annotations)
+
+    def test_write_pdf_appendix(self):
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            path.write_text('# Title\n\nClips\n', encoding='utf-8')
+
+            write_pdf_appendix(path, ['### Old.pdf\n'])
+            path.write_text(path.read_text(encoding='utf-8') + '\nMy own text\n', encoding='utf-8')
+            write_pdf_appendix(path, ['### New.pdf\n'])
+            content = path.read_text(encoding='utf-8')
+
+        # Test that the previous appendix is replaced, while the text around it is kept
+        self.assertEqual(content, '# Title\n\nClips\n\nMy own text\n\n<!-- ncli:begin pdf -->\n## Companion PDF\n\n'
+                                  '### New.pdf\n<!-- ncli:end pdf -->\n')
+
+        # Test that the appendix is not parsed as a part of the annotations
+        annotations = [Annotation(note='A note', clip_start_ms=1000, clip_end_ms=2000)]
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            export_to_markdown(path, Book(asin='1', title='Title'), annotations=annotations)
+            write_pdf_appendix(path, ['### Book.pdf\n\n> Quoted text\n\n---\n\nMore text\n'])
+
+            self.assertEqual(parse_markdown_annotations(path), annotations)
+            self.assertEqual(count_markdown_annotations(path), 1)
+
+    def test_isolate_bidi(self):
+        # Test that only text with right-to-left characters is isolated, line by line
+        self.assertEqual(isolate_bidi('Plain note'), 'Plain note')
+        self.assertEqual(isolate_bidi('שלום world\n\nok'), '\u2068שלום world\u2069\n\n\u2068ok\u2069')
+        self.assertEqual(strip_bidi_isolates(isolate_bidi('مرحبا\nhello')), 'مرحبا\nhello')
+
+    def test_goals(self):
+        now = datetime(2023, 5, 20, tzinfo=timezone.utc)
+        state = GoalsState()
+        state.update([
+            Book(asin='1', title='Finished', last_opened_date='Mon, 01 May 2023 10:00:00 +0000'),
+            Book(asin='2', title='In progress', last_opened_date='Mon, 01 May 2023 10:00:00 +0000'),
+            Book(asin='3', title='Never opened'),
+        ], ['1', '3'], now)
+
+        self.assertEqual({asin: book.finished_date for asin, book in state.finished.items()},
+                         {'1': '2023-05-01', '3': '2023-05-20'})
+
+        # Test that the finish date is not changed by later runs
+        state.update([Book(asin='1', title='Finished', last_opened_date='Thu, 01 Jun 2023 10:00:00 +0000')], ['1'], now)
+        self.assertEqual(state.finished['1'].finished_date, '2023-05-01')
+
+        self.assertEqual(format_goal_progress(1, 2), '[#####-----] 1/2')
+        self.assertEqual(format_goal_progress(3, 2), '[##########] 3/2')
+
+        with tempfile.TemporaryDirectory() as tmp:
+            path = Path(tmp).joinpath('Goals.md')
+            write_goals_note(path, [Goal(books=4)], state, now)
+            lines = path.read_text(encoding='utf-8').splitlines()
+
+        self.assertEqual(lines[:6], [
+            '# Listening goals',
+            '',
+            '## 4 book(s) per month',
+            '',
+            '- 2023-05: `[#####-----] 2/4`',
+            '  - Finished (2023-05-01)',
+        ])
+        self.assertEqual(lines[7], '- 2023-04: `[----------] 0/4`')
+
+
+if __name__ == '__main__':
+    unittest.main()
@@ -0,0 +1,147 @@
+import io
+import tempfile
+import unittest
+from contextlib import redirect_stdout
+from pathlib import Path
+
+from ncli.errors import ParseError
+from ncli.kit_amazon import ExportIndex
+from ncli.kit_import import EXPORT_INDEX_FILE_NAME, detect_layout, import_markdown, parse_bookcision, parse_clippings, \
+    parse_readwise
+
+READWISE_NOTE = """# The Psychology of Money
+
+![rw-book-cover](https://images.example.com/cover.jpg)
+
+## Metadata
+- Author: [[Morgan Housel]]
+- Full Title: The Psychology of Money: Timeless Lessons on Wealth
+- Category: #books
+
+## Highlights
+- Doing well with money has little to do with how smart you are. ([Location 97](https://readwise.io/to_kindle?action=open&asin=B084HJSJJ1&location=97))
+    - Note: The main idea
+- Spend less
+  than you earn. (Page 12)
+    - Tags: [[favorite]]
+"""
+
+BOOKCISION_TEXT = """The Psychology of Money
+Morgan Housel
+Citation (APA): Housel, M. (2020). The Psychology of Money [Kindle Android version].
+
+Highlight (yellow) - Page 5 · Location 97
+Doing well with money has little to do with how smart you are.
+
+Note - Page 5 · Location 97
+The main idea
+
+Highlight (blue) - Location 120
+Spend less
+than you earn.
+"""
+
+CLIPPINGS_TEXT = """\ufeffThe Psychology of Money: Timeless Lessons on Wealth (Morgan Housel)
+- Your Highlight on page 5 | Location 97-98 | Added on Monday, January 4, 2021 9:05:12 PM
+
+Doing well with money has little to do with how smart you are.
+==========
+The Psychology of Money: Timeless Lessons on Wealth (Morgan Housel)
+- Your Bookmark on page 6 | Location 110 | Added on Monday, January 4, 2021 9:06:00 PM
+
+
+==========
+The Psychology of Money: Timeless Lessons on Wealth (Morgan Housel)
+- Your Highlight on page 5 | Location 97-99 | Added on Monday, January 4, 2021 9:07:00 PM
+
+Doing well with money has little to do with how smart you are. It has a lot to do with how you behave.
+==========
+The Psychology of Money: Timeless Lessons on Wealth (Morgan Housel)
+- Your Note on page 5 | Location 99 | Added on Monday, January 4, 2021 9:08:00 PM
+
+The main idea
+==========
+Meditations (Marcus Aurelius)
+- Highlight Loc. 1203-05 | Added on Sunday, 10 May 2020 08:00:00
+
+Waste no more time arguing what a good man should be. Be one.
+==========
+"""
+
+
+class TestImport(unittest.TestCase):
+    def test_detect_layout(self):
+        self.assertEqual(detect_layout(READWISE_NOTE), 'readwise')
+        self.assertEqual(detect_layout(BOOKCISION_TEXT), 'bookcision')
+        self.assertIsNone(detect_layout('# Some note\n\nWith some text.\n'))
+
+    def test_import_markdown(self):
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            source, target = Path(tmp_dir).joinpath('source'), Path(tmp_dir).joinpath('target')
+            source.mkdir()
+            source.joinpath('a.md').write_text(READWISE_NOTE, encoding='utf-8')
+            source.joinpath('b.md').write_text('## Highlights\n- Without a title\n', encoding='utf-8')
+
+            # Test that the books imported before a failing file are still indexed
+            with redirect_stdout(io.StringIO()), self.assertRaises(ParseError):
+                import_markdown(source, target, layout='readwise')
+            export_index = ExportIndex.load_or_default(target.joinpath(EXPORT_INDEX_FILE_NAME))
+            self.assertEqual([item.info.asin for item in export_index.books], ['B084HJSJJ1'])
+
+    def test_parse_readwise(self):
+        book, annotations = parse_readwise(READWISE_NOTE)
+
+        self.assertEqual(book.title, 'The Psychology of Money')
+        self.assertEqual(book.subtitle, 'Timeless Lessons on Wealth')
+        self.assertEqual(book.author, 'Morgan Housel')
+        self.assertEqual(book.asin, 'B084HJSJJ1')
+        self.assertEqual(book.image_url, 'https://images.example.com/cover.jpg')
+
+        self.assertEqual(len(annotations), 2)
+        self.assertEqual(annotations[0].highlight, 'Doing well with money has little to do with how smart you are.')
+        self.assertEqual(annotations[0].location, 97)
+        self.assertEqual(annotations[0].note, 'The main idea')
+        self.assertEqual(annotations[1].highlight, 'Spend less\nthan you earn.')
+        self.assertEqual(annotations[1].page, 12)
+        self.assertIsNone(annotations[1].note)
+
+    def test_parse_bookcision(self):
+        book, annotations = parse_bookcision(BOOKCISION_TEXT)
+
+        self.assertEqual(book.title, 'The Psychology of Money')
+        self.assertEqual(book.author, 'Morgan Housel')
+
+        self.assertEqual(len(annotations), 2)
+        self.assertEqual(annotations[0].highlight, 'Doing well with money has little to do with how smart you are.')
+        self.assertEqual(annotations[0].highlight_color, 'Yellow')
+        self.assertEqual((annotations[0].page, annotations[0].location), (5, 97))
+        self.assertEqual(annotations[0].note, 'The main idea')
+        self.assertEqual(annotations[1].highlight, 'Spend less\nthan you earn.')
+        self.assertEqual(annotations[1].location, 120)
+
+    def test_parse_clippings(self):
+        books, bookmarks = parse_clippings(CLIPPINGS_TEXT)
+
+        self.assertEqual(bookmarks, 1)
+        self.assertEqual(len(books), 2)
+
+        book, annotations = books[0]
+        self.assertEqual(book.title, 'The Psychology of Money')
+        self.assertEqual(book.subtitle, 'Timeless Lessons on Wealth')
+        self.assertEqual(book.author, 'Morgan Housel')
+        # The edited highlight replaces the previous one, and the note is attached to it.
+        self.assertEqual(len(annotations), 1)
+        self.assertTrue(annotations[0].highlight.endswith('how you behave.'))
+        self.assertEqual((annotations[0].page, annotations[0].location), (5, 97))
+        self.assertEqual(annotations[0].note, 'The main idea')
+        self.assertTrue(annotations[0].created_at.startswith('Mon, 04 Jan 2021 21:07:00'))
+
+        book, annotations = books[1]
+        self.assertEqual((book.title, book.author), ('Meditations', 'Marcus Aurelius'))
+        self.assertEqual(annotations[0].location, 1203)
+        # Dates in other formats are kept as is.
+        self.assertEqual(annotations[0].created_at, 'Sunday, 10 May 2020 08:00:00')
+
+
+if __name__ == '__main__':
+    unittest.main()
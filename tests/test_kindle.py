@@ -0,0 +1,554 @@
+import csv
+import io
+import tempfile
+import unittest
+from contextlib import redirect_stderr, redirect_stdout
+from datetime import datetime, timezone
+from pathlib import Path
+from types import SimpleNamespace
+from urllib.parse import parse_qs, urlparse
+from unittest import mock
+
+from ncli.kit_amazon import Config as AmazonConfig, Annotation, AnnotationGroup, Book, DEFAULT_KINDLE_TEMPLATE_PATH, \
+    ExportIndex, ExportItem, FrontmatterConfig, build_otp_callback, count_markdown_annotations, export_to_anki, \
+    export_to_csv, export_to_markdown, format_dataview_fields, load_markdown_template, merge_into_markdown, \
+    merge_managed_sections, parse_markdown_annotations, parse_markdown_removed_annotations, RunSummary, \
+    write_markdown, write_markdown_template
+from ncli.errors import AuthError
+from ncli.kit_kindle import EXPORT_INDEX_FILE_NAME, AnnotationFetcher, BookFilter, Client, Pacing, apply_color_tags, \
+    count_highlight_words, dedupe_annotations, export, export_readwise_csv, export_single_file, \
+    find_removed_annotations, group_annotations_by_color, is_content_limit_reached, merge_edition_annotations, \
+    redact_highlights, sort_annotations, split_title, Selectors, TitleSplit, _parse_annotations_page
+from ncli.utils import parse_date
+
+FIXTURES_DIR = Path(__file__).parent.joinpath('fixtures')
+
+
+class TestKindle(unittest.TestCase):
+    def test_merge_edition_annotations(self):
+        old = [
+            Annotation(highlight='The quick brown fox jumps over the lazy dog.', location=100, page=10),
+            Annotation(highlight='Something only highlighted in the old edition.', location=150, page=15),
+            Annotation(highlight='Another shared highlight, slightly edited', location=200, page=20),
+        ]
+        new = [
+            Annotation(highlight='The quick brown fox jumps over the lazy dog', location=110, page=11),
+            Annotation(highlight='Another shared highlight, slightly edited.', location=230, page=23),
+        ]
+
+        merged = merge_edition_annotations(old, new)
+
+        # Shared highlights are not duplicated, and the old-only highlight is remapped between the anchors.
+        self.assertEqual([a.location for a in merged], [110, 170, 230])
+        self.assertEqual(merged[1].highlight, 'Something only highlighted in the old edition.')
+        self.assertIsNone(merged[1].page)
+
+    def test_merge_edition_annotations_without_anchors(self):
+        old = [Annotation(highlight='Old', location=50)]
+        new = [Annotation(highlight='New', location=10)]
+
+        merged = merge_edition_annotations(old, new)
+
+        self.assertEqual([a.location for a in merged], [10, 50])
+
+    def test_merge_edition_annotations_without_locations(self):
+        # Personal documents may only have the page
+        old = [Annotation(highlight='Shared text', page=3), Annotation(highlight='Only old', page=5),
+               Annotation(highlight='Located', location=40)]
+        new = [Annotation(highlight='Shared text', page=4), Annotation(highlight='Only new', location=20)]
+
+        merged = merge_edition_annotations(old, new)
+
+        self.assertEqual([(a.highlight, a.location, a.page) for a in merged], [
+            ('Only new', 20, None), ('Located', 40, None), ('Shared text', None, 4), ('Only old', None, 5),
+        ])
+
+    def test_split_title(self):
+        self.assertEqual(split_title('Dune: Messiah'), ('Dune', 'Messiah'))
+        self.assertEqual(split_title('Atomic Habits'), ('Atomic Habits', None))
+
+        # Test that colons without a space, within brackets, or with a short part are not separators
+        self.assertEqual(split_title('Re:Zero'), ('Re:Zero', None))
+        self.assertEqual(split_title('Saga (Volume 1: Origins)'), ('Saga (Volume 1: Origins)', None))
+        self.assertEqual(split_title('X: Y'), ('X: Y', None))
+
+        title_split = TitleSplit(keep_titles=['1984: the graphic novel'])
+        self.assertEqual(split_title('1984: The Graphic Novel', title_split), ('1984: The Graphic Novel', None))
+
+    def test_group_annotations_by_color(self):
+        annotations = [
+            Annotation(highlight='A', highlight_color='Yellow', location=10),
+            Annotation(note='Standalone note', location=15),
+            Annotation(highlight='B', highlight_color='Pink', location=20),
+            Annotation(highlight='C', highlight_color='Blue', location=30),
+            Annotation(highlight='D', highlight_color='Yellow', location=40),
+        ]
+
+        groups = group_annotations_by_color(annotations, {'blue': 'Key ideas'})
+
+        self.assertEqual(
+            [(group.title, [a.location for a in group.annotations]) for group in groups],
+            [('Blue: Key ideas', [30]), ('Yellow', [10, 40]), ('Pink', [20]), ('Notes', [15])],
+        )
+
+        # Test that the group headings are not counted as annotations
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            export_to_markdown(path, Book(asin='B000000001', title='Title'), annotations=annotations,
+                               annotation_groups=groups)
+            self.assertEqual(count_markdown_annotations(path), len(annotations))
+
+    def test_apply_color_tags(self):
+        annotations = [
+            Annotation(highlight='A', highlight_color='Blue', location=10),
+            Annotation(note='Standalone note', location=15),
+            Annotation(highlight='B', highlight_color='Yellow', location=20),
+        ]
+
+        tagged = apply_color_tags(annotations, {'blue': '#idea'})
+        self.assertEqual([a.tag for a in tagged], ['#idea', None, None])
+
+        # Test that the tag is kept when the exported note is parsed again
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            export_to_markdown(path, Book(asin='1', title='Title'), annotations=tagged)
+            self.assertEqual([a.tag for a in parse_markdown_annotations(path)], ['#idea', None, None])
+
+    def test_find_removed_annotations(self):
+        previous = [
+            Annotation(highlight='First', location=10),
+            Annotation(highlight='Second\nline', location=20),
+            Annotation(note='Standalone note', location=30),
+        ]
+        current = [Annotation(highlight='Second  line', location=20)]
+        removed = [
+            Annotation(highlight='Old', location=5, removed_at='2024-01-01'),
+            Annotation(highlight='Second line', location=20, removed_at='2024-01-01'),
+        ]
+
+        result = find_removed_annotations(previous, current, removed, '2024-02-01')
+
+        # Test that the highlights are matched regardless of whitespace, that standalone notes are not tracked,
+        # and that highlights which exist again are no longer considered removed
+        self.assertEqual([(a.highlight, a.removed_at) for a in result],
+                         [('Old', '2024-01-01'), ('First', '2024-02-01')])
+
+    def test_book_filter(self):
+        book = Book(asin='A1', title='The Stand', author='Stephen King', last_opened_date='Sunday January 1, 2023')
+
+        self.assertTrue(BookFilter().matches(book))
+        self.assertTrue(BookFilter(title='stand', author='KING', asins=['A1', 'A2']).matches(book))
+        self.assertTrue(BookFilter(since=datetime(2023, 1, 1)).matches(book))
+        self.assertFalse(BookFilter(title='It').matches(book))
+        self.assertFalse(BookFilter(asins=['A2']).matches(book))
+        self.assertFalse(BookFilter(since=datetime(2023, 1, 2)).matches(book))
+
+        # Test that books with an unknown last opened date do not match a date filter
+        self.assertFalse(BookFilter(since=datetime(2023, 1, 1)).matches(book.copy(update={'last_opened_date': ''})))
+
+    def test_redact_highlights(self):
+        annotations = [
+            Annotation(highlight='one two three', location=10),
+            Annotation(note='Standalone note', location=15),
+            Annotation(highlight='four five\nsix', note='Keep me', location=20),
+            Annotation(highlight='seven', location=30),
+        ]
+        self.assertEqual(count_highlight_words(annotations), 7)
+
+        redacted = redact_highlights(annotations, 4)
+        self.assertEqual([a.highlight for a in redacted], ['one two three', None, 'four […]', '[…]'])
+        self.assertEqual(redacted[2].note, 'Keep me')
+
+        # Test that the annotations are kept as is if they are within the limit
+        self.assertEqual(redact_highlights(annotations, 7), annotations)
+
+    def test_sort_and_dedupe_annotations(self):
+        annotations = [
+            Annotation(highlight='A', location=30, page=3),
+            Annotation(highlight='B', page=1),
+            Annotation(highlight='A', note='Edited', location=30, page=3),
+            Annotation(note='Standalone note', location=10, page=3),
+            Annotation(highlight='A', location=90, page=9),
+        ]
+
+        # Test that only the duplicates at the same position are dropped
+        deduped = dedupe_annotations(annotations)
+        self.assertEqual([(a.highlight, a.note) for a in deduped], [('A', 'Edited'), ('B', None),
+                                                                    (None, 'Standalone note'), ('A', None)])
+
+        self.assertEqual([a.location for a in sort_annotations(deduped, 'location')], [10, 30, 90, None])
+        self.assertEqual([a.page for a in sort_annotations(deduped, 'page')], [1, 3, 3, 9])
+        self.assertEqual(sort_annotations(deduped, 'none'), deduped)
+
+    def test_pacing_retry_interval(self):
+        pacing = Pacing(request_interval_s=1.0, backoff_factor=2.0, max_backoff_s=5.0)
+
+        # Test that the interval grows exponentially with each retry, up to the limit
+        self.assertEqual([pacing.get_retry_interval(attempt) for attempt in range(5)], [1.0, 2.0, 4.0, 5.0, 5.0])
+
+    def test_annotation_fetcher(self):
+        def get_annotations(_, book):
+            return [Annotation(highlight=f'{book.asin} highlight', location=int(book.asin))]
+
+        books = [Book(asin='10', title='New'), Book(asin='20', title='Other')]
+        old_editions = {'10': [Book(asin='5', title='Old')]}
+
+        # Test that the results are the same when fetching ahead with a pool of workers (each with its own client)
+        with mock.patch.object(Client, 'get_annotations', get_annotations):
+            for concurrency in [1, 2]:
+                with self.subTest(concurrency=concurrency):
+                    client = Client(SimpleNamespace(website_cookies={}))
+                    fetcher = AnnotationFetcher(client, old_editions, books, concurrency)
+                    try:
+                        annotations, duration_s = fetcher.get(books[0])
+                        self.assertEqual([a.highlight for a in annotations], ['5 highlight', '10 highlight'])
+                        self.assertGreaterEqual(duration_s, 0)
+                        self.assertEqual([a.location for a in fetcher.get(books[1])[0]], [20])
+                    finally:
+                        fetcher.close()
+
+    def test_build_otp_callback(self):
+        secret = 'GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ'
+        with redirect_stdout(io.StringIO()):
+            # Test that the command takes precedence over the secret
+            self.assertEqual(build_otp_callback(secret, 'echo " 123 456"')(), '123456')
+            self.assertEqual(len(build_otp_callback(secret)()), 6)
+            with self.assertRaises(AuthError):
+                build_otp_callback(secret, 'echo nope')()
+            with self.assertRaises(AuthError):
+                build_otp_callback(None, 'exit 1')()
+
+    def test_default_template(self):
+        book = Book(asin='B000000001', title='Title', subtitle='Subtitle', author='Author', last_opened_date='Today',
+                    cover_path='covers/B000000001.jpg', doc_type='personal')
+        annotations = [
+            Annotation(highlight='First\nline', highlight_color='Yellow', note='A note', location=10, page=2),
+            Annotation(note='Standalone note', location=15),
+            Annotation(highlight='Only a page', highlight_color='Blue', page=7, tag='#idea'),
+            Annotation(highlight='Cut sho', highlight_color='Pink', location=20, truncated=True),
+        ]
+        template = load_markdown_template(DEFAULT_KINDLE_TEMPLATE_PATH)
+
+        # Test that the default template produces the same layout as the built-in one
+        for kwargs in [
+            {'notebook_url': 'https://read.amazon.com/notebook?asin=B000000001'},
+            {'annotation_groups': [AnnotationGroup(title='Group', annotations=annotations)]},
+            {'frontmatter': FrontmatterConfig(enabled=True),
+             'removed_annotations': [Annotation(highlight='Gone', highlight_color='Blue', location=5,
+                                                removed_at='2023-05-01')]},
+            {'dataview_fields': True},
+        ]:
+            expected, actual = io.StringIO(), io.StringIO()
+            write_markdown(expected, book, annotations=annotations, **kwargs)
+            write_markdown_template(actual, template, book, annotations=annotations, **kwargs)
+            self.assertEqual(actual.getvalue(), expected.getvalue())
+
+    def test_parse_annotations_page(self):
+        content = FIXTURES_DIR.joinpath('kindle_notebook_annotations.html').read_bytes()
+        page_token, _, annotations = _parse_annotations_page(content, Selectors())
+
+        self.assertEqual(page_token, '')
+        self.assertEqual([(a.highlight, a.highlight_color, a.note, a.location, a.page) for a in annotations], [
+            ('The first highlight.', 'Yellow', 'A note on it.', 1024, 32),
+            ('The second highlight.', 'Blue', None, 2048, None),
+        ])
+
+        # Test that the creation time is read if the notebook shows it
+        self.assertEqual(parse_date(annotations[0].created_at), datetime(2023, 5, 1, 10, 15, tzinfo=timezone.utc))
+        self.assertIsNone(annotations[1].created_at)
+
+        # Test that the creation time is written in the CSV export as well
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.csv')
+            export_to_csv(path, Book(asin='B000000001', title='Title'), annotations=annotations)
+            with open(path, encoding='utf-8', newline='') as f:
+                rows = list(csv.DictReader(f))
+        self.assertEqual([row['created_at'] for row in rows], [annotations[0].created_at, ''])
+
+    def test_truncated_highlights(self):
+        # Test that only the limit reached field of the content limit state is checked
+        self.assertFalse(is_content_limit_reached(None))
+        self.assertFalse(is_content_limit_reached(''))
+        self.assertFalse(is_content_limit_reached('{"isLimitEnabled": true, "isLimitReached": false}'))
+        self.assertFalse(is_content_limit_reached('true'))
+        self.assertTrue(is_content_limit_reached('{"isLimitReached": true}'))
+
+        # Test that the highlights are only marked as truncated after the page that reports the limit
+        pages = {
+            None: FIXTURES_DIR.joinpath('kindle_notebook_annotations_limit.html').read_bytes(),
+            'next-page-token': FIXTURES_DIR.joinpath('kindle_notebook_annotations.html').read_bytes(),
+        }
+
+        def get(_, url):
+            token = parse_qs(urlparse(url).query).get('token', [None])[0]
+            return SimpleNamespace(content=pages[token])
+
+        with mock.patch.object(Client, '_get', get):
+            annotations = Client(SimpleNamespace(website_cookies={})).get_annotations(Book(asin='B1', title='Title'))
+        self.assertEqual([(a.location, a.truncated) for a in annotations], [(512, False), (1024, True), (2048, True)])
+
+        # Test that the truncated highlights are marked in the exported note
+        book = Book(asin='B000000001', title='Title')
+        annotations = [
+            Annotation(highlight='Complete', highlight_color='Yellow', location=10),
+            Annotation(highlight='Cut sho', highlight_color='Yellow', location=20, truncated=True),
+        ]
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            export_to_markdown(path, book, annotations=annotations)
+            self.assertEqual(path.read_text(encoding='utf-8').count('- Truncated: '), 1)
+            self.assertEqual(parse_markdown_annotations(path), annotations)
+
+    def test_dataview_fields(self):
+        book = Book(asin='B000000001', title='Title', cover_path='covers/B000000001.jpg')
+        annotation = Annotation(highlight='Text', highlight_color='Yellow', page=32, location=1024)
+
+        self.assertEqual(format_dataview_fields(book, annotation),
+                         '[color:: yellow] [page:: 32] [location:: 1024] [cover:: covers/B000000001.jpg]')
+
+        # Test that the fields do not affect parsing the exported note
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            export_to_markdown(path, book, annotations=[annotation], dataview_fields=True)
+            self.assertEqual(parse_markdown_annotations(path), [annotation])
+
+    def test_export_single_file(self):
+        index = ExportIndex(books=[
+            ExportItem(last_updated_time='', info=Book(asin='2', title='Second: Book'), file_name='Second - Book'),
+            ExportItem(last_updated_time='', info=Book(asin='1', title='First')),
+        ])
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            target = Path(tmp_dir)
+            for item in index.books:
+                export_to_markdown(target.joinpath(f'{item.file_name or item.info.title}.md'), item.info,
+                                   annotations=[Annotation(highlight='Text', highlight_color='Yellow', location=10)],
+                                   frontmatter=FrontmatterConfig(enabled=True))
+
+            path = export_single_file(target.joinpath('highlights.md'), target, index)
+            content = path.read_text(encoding='utf-8')
+
+        # Test that the books are linked from the table of contents, with their headings one level deeper
+        self.assertTrue(content.startswith('# Kindle highlights\n\n## Contents\n\n- [First](#first)\n'
+                                           '- [Second: Book](#second-book)\n\n## First\n'))
+        self.assertIn('\n### Annotations\n', content)
+        self.assertNotIn('asin:', content)
+
+    def test_export_to_anki(self):
+        annotations = [
+            Annotation(highlight='First\nline', note='Question?', location=10, tag='#idea'),
+            Annotation(highlight='Second', location=20),
+            Annotation(note='Only a note', location=30),
+        ]
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.tsv')
+            export_to_anki(path, Book(asin='1', title='Title'), annotations=annotations)
+            content = path.read_text(encoding='utf-8')
+
+        # Test that the highlights without a note are asked by their position, and the notes without a highlight are
+        # skipped
+        self.assertEqual(content, '#separator:tab\n#html:false\n#notetype:Basic\n#deck:Highlights::Title\n'
+                                  '#tags column:3\n'
+                                  'Question?\t"First\nline"\tidea\n'
+                                  'Title, location 20\tSecond\t\n')
+
+    def test_export_readwise_csv(self):
+        book = Book(asin='B000000001', title='Title', author='Author')
+        index = ExportIndex(books=[ExportItem(last_updated_time='', info=book)])
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            target = Path(tmp_dir)
+            export_to_markdown(target.joinpath('Title.md'), book, annotations=[
+                Annotation(highlight='Text', note='Note', location=10, created_at='Mon, 01 Jan 2024 10:00:00 +0000'),
+                Annotation(note='Only a note', location=20),
+            ])
+
+            path = export_readwise_csv(target.joinpath('readwise.csv'), target, index)
+            with open(path, encoding='utf-8', newline='') as f:
+                rows = list(csv.reader(f))
+
+        # Test that notes without a highlight are skipped
+        self.assertEqual(rows, [
+            ['Highlight', 'Title', 'Author', 'URL', 'Note', 'Location', 'Date'],
+            ['Text', 'Title', 'Author', 'https://read.amazon.com/notebook?asin=B000000001', 'Note', '10',
+             '2024-01-01 10:00:00'],
+        ])
+
+    def test_resolve_file_name(self):
+        index = ExportIndex(books=[
+            ExportItem(last_updated_time='', info=Book(asin='1', title='Title'), file_name='Title'),
+            ExportItem(last_updated_time='', info=Book(asin='2', title='Title (2)')),
+        ])
+
+        # Test that the title is sanitized, and collisions with the indexed books are resolved by the policy
+        self.assertEqual(index.resolve_file_name(Book(asin='3', title='Other: Sub?'), False), 'Other - Sub')
+        self.assertEqual(index.resolve_file_name(Book(asin='4', title='Title'), False, 'counter'), 'Title (3)')
+        self.assertEqual(index.resolve_file_name(Book(asin='4', title='Title'), False, 'asin'), 'Title (4)')
+        self.assertEqual(index.resolve_file_name(Book(asin='1', title='Title'), True, 'asin'), 'Title')
+
+    def test_run_summary_failed_asins(self):
+        summary = RunSummary()
+        summary.add(Book(asin='1', title='One'), 'exported', annotations=3)
+        summary.add(Book(asin='2', title='Two'), 'failed', error='timeout')
+        summary.add(Book(asin='3', title='Three'), 'skipped')
+
+        with tempfile.TemporaryDirectory() as tmp:
+            path = Path(tmp).joinpath('run-summary.json')
+            summary.save(path)
+
+            self.assertEqual(RunSummary.load(path).failed_asins(), ['2'])
+
+    def test_export_saves_progress(self):
+        books = [Book(asin='10', title='First'), Book(asin='20', title='Second')]
+
+        def get_annotations(_, book):
+            if book.asin == '20':
+                raise AuthError('session expired')
+            return [Annotation(highlight='A highlight', location=10)]
+
+        # Test that the books exported before an auth error are saved in the index (and the summary), while the
+        # book that failed is left out, so that only it is fetched again in the next run
+        with tempfile.TemporaryDirectory() as tmp_dir, \
+                mock.patch('ncli.kit_kindle.load_authenticator', return_value=SimpleNamespace(website_cookies={})), \
+                mock.patch.object(Client, 'get_books', return_value=books), \
+                mock.patch.object(Client, 'get_annotations', get_annotations), \
+                redirect_stdout(io.StringIO()):
+            target = Path(tmp_dir)
+            with self.assertRaises(AuthError):
+                export(AmazonConfig(), target, True)
+
+            export_index = ExportIndex.load_or_default(target.joinpath(EXPORT_INDEX_FILE_NAME))
+            self.assertEqual([item.info.asin for item in export_index.books], ['10'])
+            self.assertTrue(target.joinpath('First.md').is_file())
+            self.assertEqual(RunSummary.load(target.joinpath('run-summary.json')).failed_asins(), ['20'])
+
+    def test_export_reuses_legacy_file_names(self):
+        book = Book(asin='10', title='What If?')
+
+        # Test that the note of a book indexed before the file names were recorded (and sanitized) is still used
+        with tempfile.TemporaryDirectory() as tmp_dir, \
+                mock.patch('ncli.kit_kindle.load_authenticator', return_value=SimpleNamespace(website_cookies={})), \
+                mock.patch.object(Client, 'get_books', return_value=[book]), \
+                mock.patch.object(Client, 'get_annotations', return_value=[Annotation(highlight='New', location=10)]), \
+                redirect_stdout(io.StringIO()):
+            target = Path(tmp_dir)
+            ExportIndex(books=[ExportItem(last_updated_time='2023-01-01T00:00:00+00:00', info=book)]).save(
+                target.joinpath(EXPORT_INDEX_FILE_NAME))
+            target.joinpath('What If?.md').write_text('# What If?\n', encoding='utf-8')
+
+            export(AmazonConfig(), target, True)
+
+            export_index = ExportIndex.load_or_default(target.joinpath(EXPORT_INDEX_FILE_NAME))
+            self.assertEqual(export_index.get_item('10').file_name, 'What If?')
+            self.assertEqual([path.name for path in target.glob('*.md')], ['What If?.md'])
+            self.assertIn('New', target.joinpath('What If?.md').read_text(encoding='utf-8'))
+
+    def test_export_without_prompts(self):
+        books = [Book(asin='10', title='Title', author='A'), Book(asin='20', title='Title', author='B')]
+
+        # Test that the colliding file names get the ASIN (instead of prompting) when only fetching new books
+        with tempfile.TemporaryDirectory() as tmp_dir, \
+                mock.patch('ncli.kit_kindle.load_authenticator', return_value=SimpleNamespace(website_cookies={})), \
+                mock.patch('ncli.kit_amazon.prompt', side_effect=AssertionError('prompted')), \
+                mock.patch.object(Client, 'get_books', return_value=books), \
+                mock.patch.object(Client, 'get_annotations', return_value=[]), \
+                redirect_stdout(io.StringIO()):
+            target = Path(tmp_dir)
+            export(AmazonConfig(), target, False, new_only=True)
+
+            self.assertEqual(sorted(path.name for path in target.glob('*.md')), ['Title (10).md', 'Title (20).md'])
+
+    def test_get_product_metadata(self):
+        client = Client(SimpleNamespace(website_cookies={}, locale=SimpleNamespace(domain='com')))
+        book = Book(asin='B000000001', title='Title')
+        pages = {
+            'captcha': b'<form method="get" action="/errors/validateCaptcha"><input name="amzn"></form>',
+            'empty': b'<html><body><div id="dp">Unavailable</div></body></html>',
+        }
+
+        # Test that the captcha and empty pages are skipped (so that they are not cached)
+        for name, content in pages.items():
+            with self.subTest(name), mock.patch.object(Client, '_get', return_value=SimpleNamespace(content=content)), \
+                    redirect_stderr(io.StringIO()):
+                self.assertIsNone(client.get_product_metadata(book))
+
+    def test_get_pending_reason(self):
+        book = Book(asin='1', title='Title', last_opened_date='Today')
+        index = ExportIndex(books=[ExportItem(last_updated_time='', info=book)])
+
+        self.assertIsNone(index.get_pending_reason(book))
+        # Test that the same date in another format (e.g., from an older index) is not considered a modification
+        old_index = ExportIndex(books=[ExportItem(last_updated_time='', info=book.copy(
+            update={'last_opened_date': 'Sunday January 1, 2023'}))])
+        self.assertIsNone(old_index.get_pending_reason(book.copy(update={'last_opened_date': '2023-01-01'})))
+        self.assertEqual(index.get_pending_reason(book.copy(update={'last_opened_date': 'Tomorrow'})), 'modified')
+        self.assertEqual(index.get_pending_reason(Book(asin='2', title='Other')), 'new')
+
+    def test_merge_into_markdown(self):
+        book = Book(asin='B000000001', title='Title', author='Author')
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            export_to_markdown(path, book, annotations=[Annotation(highlight='First', highlight_color='Yellow',
+                                                                   location=10)],
+                               removed_annotations=[Annotation(highlight='Gone', highlight_color='Blue', location=5,
+                                                               removed_at='2023-05-01')])
+            with open(path, 'a', encoding='utf-8') as f:
+                f.write('My own remarks\n')
+
+            annotations = [
+                Annotation(highlight='First', highlight_color='Yellow', note='Added note', location=10),
+                Annotation(highlight='Second', highlight_color='Pink', location=20),
+            ]
+            self.assertEqual(merge_into_markdown(path, book, annotations), 1)
+            self.assertEqual(merge_into_markdown(path, book, annotations), 0)
+
+            # Test that the existing content is kept, and the new annotation is added before the removed ones
+            merged = [(annotation.note, annotation.location) for annotation in parse_markdown_annotations(path)]
+            self.assertEqual(merged, [(None, 10), (None, 20)])
+            self.assertTrue(path.read_text(encoding='utf-8').endswith('My own remarks\n'))
+
+            # Test that the annotations with only the page (e.g., of PDFs) are told apart by their highlight
+            path = Path(tmp_dir).joinpath('Document.md')
+            export_to_markdown(path, book, annotations=[Annotation(highlight='First', highlight_color='Yellow',
+                                                                   page=3)])
+            annotations = [
+                Annotation(highlight='First', highlight_color='Yellow', page=3),
+                Annotation(highlight='Second', highlight_color='Yellow', page=3),
+            ]
+            self.assertEqual(merge_into_markdown(path, book, annotations), 1)
+            self.assertEqual(merge_into_markdown(path, book, annotations), 0)
+            merged = [(annotation.highlight, annotation.page) for annotation in parse_markdown_annotations(path)]
+            self.assertEqual(merged, [('First', 3), ('Second', 3)])
+
+    def test_merge_managed_sections(self):
+        book = Book(asin='B000000001', title='Title', author='Author')
+        frontmatter = FrontmatterConfig(enabled=True)
+        first = io.StringIO()
+        write_markdown(first, book, annotations=[Annotation(highlight='First', highlight_color='Yellow', location=10)],
+                       frontmatter=frontmatter, managed_sections=True)
+        existing = first.getvalue() + '\n## My thoughts\n\nSome commentary\n'
+
+        generated = io.StringIO()
+        write_markdown(generated, book.copy(update={'last_opened_date': 'Today'}),
+                       annotations=[Annotation(highlight='Second', highlight_color='Blue', location=20)],
+                       frontmatter=frontmatter, managed_sections=True,
+                       removed_annotations=[Annotation(highlight='First', highlight_color='Yellow', location=10,
+                                                       removed_at='2023-05-01')])
+        merged = merge_managed_sections(existing, generated.getvalue())
+
+        # Test that the managed sections are regenerated (with the new one right after the preceding one), while the
+        # commentary is kept
+        self.assertIn('- Last opened date: Today\n', merged)
+        self.assertTrue(merged.startswith('---\n'))
+        self.assertLess(merged.index('## Removed highlights'), merged.index('Some commentary'))
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            path = Path(tmp_dir).joinpath('Title.md')
+            path.write_text(merged, encoding='utf-8')
+            self.assertEqual([annotation.highlight for annotation in parse_markdown_annotations(path)], ['Second'])
+            self.assertEqual([annotation.highlight for annotation in parse_markdown_removed_annotations(path)],
+                             ['First'])
+
+        # Test that files without managed sections are overwritten
+        self.assertEqual(merge_managed_sections('# Old\n', generated.getvalue()), generated.getvalue())
+
+
+if __name__ == '__main__':
+    unittest.main()
@@ -0,0 +1,57 @@
+import random
+import tempfile
+import unittest
+from pathlib import Path
+
+from ncli.kit_amazon import Annotation, Book, ExportIndex, ExportItem, export_to_markdown
+from ncli.kit_dashboard import EXPORT_INDEX_FILE_NAME, Source
+from ncli.kit_quote import Quote, format_quote, load_quotes, select_quote
+
+
+class TestQuote(unittest.TestCase):
+    def setUp(self):
+        book = Book(asin='B000000001', title='Title', author='Author')
+        self.quotes = [
+            Quote(id='B000000001-1', book=book,
+                  annotation=Annotation(highlight='First line\nSecond line', location=12)),
+            Quote(id='B000000001-2', book=book, annotation=Annotation(highlight='Other', note='About <tags>',
+                                                                      clip_start_ms=61000)),
+        ]
+
+    def test_load_quotes(self):
+        book = Book(asin='B000000001', title='Title', author='Author')
+        annotations = [
+            Annotation(note='Standalone note', location=5),
+            Annotation(highlight='First', highlight_color='Yellow', location=10),
+            Annotation(highlight='Second', highlight_color='Blue', note='A note', location=20),
+        ]
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            export_dir = Path(tmp_dir)
+            ExportIndex(books=[ExportItem(last_updated_time='', info=book)]).save(
+                export_dir.joinpath(EXPORT_INDEX_FILE_NAME))
+            export_to_markdown(export_dir.joinpath('Title.md'), book, annotations=annotations)
+
+            quotes = load_quotes([Source('Kindle', export_dir)])
+
+        # Test that the quotes are numbered by the highlights only, i.e., the note-only annotations are not counted
+        self.assertEqual([(quote.id, quote.annotation.highlight) for quote in quotes],
+                         [('B000000001-1', 'First'), ('B000000001-2', 'Second')])
+
+    def test_select_quote(self):
+        self.assertEqual(select_quote(self.quotes, quote_id='B000000001-2').id, 'B000000001-2')
+        self.assertEqual(select_quote(self.quotes, search='TAGS', rng=random.Random(0)).id, 'B000000001-2')
+        with self.assertRaises(ValueError):
+            select_quote(self.quotes, search='missing')
+
+    def test_format_quote(self):
+        self.assertEqual(format_quote(self.quotes[0], 'plain'),
+                         '"First line\nSecond line"\n— Author, Title (Location 12)\n')
+        self.assertEqual(format_quote(self.quotes[1], 'markdown'), '> Other\n>\n> — Author, Title (0:01:01)\n')
+
+        svg = format_quote(self.quotes[0], 'svg')
+        self.assertIn('<tspan x="64" dy="32">Second line</tspan>', svg)
+        self.assertIn('— Author, Title (Location 12)</text>', svg)
+
+
+if __name__ == '__main__':
+    unittest.main()
@@ -1,6 +1,15 @@
 import unittest
 
-from ncli.utils import format_duration
+import base64
+import shutil
+import tempfile
+from datetime import date, datetime, timezone
+from pathlib import Path
+
+from ncli.errors import ConfigError, IoError
+from ncli.utils import configure_dates, configure_durations, current_timestamp, ensure_free_space, extract_date, \
+    format_duration, format_duration_from_ms, format_simple_date, format_stored_date, format_timestamp, generate_totp, \
+    heading_anchor, parse_date, parse_duration_to_ms, same_date, sanitize_file_name
 
 
 class TestUtils(unittest.TestCase):
@@ -15,6 +24,93 @@ class TestUtils(unittest.TestCase):
         self.assertEqual(format_duration(3661.0), '1:01:01')
         self.assertEqual(format_duration(4500.0), '1:15:00')
 
+    def test_format_duration_from_ms(self):
+        value = 3723456  # 1:02:03.456
+
+        self.assertEqual(format_duration_from_ms(value), '1:02:03')
+        self.assertEqual(format_duration_from_ms(value, 'ms'), '1:02:03.456')
+        self.assertEqual(format_duration_from_ms(value, 'frames'), '1:02:03:13')
+
+        # Test that the formatted durations are parsed back up to their precision
+        self.assertEqual(parse_duration_to_ms('1:02:03'), 3723000)
+        self.assertEqual(parse_duration_to_ms('1:02:03.456'), value)
+        self.assertEqual(parse_duration_to_ms('1:02:03:13'), 3723433)
+
+        try:
+            configure_durations('ms')
+            self.assertEqual(format_duration_from_ms(value), '1:02:03.456')
+            with self.assertRaises(ConfigError):
+                configure_durations('minutes')
+        finally:
+            configure_durations()
+
+    def test_generate_totp(self):
+        # Test vectors from RFC 6238 (SHA1), truncated to the last 6 digits.
+        secret = base64.b32encode(b'12345678901234567890').decode()
+        self.assertEqual(generate_totp(secret, 59), '287082')
+        self.assertEqual(generate_totp(secret, 1111111109), '081804')
+        self.assertEqual(generate_totp(secret, 1234567890), '005924')
+        self.assertEqual(generate_totp(secret, 20000000000, digits=8), '65353130')
+
+        # Test that the secret can be written in groups, in lower-case, and without padding
+        self.assertEqual(generate_totp(secret.lower()[:8] + ' ' + secret.lower()[8:].rstrip('='), 59), '287082')
+
+    def test_sanitize_file_name(self):
+        self.assertEqual(sanitize_file_name('AC/DC: The Story'), 'AC-DC - The Story')
+        self.assertEqual(sanitize_file_name('What If? [Illustrated] | Vol. 2...'), 'What If (Illustrated) - Vol. 2')
+        self.assertEqual(sanitize_file_name('C# in  Depth '), 'C in Depth')
+
+        # Test that reserved or empty names are still valid file names
+        self.assertEqual(sanitize_file_name('con'), 'con_')
+        self.assertEqual(sanitize_file_name('???'), '_')
+
+    def test_heading_anchor(self):
+        self.assertEqual(heading_anchor(' My Heading '), 'my-heading')
+        self.assertEqual(heading_anchor('What If? (Vol. 2)'), 'what-if-vol-2')
+
+    def test_same_date(self):
+        # Test that the same date in different formats (e.g., from an older index) is considered the same
+        self.assertTrue(same_date('Sunday January 1, 2023', '2023-01-01'))
+        self.assertTrue(same_date('Wed, 01 Jan 2023 00:00:01 +0800', '2023-01-01T00:00:01+08:00'))
+        self.assertFalse(same_date('2023-01-01', '2023-01-02'))
+        self.assertTrue(same_date('Unknown', 'Unknown'))
+        self.assertEqual(parse_date('2023-01-01T00:00:01+08:00').utcoffset().total_seconds(), 8 * 3600)
+
+    def test_configure_dates(self):
+        self.addCleanup(configure_dates)
+        configure_dates('Asia/Singapore', '%d/%m/%Y')
+
+        # Test that the datetimes are converted to the timezone, while the dates are formatted as is
+        self.assertEqual(format_simple_date(datetime(2023, 1, 1, 20, tzinfo=timezone.utc)), '02/01/2023')
+        self.assertEqual(format_simple_date(date(2023, 1, 1)), '01/01/2023')
+        self.assertEqual(format_timestamp(datetime(2023, 1, 1, 20, tzinfo=timezone.utc)),
+                         'Mon, 02 Jan 2023 04:00:00 +0800')
+        self.assertTrue(current_timestamp().endswith('+08:00'))
+
+        # Test that the dates in the configured format can be read back, together with the existing formats
+        self.assertTrue(same_date('01/02/2023', '2023-02-01'))
+
+        # Test that the stored dates are kept in ISO format, and only formatted for the notes
+        self.assertEqual(extract_date('2023-02-01T10:00:00.000Z'), '2023-02-01')
+        self.assertEqual(format_stored_date('2023-02-01'), '01/02/2023')
+        self.assertEqual(format_stored_date('Wed, 01 Feb 2023 00:00:01 +0800'), 'Wed, 01 Feb 2023 00:00:01 +0800')
+        self.assertIsNone(format_stored_date(None))
+
+        with self.assertRaises(ConfigError):
+            configure_dates('Nowhere/Unknown')
+
+    def test_ensure_free_space(self):
+        with tempfile.TemporaryDirectory() as tmp_dir:
+            # Test that paths that do not exist yet are checked using their closest existing parent
+            ensure_free_space({Path(tmp_dir).joinpath('not', 'created'): 1})
+
+            # Test that the requirements on the same file system are added up, i.e., each of them fits in the free
+            # space, but not both of them
+            half = shutil.disk_usage(tmp_dir).free // 2 + 1
+            ensure_free_space({Path(tmp_dir): half})
+            with self.assertRaises(IoError):
+                ensure_free_space({Path(tmp_dir): half, Path(tmp_dir).joinpath('other'): half})
+
 
 if __name__ == '__main__':
     unittest.main()
@@ -0,0 +1,31 @@
+import tempfile
+import unittest
+from pathlib import Path
+
+from ncli.kit_validate import validate
+
+
+class TestValidate(unittest.TestCase):
+    def test_validate(self):
+        with tempfile.TemporaryDirectory() as tmp:
+            target = Path(tmp)
+            target.joinpath('A.md').write_text('# A\n\nSee [B](B.md) and [C](C%20Note.md#section).\n',
+                                               encoding='utf-8')
+            target.joinpath('B.md').write_text('---\nid: 1\n---\n# B\n[web](https://example.com)\n',
+                                               encoding='utf-8')
+            target.joinpath('Bad: name.md').write_text('---\nid: 1\ntags: [a\n---\n', encoding='utf-8')
+            target.joinpath('D.md').write_text('---\nid: 1\n---\n', encoding='utf-8')
+
+            issues = [str(issue).replace(f'{tmp}/', '') for issue in validate(target)]
+
+        self.assertEqual(issues, [
+            "A.md:3: broken link 'C%20Note.md#section'",
+            'B.md:2: duplicate id 1',
+            'Bad: name.md: name contains forbidden character(s): :',
+            'Bad: name.md:3: malformed frontmatter: expected \',\' or \']\', but got \'<stream end>\'',
+            'D.md:2: duplicate id 1',
+        ])
+
+
+if __name__ == '__main__':
+    unittest.main()